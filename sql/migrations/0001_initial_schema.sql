@@ -0,0 +1,236 @@
+-- Initial schema. Tables are created in the schema of the connection search_path (config: pg_schema).
+-- Databases created from the former sql/database_structure.sql are upgraded by the ALTER statements at the end.
+
+-- Table: api_keys
+-- new_node_policy: what a checkin of an unknown node_id does. 'reject', 'pending' (registered, not monitored until approved)
+-- or 'auto_enable' (monitored, email list / group / triggers copied from template_node_id or a node of template_node_group).
+
+CREATE TABLE IF NOT EXISTS api_keys
+(
+    id integer NOT NULL,
+    api_key character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    new_node_policy character varying(20) COLLATE pg_catalog."default" NOT NULL DEFAULT 'pending',
+    template_node_id integer,
+    template_node_group character varying(100) COLLATE pg_catalog."default",
+    CONSTRAINT api_keys_pkey PRIMARY KEY (id),
+    CONSTRAINT "apy key is unique" UNIQUE (api_key)
+);
+
+
+-- Table: nodes
+
+CREATE TABLE IF NOT EXISTS nodes
+(
+    id serial,
+    node_id_external character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    fk_api_key_id integer NOT NULL,
+    monitoring_enabled boolean NOT NULL DEFAULT 'false',
+    last_checkin_timestamp timestamp with time zone NOT NULL,
+    notification_email_list character varying(255) COLLATE pg_catalog."default",
+    node_group character varying(100) COLLATE pg_catalog."default",
+    clock_offset_seconds double precision,
+    clock_offset_measured_at timestamp with time zone,
+    approval_status character varying(20) COLLATE pg_catalog."default" NOT NULL DEFAULT 'approved',
+    CONSTRAINT nodes_pkey PRIMARY KEY (id)
+);
+
+
+-- Table: sensor_triggers
+
+CREATE TABLE IF NOT EXISTS sensor_triggers
+(
+    sensor_triggers_id serial,
+    node_id integer NOT NULL,
+    sensor_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    monitoring_enabled boolean NOT NULL,
+    validation_function character varying(3) COLLATE pg_catalog."default" NOT NULL,
+    validation_parameter_1 double precision,
+    validation_parameter_2 double precision,
+    validation_parameter_text character varying(255) COLLATE pg_catalog."default",
+    validation_expression text COLLATE pg_catalog."default",
+    deadband double precision NOT NULL DEFAULT 0.05,
+    deadband_type character varying(10) COLLATE pg_catalog."default" NOT NULL DEFAULT 'absolute',
+    fail_after_count integer NOT NULL DEFAULT 1,
+    fail_after_seconds integer NOT NULL DEFAULT 0,
+    recover_after_count integer NOT NULL DEFAULT 1,
+    consecutive_fail_count integer NOT NULL DEFAULT 0,
+    consecutive_ok_count integer NOT NULL DEFAULT 0,
+    fail_streak_started_at timestamp with time zone,
+    missing_grace_seconds integer NOT NULL DEFAULT 0,
+    missing_since timestamp with time zone,
+    CONSTRAINT sensor_triggers_pkey PRIMARY KEY (sensor_triggers_id)
+);
+
+
+-- Table: incidents
+-- incident_type: 'node_offline', 'sensor_trigger', 'sensor_missing', 'disk_full', 'cpu_throttled', 'node_rebooted' or 'clock_skew'. An incident is open while resolved_at is NULL.
+
+CREATE TABLE IF NOT EXISTS incidents
+(
+    id serial,
+    incident_type character varying(20) COLLATE pg_catalog."default" NOT NULL,
+    node_id integer NOT NULL,
+    sensor_triggers_id integer,
+    opened_at timestamp with time zone NOT NULL,
+    acknowledged_at timestamp with time zone,
+    acknowledged_by character varying(255) COLLATE pg_catalog."default",
+    resolved_at timestamp with time zone,
+    last_value double precision,
+    CONSTRAINT incidents_pkey PRIMARY KEY (id)
+);
+
+
+-- Table: node_state_transitions
+-- state: 'online' or 'offline'. Used for uptime / SLA reports.
+
+CREATE TABLE IF NOT EXISTS node_state_transitions
+(
+    id serial,
+    node_id integer NOT NULL,
+    state character varying(10) COLLATE pg_catalog."default" NOT NULL,
+    transition_at timestamp with time zone NOT NULL,
+    CONSTRAINT node_state_transitions_pkey PRIMARY KEY (id)
+);
+
+
+-- Table: uptime_reports_sent
+
+CREATE TABLE IF NOT EXISTS uptime_reports_sent
+(
+    report_month timestamp with time zone NOT NULL,
+    sent_at timestamp with time zone NOT NULL,
+    CONSTRAINT uptime_reports_sent_pkey PRIMARY KEY (report_month)
+);
+
+
+-- Table: sensor_readings
+-- History of sensor values received in checkins. Used by rate-of-change and delta triggers.
+-- value_type: 'number', 'boolean' or 'string'. value holds numbers and booleans (1 / 0), value_text booleans and strings.
+
+CREATE TABLE IF NOT EXISTS sensor_readings
+(
+    id bigserial,
+    node_id integer NOT NULL,
+    sensor_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    sensor_name character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    value_type character varying(10) COLLATE pg_catalog."default" NOT NULL DEFAULT 'number',
+    value double precision,
+    value_text character varying(255) COLLATE pg_catalog."default",
+    unit character varying(20) COLLATE pg_catalog."default",
+    reading_timestamp timestamp with time zone NOT NULL,
+    CONSTRAINT sensor_readings_pkey PRIMARY KEY (id)
+);
+
+
+CREATE INDEX IF NOT EXISTS sensor_readings_node_sensor_timestamp
+    ON sensor_readings USING btree (node_id, sensor_id, reading_timestamp);
+
+
+-- Table: sensors
+-- Sensors reported by each node. Registered automatically on checkin, label is set by operators.
+
+CREATE TABLE IF NOT EXISTS sensors
+(
+    id serial,
+    node_id integer NOT NULL,
+    sensor_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    sensor_name character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    first_seen timestamp with time zone NOT NULL,
+    last_seen timestamp with time zone NOT NULL,
+    value_type character varying(10) COLLATE pg_catalog."default" NOT NULL DEFAULT 'number',
+    last_value double precision,
+    last_value_text character varying(255) COLLATE pg_catalog."default",
+    unit character varying(20) COLLATE pg_catalog."default",
+    label character varying(255) COLLATE pg_catalog."default",
+    CONSTRAINT sensors_pkey PRIMARY KEY (id),
+    CONSTRAINT "sensor id is unique per node" UNIQUE (node_id, sensor_id)
+);
+
+
+-- Table: node_system_health
+-- Latest system health block reported by each node.
+
+CREATE TABLE IF NOT EXISTS node_system_health
+(
+    node_id integer NOT NULL,
+    reported_at timestamp with time zone NOT NULL,
+    uptime_seconds bigint,
+    cpu_temperature double precision,
+    cpu_throttled boolean,
+    load_1m double precision,
+    load_5m double precision,
+    load_15m double precision,
+    disk_used_percent double precision,
+    memory_used_percent double precision,
+    ip_addresses text[] COLLATE pg_catalog."default",
+    os character varying(255) COLLATE pg_catalog."default",
+    agent_version character varying(100) COLLATE pg_catalog."default",
+    CONSTRAINT node_system_health_pkey PRIMARY KEY (node_id)
+);
+
+
+-- Upgrade of existing databases: notification state is now kept in incidents.
+-- A sent notification becomes an open incident, otherwise the first sweep after the upgrade notifies again.
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'nodes' AND column_name = 'offline_notification_sent') THEN
+        INSERT INTO incidents(incident_type, node_id, sensor_triggers_id, opened_at)
+        SELECT 'node_offline', nodes.id, NULL, nodes.last_checkin_timestamp FROM nodes
+        WHERE nodes.offline_notification_sent = true
+        AND NOT EXISTS (SELECT 1 FROM incidents WHERE incidents.node_id = nodes.id AND incidents.incident_type = 'node_offline' AND incidents.resolved_at IS NULL);
+    END IF;
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+        WHERE table_schema = current_schema() AND table_name = 'sensor_triggers' AND column_name = 'trigger_notification_sent') THEN
+        INSERT INTO incidents(incident_type, node_id, sensor_triggers_id, opened_at)
+        SELECT 'sensor_trigger', sensor_triggers.node_id, sensor_triggers.sensor_triggers_id, now() FROM sensor_triggers
+        WHERE sensor_triggers.trigger_notification_sent = true
+        AND NOT EXISTS (SELECT 1 FROM incidents WHERE incidents.sensor_triggers_id = sensor_triggers.sensor_triggers_id
+            AND incidents.incident_type = 'sensor_trigger' AND incidents.resolved_at IS NULL);
+    END IF;
+END $$;
+
+ALTER TABLE IF EXISTS nodes DROP COLUMN IF EXISTS offline_notification_sent;
+ALTER TABLE IF EXISTS sensor_triggers DROP COLUMN IF EXISTS trigger_notification_sent;
+ALTER TABLE IF EXISTS nodes ADD COLUMN IF NOT EXISTS node_group character varying(100) COLLATE pg_catalog."default";
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS deadband double precision NOT NULL DEFAULT 0.05;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS deadband_type character varying(10) COLLATE pg_catalog."default" NOT NULL DEFAULT 'absolute';
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS fail_after_count integer NOT NULL DEFAULT 1;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS fail_after_seconds integer NOT NULL DEFAULT 0;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS recover_after_count integer NOT NULL DEFAULT 1;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS consecutive_fail_count integer NOT NULL DEFAULT 0;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS consecutive_ok_count integer NOT NULL DEFAULT 0;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS fail_streak_started_at timestamp with time zone;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS validation_expression text COLLATE pg_catalog."default";
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS missing_grace_seconds integer NOT NULL DEFAULT 0;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS missing_since timestamp with time zone;
+
+-- Upgrade of existing databases: typed sensor values (number / boolean / string) with double precision numbers
+
+ALTER TABLE IF EXISTS sensor_triggers ALTER COLUMN validation_parameter_1 TYPE double precision;
+ALTER TABLE IF EXISTS sensor_triggers ALTER COLUMN validation_parameter_2 TYPE double precision;
+ALTER TABLE IF EXISTS sensor_triggers ALTER COLUMN deadband TYPE double precision;
+ALTER TABLE IF EXISTS sensor_triggers ADD COLUMN IF NOT EXISTS validation_parameter_text character varying(255) COLLATE pg_catalog."default";
+ALTER TABLE IF EXISTS incidents ALTER COLUMN last_value TYPE double precision;
+ALTER TABLE IF EXISTS sensor_readings ALTER COLUMN value TYPE double precision;
+ALTER TABLE IF EXISTS sensor_readings ALTER COLUMN value DROP NOT NULL;
+ALTER TABLE IF EXISTS sensor_readings ADD COLUMN IF NOT EXISTS value_type character varying(10) COLLATE pg_catalog."default" NOT NULL DEFAULT 'number';
+ALTER TABLE IF EXISTS sensor_readings ADD COLUMN IF NOT EXISTS value_text character varying(255) COLLATE pg_catalog."default";
+ALTER TABLE IF EXISTS sensor_readings ADD COLUMN IF NOT EXISTS unit character varying(20) COLLATE pg_catalog."default";
+ALTER TABLE IF EXISTS sensors ALTER COLUMN last_value TYPE double precision;
+ALTER TABLE IF EXISTS sensors ALTER COLUMN last_value DROP NOT NULL;
+ALTER TABLE IF EXISTS sensors ADD COLUMN IF NOT EXISTS value_type character varying(10) COLLATE pg_catalog."default" NOT NULL DEFAULT 'number';
+ALTER TABLE IF EXISTS sensors ADD COLUMN IF NOT EXISTS last_value_text character varying(255) COLLATE pg_catalog."default";
+
+-- Upgrade of existing databases: device clock offset (device time - server time) of the last checkin with device timestamp
+
+ALTER TABLE IF EXISTS nodes ADD COLUMN IF NOT EXISTS clock_offset_seconds double precision;
+ALTER TABLE IF EXISTS nodes ADD COLUMN IF NOT EXISTS clock_offset_measured_at timestamp with time zone;
+
+-- Upgrade of existing databases: registration policy of unknown nodes. Existing nodes stay approved
+
+ALTER TABLE IF EXISTS api_keys ADD COLUMN IF NOT EXISTS new_node_policy character varying(20) COLLATE pg_catalog."default" NOT NULL DEFAULT 'pending';
+ALTER TABLE IF EXISTS api_keys ADD COLUMN IF NOT EXISTS template_node_id integer;
+ALTER TABLE IF EXISTS api_keys ADD COLUMN IF NOT EXISTS template_node_group character varying(100) COLLATE pg_catalog."default";
+ALTER TABLE IF EXISTS nodes ADD COLUMN IF NOT EXISTS approval_status character varying(20) COLLATE pg_catalog."default" NOT NULL DEFAULT 'approved';
@@ -0,0 +1,82 @@
+-- Foreign keys, indexes and the unique node key.
+-- Duplicate nodes (same api key and node_id_external) created by concurrent first checkins are merged into the
+-- oldest row. Rows pointing to nodes / triggers that do not exist anymore are removed before the foreign keys are added.
+-- Every merged / removed row is reported: the rows of the final SELECT are logged by the migration runner.
+CREATE TEMPORARY TABLE migration_report (message text NOT NULL) ON COMMIT DROP;
+
+UPDATE sensor_triggers t SET node_id = d.keep_id
+    FROM (SELECT id, min(id) OVER (PARTITION BY fk_api_key_id, node_id_external) AS keep_id FROM nodes) d
+    WHERE t.node_id = d.id AND d.id <> d.keep_id;
+UPDATE incidents i SET node_id = d.keep_id
+    FROM (SELECT id, min(id) OVER (PARTITION BY fk_api_key_id, node_id_external) AS keep_id FROM nodes) d
+    WHERE i.node_id = d.id AND d.id <> d.keep_id;
+UPDATE node_state_transitions s SET node_id = d.keep_id
+    FROM (SELECT id, min(id) OVER (PARTITION BY fk_api_key_id, node_id_external) AS keep_id FROM nodes) d
+    WHERE s.node_id = d.id AND d.id <> d.keep_id;
+UPDATE sensor_readings r SET node_id = d.keep_id
+    FROM (SELECT id, min(id) OVER (PARTITION BY fk_api_key_id, node_id_external) AS keep_id FROM nodes) d
+    WHERE r.node_id = d.id AND d.id <> d.keep_id;
+-- the registry and the system snapshot are rebuilt by the next checkin
+WITH removed AS (
+    DELETE FROM nodes n USING (SELECT id, min(id) OVER (PARTITION BY fk_api_key_id, node_id_external) AS keep_id FROM nodes) d
+    WHERE n.id = d.id AND d.id <> d.keep_id
+    RETURNING n.id, n.fk_api_key_id, n.node_id_external, d.keep_id
+)
+INSERT INTO migration_report
+    SELECT format('merged duplicate node %s of api key %s: nodes.id %s into %s', node_id_external, fk_api_key_id, id, keep_id) FROM removed;
+
+WITH removed AS (DELETE FROM nodes WHERE fk_api_key_id NOT IN (SELECT id FROM api_keys) RETURNING id, fk_api_key_id, node_id_external)
+INSERT INTO migration_report
+    SELECT format('removed node %s of missing api key %s: nodes.id %s', node_id_external, fk_api_key_id, id) FROM removed;
+WITH removed AS (DELETE FROM sensor_triggers WHERE node_id NOT IN (SELECT id FROM nodes) RETURNING sensor_triggers_id, node_id)
+INSERT INTO migration_report
+    SELECT format('removed sensor trigger %s of missing node %s', sensor_triggers_id, node_id) FROM removed;
+WITH removed AS (
+    DELETE FROM incidents WHERE node_id NOT IN (SELECT id FROM nodes)
+    OR (sensor_triggers_id IS NOT NULL AND sensor_triggers_id NOT IN (SELECT sensor_triggers_id FROM sensor_triggers))
+    RETURNING id, incident_type, node_id
+)
+INSERT INTO migration_report
+    SELECT format('removed incident %s (%s) of missing node / sensor trigger, node %s', id, incident_type, node_id) FROM removed;
+WITH removed AS (DELETE FROM node_state_transitions WHERE node_id NOT IN (SELECT id FROM nodes) RETURNING node_id)
+INSERT INTO migration_report
+    SELECT format('removed %s node state transitions of missing nodes', count(*)) FROM removed HAVING count(*) > 0;
+WITH removed AS (DELETE FROM sensor_readings WHERE node_id NOT IN (SELECT id FROM nodes) RETURNING node_id)
+INSERT INTO migration_report
+    SELECT format('removed %s sensor readings of missing nodes', count(*)) FROM removed HAVING count(*) > 0;
+WITH removed AS (DELETE FROM sensors WHERE node_id NOT IN (SELECT id FROM nodes) RETURNING node_id)
+INSERT INTO migration_report
+    SELECT format('removed %s registry sensors of missing nodes', count(*)) FROM removed HAVING count(*) > 0;
+WITH removed AS (DELETE FROM node_system_health WHERE node_id NOT IN (SELECT id FROM nodes) RETURNING node_id)
+INSERT INTO migration_report
+    SELECT format('removed %s system health snapshots of missing nodes', count(*)) FROM removed HAVING count(*) > 0;
+WITH cleared AS (UPDATE api_keys SET template_node_id = NULL WHERE template_node_id NOT IN (SELECT id FROM nodes) RETURNING id)
+INSERT INTO migration_report
+    SELECT format('cleared the missing template node of api key %s', id) FROM cleared;
+
+ALTER TABLE nodes ADD CONSTRAINT "node id is unique per api key" UNIQUE (fk_api_key_id, node_id_external);
+
+ALTER TABLE nodes ADD CONSTRAINT nodes_fk_api_key_id_fkey FOREIGN KEY (fk_api_key_id) REFERENCES api_keys (id);
+ALTER TABLE api_keys ADD CONSTRAINT api_keys_template_node_id_fkey FOREIGN KEY (template_node_id) REFERENCES nodes (id) ON DELETE SET NULL;
+ALTER TABLE sensor_triggers ADD CONSTRAINT sensor_triggers_node_id_fkey FOREIGN KEY (node_id) REFERENCES nodes (id) ON DELETE CASCADE;
+ALTER TABLE incidents ADD CONSTRAINT incidents_node_id_fkey FOREIGN KEY (node_id) REFERENCES nodes (id) ON DELETE CASCADE;
+ALTER TABLE incidents ADD CONSTRAINT incidents_sensor_triggers_id_fkey FOREIGN KEY (sensor_triggers_id) REFERENCES sensor_triggers (sensor_triggers_id) ON DELETE CASCADE;
+ALTER TABLE node_state_transitions ADD CONSTRAINT node_state_transitions_node_id_fkey FOREIGN KEY (node_id) REFERENCES nodes (id) ON DELETE CASCADE;
+ALTER TABLE sensor_readings ADD CONSTRAINT sensor_readings_node_id_fkey FOREIGN KEY (node_id) REFERENCES nodes (id) ON DELETE CASCADE;
+ALTER TABLE sensors ADD CONSTRAINT sensors_node_id_fkey FOREIGN KEY (node_id) REFERENCES nodes (id) ON DELETE CASCADE;
+ALTER TABLE node_system_health ADD CONSTRAINT node_system_health_node_id_fkey FOREIGN KEY (node_id) REFERENCES nodes (id) ON DELETE CASCADE;
+
+
+-- offline scan of alert-sender
+CREATE INDEX IF NOT EXISTS nodes_monitored_last_checkin
+    ON nodes USING btree (last_checkin_timestamp) WHERE monitoring_enabled = true;
+-- open incident lookups of every checkin and of alert-sender
+CREATE INDEX IF NOT EXISTS incidents_open_node_type
+    ON incidents USING btree (node_id, incident_type, sensor_triggers_id) WHERE resolved_at IS NULL;
+CREATE INDEX IF NOT EXISTS sensor_triggers_node
+    ON sensor_triggers USING btree (node_id);
+CREATE INDEX IF NOT EXISTS node_state_transitions_node_transition_at
+    ON node_state_transitions USING btree (node_id, transition_at);
+
+
+SELECT message AS migration_report FROM migration_report;
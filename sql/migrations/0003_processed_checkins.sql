@@ -0,0 +1,16 @@
+-- Table: processed_checkins
+-- Client provided checkin ids. A retried checkin with a known id is answered without processing it again.
+-- Ids older than the retention are deleted by alert-sender.
+
+CREATE TABLE IF NOT EXISTS processed_checkins
+(
+    fk_api_key_id integer NOT NULL,
+    node_id_external character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    checkin_id character varying(100) COLLATE pg_catalog."default" NOT NULL,
+    processed_at timestamp with time zone NOT NULL,
+    CONSTRAINT processed_checkins_pkey PRIMARY KEY (fk_api_key_id, node_id_external, checkin_id),
+    CONSTRAINT processed_checkins_fk_api_key_id_fkey FOREIGN KEY (fk_api_key_id) REFERENCES api_keys (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS processed_checkins_processed_at
+    ON processed_checkins USING btree (processed_at);
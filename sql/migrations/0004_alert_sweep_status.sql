@@ -0,0 +1,11 @@
+-- Table: alert_sweep_status
+-- One row, updated by every alert sweep (alert-sender or run-alert-sweep). Used by /readyz.
+
+CREATE TABLE IF NOT EXISTS alert_sweep_status
+(
+    id smallint NOT NULL DEFAULT 1,
+    last_finished_at timestamp with time zone NOT NULL,
+    last_duration_seconds double precision NOT NULL,
+    CONSTRAINT alert_sweep_status_pkey PRIMARY KEY (id),
+    CONSTRAINT "alert sweep status has one row" CHECK (id = 1)
+);
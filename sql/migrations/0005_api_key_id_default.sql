@@ -0,0 +1,7 @@
+-- api_keys.id gets its value from a sequence, concurrently created keys can not pick the same id.
+
+CREATE SEQUENCE IF NOT EXISTS api_keys_id_seq OWNED BY api_keys.id;
+
+SELECT setval('api_keys_id_seq', COALESCE((SELECT max(id) FROM api_keys), 0) + 1, false);
+
+ALTER TABLE api_keys ALTER COLUMN id SET DEFAULT nextval('api_keys_id_seq');
@@ -0,0 +1,4 @@
+-- the alert sweep deletes readings older than sensor_history_retention_days
+
+CREATE INDEX IF NOT EXISTS sensor_readings_reading_timestamp
+    ON sensor_readings USING btree (reading_timestamp);
@@ -0,0 +1,103 @@
+use crate::models::{Email, IncidentConfig, Nodes, SensorHistoryConfig, TelegramConfig};
+use actix_web::web;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::GenericClient;
+
+use log::debug;
+use std::time::Instant;
+
+use crate::checkin_idempotency;
+use crate::incident_functions;
+use crate::metrics;
+use crate::notification_outbox::Outbox;
+use crate::send_email;
+use crate::sensor_history;
+use crate::uptime_report;
+
+/// A monitored node is offline when it did not check in for this long.
+pub const OFFLINE_AFTER_MINUTES: i64 = 5;
+
+pub struct AlertSweepResult {
+    pub offline_nodes_count: usize,
+    pub expired_checkin_ids: u64,
+    pub expired_sensor_readings: u64,
+}
+
+/// Opens an offline incident (and sends the notification) for every monitored node that stopped checking in,
+/// records the offline transition of every node that stopped checking in and deletes expired checkin ids and sensor readings.
+/// Called periodically through /alert-sender or `run-alert-sweep`. Database errors are returned, a repeating sweep
+/// continues with the next run.
+pub async fn run_alert_sweep(
+    client: &impl GenericClient,
+    email_config: &web::Data<Email>,
+    telegram_config: &web::Data<TelegramConfig>,
+    incident_config: &web::Data<IncidentConfig>,
+    sensor_history_config: &web::Data<SensorHistoryConfig>,
+) -> Result<AlertSweepResult, tokio_postgres::Error> {
+    use tokio_pg_mapper::FromTokioPostgresRow;
+
+    let started_at = Instant::now();
+    let offline_select_timestamp =  Utc::now() - Duration::minutes(OFFLINE_AFTER_MINUTES);
+    debug!("selecting nodes with monitoring enabled and checkin time < than {:?}" , &offline_select_timestamp);
+
+    let stmt_offline_nodes_list = client.prepare_cached("SELECT id, node_id_external, fk_api_key_id, monitoring_enabled, last_checkin_timestamp, notification_email_list, node_group \
+    FROM nodes where monitoring_enabled = true AND notification_email_list <> '' AND last_checkin_timestamp < $1 \
+    AND NOT EXISTS (SELECT 1 FROM incidents WHERE incidents.node_id = nodes.id AND incidents.incident_type = $2 AND incidents.resolved_at IS NULL);").await?;
+    let rows_offline_nodes_list = client.query(&stmt_offline_nodes_list, &[&offline_select_timestamp, &incident_functions::INCIDENT_TYPE_NODE_OFFLINE] ).await?;
+
+    let offline_nodes_count = rows_offline_nodes_list.len();
+    let mut outbox = Outbox::default();
+
+    for row_offline_nodes in rows_offline_nodes_list {
+        let offline_node = Nodes::from_row(row_offline_nodes).unwrap();
+        debug!("Offline node: id = {} last_checkin_timestamp= {:?}", &offline_node.id, &offline_node.last_checkin_timestamp);
+
+        let incident = incident_functions::open_incident(
+            client,
+            incident_functions::INCIDENT_TYPE_NODE_OFFLINE,
+            &offline_node.id,
+            &None,
+            &Utc::now(),
+            &None,
+        ).await?;
+
+        outbox.push(send_email::node_offline_email(
+            &offline_node.node_id_external,
+            &offline_node.notification_email_list,
+            &offline_node.last_checkin_timestamp,
+            &incident,
+        ));
+    }
+
+    // the node is counted as offline since it was last seen, also when it is not monitored
+    uptime_report::record_offline_transitions(client, &offline_select_timestamp).await?;
+
+    outbox.send(email_config, telegram_config, incident_config).await;
+
+    let expired_checkin_ids = checkin_idempotency::delete_expired_checkin_ids(client, &Utc::now()).await?;
+    let expired_sensor_readings = sensor_history::delete_expired_sensor_readings(client, &Utc::now(), sensor_history_config.retention_days).await?;
+    let finished_at = Utc::now();
+    record_alert_sweep(client, &finished_at, started_at.elapsed().as_secs_f64()).await?;
+    metrics::observe_alert_sweep(started_at.elapsed(), &finished_at);
+
+    Ok(AlertSweepResult { offline_nodes_count, expired_checkin_ids, expired_sensor_readings })
+}
+
+/// Kept in the database, the sweep may run in another process (`run-alert-sweep`).
+pub async fn record_alert_sweep(
+    client: &impl GenericClient,
+    finished_at: &DateTime<Utc>,
+    duration_seconds: f64,
+) -> Result<(), tokio_postgres::Error> {
+    let stmt_sweep_status_upsert = client.prepare_cached("INSERT INTO alert_sweep_status(id, last_finished_at, last_duration_seconds) VALUES (1, $1, $2)
+	ON CONFLICT (id) DO UPDATE SET last_finished_at = EXCLUDED.last_finished_at, last_duration_seconds = EXCLUDED.last_duration_seconds;").await?;
+    client.execute(&stmt_sweep_status_upsert, &[finished_at, &duration_seconds]).await?;
+    Ok(())
+}
+
+/// None when no sweep has run yet. Errors are returned, /readyz reports them instead of failing the request.
+pub async fn last_alert_sweep_at(client: &impl GenericClient) -> Result<Option<DateTime<Utc>>, tokio_postgres::Error> {
+    let stmt_sweep_status = client.prepare_cached("SELECT last_finished_at FROM alert_sweep_status WHERE id = 1;").await?;
+    let rows = client.query(&stmt_sweep_status, &[]).await?;
+    Ok(rows.first().map(|row| row.get(0)))
+}
@@ -0,0 +1,362 @@
+use crate::models::{AdminConfig, Email, IncidentConfig, RateLimitConfig, ReadinessConfig, ReportConfig, SensorHistoryConfig, SystemHealthConfig, TelegramConfig};
+use ::config::{Config, ConfigError};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+use crate::migrations;
+use crate::trigger_expression;
+
+/// Optional TOML file. Settings have the same names in the file and in the environment, environment wins.
+pub const DEFAULT_CONFIG_FILE: &str = "remote-pi-monitor.toml";
+
+/// Acknowledge links are signed with incident_ack_secret, a short secret would let anyone compute them.
+pub const MIN_ACK_SECRET_LENGTH: usize = 32;
+
+
+#[derive(Debug, Clone)]
+pub struct DatabaseConfig {
+    pub user: String,
+    pub password: String,
+    pub host: String,
+    pub port: u16,
+    pub dbname: String,
+    pub schema: String,
+    pub migrate_on_startup: bool,
+    pub pool_max_size: usize,
+    pub pool_wait_timeout_seconds: u64,
+    pub pool_create_timeout_seconds: u64,
+    pub pool_recycle_timeout_seconds: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub server_addr: String,
+    pub database: DatabaseConfig,
+    pub email: Email,
+    pub telegram: TelegramConfig,
+    pub incident: IncidentConfig,
+    pub report: ReportConfig,
+    pub system_health: SystemHealthConfig,
+    pub sensor_history: SensorHistoryConfig,
+    pub rate_limit: RateLimitConfig,
+    pub admin: AdminConfig,
+    pub readiness: ReadinessConfig,
+}
+
+/// Reads settings one by one and keeps every missing / invalid one, so that all of them are reported at once.
+struct ConfigReader<'a> {
+    config: &'a Config,
+    errors: Vec<String>,
+}
+
+impl ConfigReader<'_> {
+    fn required<T: DeserializeOwned + Default>(&mut self, key: &str) -> T {
+        match self.config.get::<T>(key) {
+            Ok(x) => x,
+            Err(ConfigError::NotFound(_)) => {
+                self.errors.push(format!("{}: missing", key));
+                T::default()
+            }
+            Err(e) => {
+                self.errors.push(format!("{}: invalid value ({})", key, e));
+                T::default()
+            }
+        }
+    }
+
+    /// Required string of at least `min_length` characters. Empty counts as missing.
+    fn required_secret(&mut self, key: &str, min_length: usize) -> String {
+        let secret: String = self.optional(key, String::new());
+        if secret.is_empty() {
+            self.errors.push(format!("{}: missing", key));
+        } else if secret.chars().count() < min_length {
+            self.errors.push(format!("{}: must be at least {} characters", key, min_length));
+        }
+        secret
+    }
+
+    fn optional<T: DeserializeOwned>(&mut self, key: &str, default: T) -> T {
+        match self.config.get::<T>(key) {
+            Ok(x) => x,
+            Err(ConfigError::NotFound(_)) => default,
+            Err(e) => {
+                self.errors.push(format!("{}: invalid value ({})", key, e));
+                default
+            }
+        }
+    }
+
+    fn check(&mut self, valid: bool, key: &str, error: &str) {
+        if !valid {
+            self.errors.push(format!("{}: {}", key, error));
+        }
+    }
+}
+
+impl AppConfig {
+    /// Configuration of the file (if present) overridden by the environment.
+    pub fn load(config_file: &str) -> Result<AppConfig, Vec<String>> {
+        let config = Config::builder()
+            .add_source(::config::File::with_name(config_file).required(false))
+            .add_source(::config::Environment::default())
+            .build()
+            .map_err(|e| vec![format!("{}: {}", config_file, e)])?;
+
+        AppConfig::from_config(&config)
+    }
+
+    pub fn from_config(config: &Config) -> Result<AppConfig, Vec<String>> {
+        let mut reader = ConfigReader { config, errors: Vec::new() };
+
+        let app_config = AppConfig {
+            server_addr: reader.required("server_addr"),
+            database: DatabaseConfig {
+                user: reader.required("pg_user"),
+                password: reader.required("pg_password"),
+                host: reader.required("pg_host"),
+                port: reader.required("pg_port"),
+                dbname: reader.required("pg_dbname"),
+                // all tables live in this schema, queries use unqualified names resolved through search_path
+                schema: reader.optional("pg_schema", "remote_pi_monitor".to_string()),
+                migrate_on_startup: reader.optional("migrate_on_startup", true),
+                pool_max_size: reader.optional("pg_pool_max_size", 16),
+                pool_wait_timeout_seconds: reader.optional("pg_pool_wait_timeout_seconds", 10),
+                pool_create_timeout_seconds: reader.optional("pg_pool_create_timeout_seconds", 10),
+                pool_recycle_timeout_seconds: reader.optional("pg_pool_recycle_timeout_seconds", 5),
+            },
+            // notification channels are optional, each one is enabled by its server / bot token
+            email: Email {
+                smtp_server: reader.optional("email_smtp_server", String::new()),
+                username: reader.optional("email_username", String::new()),
+                password: reader.optional("email_password", String::new()),
+            },
+            telegram: TelegramConfig {
+                bot_token: reader.optional("telegram_config_bot_token", String::new()),
+                channel_id: reader.optional("telegram_config_channel_id", String::new()),
+            },
+            incident: IncidentConfig {
+                ack_secret: reader.required_secret("incident_ack_secret", MIN_ACK_SECRET_LENGTH),
+                public_base_url: reader.required("public_base_url"),
+            },
+            // monthly uptime report is not sent when the list is not set
+            report: ReportConfig {
+                email_list: reader.optional("report_email_list", String::new()),
+            },
+            // built-in node checks: disk usage of the checkin system block, device clock skew
+            system_health: SystemHealthConfig {
+                disk_used_percent_limit: reader.optional("system_disk_used_percent_limit", 90.0),
+                clock_skew_warning_seconds: reader.optional("clock_skew_warning_seconds", 120.0),
+            },
+            // sensor readings older than this are deleted by the alert sweep
+            sensor_history: SensorHistoryConfig {
+                retention_days: reader.optional("sensor_history_retention_days", 90),
+            },
+            // token buckets of /checkin: sustained requests per second and burst size
+            rate_limit: RateLimitConfig {
+                api_key_per_second: reader.optional("rate_limit_api_key_per_second", 10.0),
+                api_key_burst: reader.optional("rate_limit_api_key_burst", 50.0),
+                node_per_second: reader.optional("rate_limit_node_per_second", 1.0),
+                node_burst: reader.optional("rate_limit_node_burst", 5.0),
+            },
+            // admin API is disabled when the key is not set, new node notifications are not sent without the list
+            admin: AdminConfig {
+                admin_api_key: reader.optional("admin_api_key", String::new()),
+                admin_email_list: reader.optional("admin_email_list", String::new()),
+            },
+            // /readyz reports degraded when the last alert sweep is older or more notifications are waiting
+            readiness: ReadinessConfig {
+                alert_sweep_max_age_seconds: reader.optional("readiness_alert_sweep_max_age_seconds", 600),
+                notification_backlog_limit: reader.optional("readiness_notification_backlog_limit", 100),
+            },
+        };
+
+        reader.check(migrations::is_valid_schema_name(&app_config.database.schema), "pg_schema", "must be lowercase letters, digits and '_'");
+        if app_config.email.is_enabled() {
+            reader.check(!app_config.email.username.is_empty(), "email_username", "missing, required when email_smtp_server is set");
+            reader.check(!app_config.email.password.is_empty(), "email_password", "missing, required when email_smtp_server is set");
+        }
+        if app_config.telegram.is_enabled() {
+            reader.check(!app_config.telegram.channel_id.is_empty(), "telegram_config_channel_id", "missing, required when telegram_config_bot_token is set");
+        }
+        reader.check(app_config.database.pool_max_size > 0, "pg_pool_max_size", "must be greater than 0");
+        reader.check(
+            app_config.incident.public_base_url.is_empty() || app_config.incident.public_base_url.starts_with("http"),
+            "public_base_url",
+            "must start with http:// or https://",
+        );
+        reader.check(
+            (0.0..=100.0).contains(&app_config.system_health.disk_used_percent_limit),
+            "system_disk_used_percent_limit",
+            "must be between 0 and 100",
+        );
+        reader.check(app_config.system_health.clock_skew_warning_seconds > 0.0, "clock_skew_warning_seconds", "must be greater than 0");
+        // trigger expressions aggregate the history of up to avg_31d
+        reader.check(
+            app_config.sensor_history.retention_days >= trigger_expression::MAX_HISTORY_WINDOW_DAYS,
+            "sensor_history_retention_days",
+            &format!("must be at least {}, the longest trigger expression window", trigger_expression::MAX_HISTORY_WINDOW_DAYS),
+        );
+        reader.check(app_config.readiness.alert_sweep_max_age_seconds > 0, "readiness_alert_sweep_max_age_seconds", "must be greater than 0");
+        for (key, value) in [
+            ("rate_limit_api_key_per_second", app_config.rate_limit.api_key_per_second),
+            ("rate_limit_node_per_second", app_config.rate_limit.node_per_second),
+        ] {
+            reader.check(value > 0.0, key, "must be greater than 0");
+        }
+        // a request takes a whole token, a smaller bucket never allows one
+        for (key, value) in [
+            ("rate_limit_api_key_burst", app_config.rate_limit.api_key_burst),
+            ("rate_limit_node_burst", app_config.rate_limit.node_burst),
+        ] {
+            reader.check(value >= 1.0, key, "must be at least 1");
+        }
+
+        if reader.errors.is_empty() {
+            Ok(app_config)
+        } else {
+            Err(reader.errors)
+        }
+    }
+
+    pub fn pg_pool_config(&self) -> deadpool_postgres::Config {
+        let database = &self.database;
+        let mut pool_config = deadpool_postgres::PoolConfig::new(database.pool_max_size);
+        pool_config.timeouts.wait = Some(Duration::from_secs(database.pool_wait_timeout_seconds));
+        pool_config.timeouts.create = Some(Duration::from_secs(database.pool_create_timeout_seconds));
+        pool_config.timeouts.recycle = Some(Duration::from_secs(database.pool_recycle_timeout_seconds));
+
+        deadpool_postgres::Config {
+            user: Some(database.user.clone()),
+            password: Some(database.password.clone()),
+            host: Some(database.host.clone()),
+            port: Some(database.port),
+            dbname: Some(database.dbname.clone()),
+            options: Some(migrations::search_path_option(&database.schema)),
+            pool: Some(pool_config),
+            ..Default::default()
+        }
+    }
+
+    /// Startup log lines. Secrets are only reported as set / not set.
+    pub fn report(&self) -> Vec<String> {
+        let set = |x: &str| if x.is_empty() { "not set" } else { "set" };
+        vec![
+            format!("server_addr = {}", self.server_addr),
+            format!(
+                "database = {}@{}:{}/{} schema = {} migrate_on_startup = {}",
+                self.database.user, self.database.host, self.database.port, self.database.dbname, self.database.schema, self.database.migrate_on_startup,
+            ),
+            format!(
+                "pool max_size = {} wait/create/recycle timeouts = {}/{}/{} seconds",
+                self.database.pool_max_size, self.database.pool_wait_timeout_seconds, self.database.pool_create_timeout_seconds, self.database.pool_recycle_timeout_seconds,
+            ),
+            match self.email.is_enabled() {
+                true => format!("email = {} {}", self.email.smtp_server, self.email.username),
+                false => "email = disabled".to_string(),
+            },
+            match self.telegram.is_enabled() {
+                true => format!("telegram = channel {}", self.telegram.channel_id),
+                false => "telegram = disabled".to_string(),
+            },
+            format!("public_base_url = {} incident_ack_secret = {}", self.incident.public_base_url, set(&self.incident.ack_secret)),
+            format!("report_email_list = {}", self.report.email_list),
+            format!(
+                "system_disk_used_percent_limit = {} clock_skew_warning_seconds = {}",
+                self.system_health.disk_used_percent_limit, self.system_health.clock_skew_warning_seconds,
+            ),
+            format!("sensor_history_retention_days = {}", self.sensor_history.retention_days),
+            format!(
+                "rate limit api key = {}/s burst {} node = {}/s burst {}",
+                self.rate_limit.api_key_per_second, self.rate_limit.api_key_burst, self.rate_limit.node_per_second, self.rate_limit.node_burst,
+            ),
+            format!("admin_api_key = {} admin_email_list = {}", set(&self.admin.admin_api_key), self.admin.admin_email_list),
+            format!(
+                "readiness alert_sweep_max_age_seconds = {} notification_backlog_limit = {}",
+                self.readiness.alert_sweep_max_age_seconds, self.readiness.notification_backlog_limit,
+            ),
+        ]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REQUIRED: [(&str, &str); 6] = [
+        ("server_addr", "127.0.0.1:8080"),
+        ("pg_user", "monitor"),
+        ("pg_password", "secret"),
+        ("pg_host", "localhost"),
+        ("pg_port", "5432"),
+        ("pg_dbname", "monitor"),
+    ];
+
+    const ACK_SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn config(settings: &[(&str, &str)]) -> Config {
+        let mut builder = Config::builder();
+        for (key, value) in settings {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn defaults_of_optional_settings() {
+        let mut settings = REQUIRED.to_vec();
+        settings.extend([("incident_ack_secret", ACK_SECRET), ("public_base_url", "https://monitor.example.com")]);
+
+        let app_config = AppConfig::from_config(&config(&settings)).unwrap();
+        assert_eq!(app_config.database.port, 5432);
+        assert_eq!(app_config.database.schema, "remote_pi_monitor");
+        assert_eq!(app_config.database.pool_max_size, 16);
+        assert_eq!(app_config.system_health.disk_used_percent_limit, 90.0);
+        assert_eq!(app_config.sensor_history.retention_days, 90);
+        assert!(app_config.admin.admin_api_key.is_empty());
+        assert!(!app_config.email.is_enabled());
+        assert!(!app_config.telegram.is_enabled());
+    }
+
+    #[test]
+    fn all_missing_and_invalid_settings_are_reported() {
+        let mut settings = REQUIRED.to_vec();
+        settings.retain(|(key, _)| *key != "pg_host");
+        settings.extend([
+            ("pg_port", "postgres"),
+            ("pg_schema", "Monitor"),
+            ("rate_limit_node_burst", "0.5"),
+            ("sensor_history_retention_days", "30"),
+            ("email_smtp_server", "smtp.example.com"),
+            ("email_username", "monitor@example.com"),
+            ("telegram_config_bot_token", "token"),
+        ]);
+
+        let errors = AppConfig::from_config(&config(&settings)).unwrap_err();
+        let keys: Vec<&str> = errors.iter().map(|x| x.split(':').next().unwrap()).collect();
+        assert_eq!(keys, vec![
+            "pg_host",
+            "pg_port",
+            "incident_ack_secret",
+            "public_base_url",
+            "pg_schema",
+            "email_password",
+            "telegram_config_channel_id",
+            "sensor_history_retention_days",
+            "rate_limit_node_burst",
+        ]);
+    }
+
+    #[test]
+    fn empty_or_short_ack_secret_is_rejected() {
+        let mut settings = REQUIRED.to_vec();
+        settings.extend([("public_base_url", "https://monitor.example.com"), ("incident_ack_secret", "")]);
+        assert_eq!(AppConfig::from_config(&config(&settings)).unwrap_err(), vec!["incident_ack_secret: missing".to_string()]);
+
+        settings.push(("incident_ack_secret", "ack"));
+        assert_eq!(AppConfig::from_config(&config(&settings)).unwrap_err(), vec!["incident_ack_secret: must be at least 32 characters".to_string()]);
+
+        settings.push(("incident_ack_secret", ACK_SECRET));
+        assert!(AppConfig::from_config(&config(&settings)).is_ok());
+    }
+}
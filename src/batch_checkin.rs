@@ -0,0 +1,84 @@
+use crate::models::SensorData;
+use chrono::{DateTime, Duration, Utc};
+
+
+/// Readings older than this are backfill: they are stored in history but do not run triggers. Same window after
+/// which alert-sender reports a node offline.
+pub const BACKFILL_AGE_MINUTES: i64 = 5;
+
+
+pub fn is_backfill(reading_timestamp: &DateTime<Utc>, now: &DateTime<Utc>) -> bool {
+    *reading_timestamp < *now - Duration::minutes(BACKFILL_AGE_MINUTES)
+}
+
+/// Readings of the checkin that are recent enough to be validated by triggers.
+pub fn live_sensor_data(
+    sensor_data: &Option<Vec<SensorData>>,
+    now: &DateTime<Utc>,
+) -> Option<Vec<SensorData>> {
+    sensor_data.as_ref().map(|sensor_data| {
+        sensor_data
+            .iter()
+            .filter(|x| !x.timestamp.is_some_and(|timestamp| is_backfill(&timestamp, now)))
+            .cloned()
+            .collect()
+    })
+}
+
+/// Live checkins run the trigger, offline and system health checks. A checkin is live unless all of its readings are backfill.
+pub fn is_live_checkin(sensor_data: &Option<Vec<SensorData>>, now: &DateTime<Utc>) -> bool {
+    match live_sensor_data(sensor_data, now) {
+        Some(live_sensor_data) => !live_sensor_data.is_empty() || sensor_data.as_ref().is_some_and(|x| x.is_empty()),
+        None => true,
+    }
+}
+
+/// Timestamp the node was last seen at: the newest reading, at most `now`.
+pub fn newest_reading_timestamp(sensor_data: &Option<Vec<SensorData>>, now: &DateTime<Utc>) -> DateTime<Utc> {
+    sensor_data
+        .iter()
+        .flatten()
+        .map(|x| x.timestamp.unwrap_or(*now))
+        .max()
+        .map(|x| x.min(*now))
+        .unwrap_or(*now)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SensorValue;
+
+    fn reading(id: &str, minutes_ago: Option<i64>, now: &DateTime<Utc>) -> SensorData {
+        SensorData {
+            id: id.to_string(),
+            sensor_name: id.to_string(),
+            value: SensorValue::Number(1.0),
+            unit: None,
+            timestamp: minutes_ago.map(|x| *now - Duration::minutes(x)),
+        }
+    }
+
+    #[test]
+    fn backfilled_readings_are_not_live() {
+        let now = Utc::now();
+        let sensor_data = Some(vec![reading("a", Some(60), &now), reading("b", Some(1), &now), reading("c", None, &now)]);
+
+        let live: Vec<String> = live_sensor_data(&sensor_data, &now).unwrap().into_iter().map(|x| x.id).collect();
+        assert_eq!(live, vec!["b".to_string(), "c".to_string()]);
+        assert!(is_live_checkin(&sensor_data, &now));
+        assert!(!is_live_checkin(&Some(vec![reading("a", Some(60), &now)]), &now));
+        assert!(is_live_checkin(&None, &now));
+        assert!(is_live_checkin(&Some(Vec::new()), &now));
+    }
+
+    #[test]
+    fn newest_reading_is_last_seen() {
+        let now = Utc::now();
+        let buffered = Some(vec![reading("a", Some(60), &now), reading("a", Some(30), &now)]);
+
+        assert_eq!(newest_reading_timestamp(&buffered, &now), now - Duration::minutes(30));
+        assert_eq!(newest_reading_timestamp(&None, &now), now);
+    }
+}
@@ -0,0 +1,34 @@
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::GenericClient;
+
+use log::debug;
+
+
+/// Checkin ids are remembered this long. A node retrying later than that is processed again.
+pub const CHECKIN_ID_RETENTION_HOURS: i64 = 24;
+
+/// Records the checkin id within the checkin transaction. Returns false when the checkin was already processed.
+/// A concurrent retry waits on the primary key until the first checkin commits and is then a duplicate,
+/// if the first checkin rolls back the retry is processed.
+pub async fn claim_checkin_id(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+    node_id_external: &str,
+    checkin_id: &str,
+    processed_at: &DateTime<Utc>,
+) -> bool {
+    let stmt_checkin_id_insert = dbconnection.prepare_cached("INSERT INTO processed_checkins(fk_api_key_id, node_id_external, checkin_id, processed_at)
+	VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING;").await.unwrap();
+    let inserted = dbconnection.execute(&stmt_checkin_id_insert, &[api_key_id, &node_id_external, &checkin_id, processed_at]).await.unwrap();
+    debug!("checkin_id = {} node_id = {} claimed = {}", checkin_id, node_id_external, inserted == 1);
+
+    inserted == 1
+}
+
+pub async fn delete_expired_checkin_ids(
+    dbconnection: &impl GenericClient,
+    now: &DateTime<Utc>,
+) -> Result<u64, tokio_postgres::Error> {
+    let stmt_checkin_id_delete = dbconnection.prepare_cached("DELETE FROM processed_checkins WHERE processed_at < $1;").await?;
+    dbconnection.execute(&stmt_checkin_id_delete, &[&(*now - Duration::hours(CHECKIN_ID_RETENTION_HOURS))]).await
+}
@@ -0,0 +1,197 @@
+use crate::models::{CheckinData, FieldError, SensorValue};
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+
+
+// column sizes of nodes / sensors / sensor_readings / node_system_health
+pub const MAX_ID_LENGTH: usize = 100;
+pub const MAX_SENSOR_NAME_LENGTH: usize = 100;
+pub const MAX_TEXT_VALUE_LENGTH: usize = 255;
+pub const MAX_UNIT_LENGTH: usize = 20;
+pub const MAX_OS_LENGTH: usize = 255;
+pub const MAX_AGENT_VERSION_LENGTH: usize = 100;
+
+// device and reading timestamps: 2000-01-01 .. 2100-01-01. Wrong clocks are expected, absurd dates are rejected
+pub const MIN_TIMESTAMP_SECONDS: i64 = 946684800;
+pub const MAX_TIMESTAMP_SECONDS: i64 = 4102444800;
+
+pub const MAX_SENSORS_PER_CHECKIN: usize = 200;
+pub const MAX_CHECKINS_PER_BATCH: usize = 100;
+
+
+/// Node and sensor ids: letters, digits and '.', '_', ':', '-'.
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_ID_LENGTH && id.chars().all(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | '_' | ':' | '-'))
+}
+
+fn id_error(id: &str) -> String {
+    format!("must be 1 to {} characters of letters, digits, '.', '_', ':' or '-'. Got '{}'", MAX_ID_LENGTH, id)
+}
+
+fn check_length(errors: &mut Vec<FieldError>, field: String, value: &str, max_length: usize) {
+    if value.chars().count() > max_length {
+        errors.push(FieldError { field, error: format!("must be at most {} characters", max_length) });
+    }
+}
+
+fn check_finite(errors: &mut Vec<FieldError>, field: String, value: &Option<f64>) {
+    if value.is_some_and(|x| !x.is_finite()) {
+        errors.push(FieldError { field, error: "must be a finite number".to_string() });
+    }
+}
+
+fn check_timestamp(errors: &mut Vec<FieldError>, field: String, value: &Option<DateTime<Utc>>) {
+    if value.is_some_and(|x| !(MIN_TIMESTAMP_SECONDS..MAX_TIMESTAMP_SECONDS).contains(&x.timestamp())) {
+        errors.push(FieldError { field, error: "must be between 2000-01-01 and 2100-01-01".to_string() });
+    }
+}
+
+/// All field errors of a checkin. `field_prefix` is prepended to the field names ("checkins[2]." for batch entries).
+pub fn validate_checkin(checkin_data: &CheckinData, field_prefix: &str) -> Vec<FieldError> {
+    let mut errors: Vec<FieldError> = Vec::new();
+
+    if !is_valid_id(&checkin_data.node_id) {
+        errors.push(FieldError { field: format!("{}node_id", field_prefix), error: id_error(&checkin_data.node_id) });
+    }
+    if let Some(checkin_id) = checkin_data.checkin_id.as_ref().filter(|x| !is_valid_id(x)) {
+        errors.push(FieldError { field: format!("{}checkin_id", field_prefix), error: id_error(checkin_id) });
+    }
+    check_timestamp(&mut errors, format!("{}device_timestamp", field_prefix), &checkin_data.device_timestamp);
+
+    if let Some(sensor_data) = &checkin_data.sensor_data {
+        if sensor_data.len() > MAX_SENSORS_PER_CHECKIN {
+            errors.push(FieldError {
+                field: format!("{}sensor_data", field_prefix),
+                error: format!("must have at most {} entries. Got {}", MAX_SENSORS_PER_CHECKIN, sensor_data.len()),
+            });
+        }
+
+        // buffered readings repeat sensor ids, but only with different timestamps
+        let mut seen_readings = HashSet::new();
+        for (index, sensor_value) in sensor_data.iter().enumerate() {
+            let field = |name: &str| format!("{}sensor_data[{}].{}", field_prefix, index, name);
+
+            if !is_valid_id(&sensor_value.id) {
+                errors.push(FieldError { field: field("id"), error: id_error(&sensor_value.id) });
+            }
+            if !seen_readings.insert((sensor_value.id.as_str(), sensor_value.timestamp)) {
+                errors.push(FieldError { field: field("id"), error: format!("duplicate sensor id '{}'", sensor_value.id) });
+            }
+            if sensor_value.sensor_name.is_empty() {
+                errors.push(FieldError { field: field("sensor_name"), error: "must not be empty".to_string() });
+            }
+            check_length(&mut errors, field("sensor_name"), &sensor_value.sensor_name, MAX_SENSOR_NAME_LENGTH);
+            match &sensor_value.value {
+                SensorValue::Number(x) => check_finite(&mut errors, field("value"), &Some(*x)),
+                SensorValue::Text(x) => check_length(&mut errors, field("value"), x, MAX_TEXT_VALUE_LENGTH),
+                SensorValue::Boolean(_) => {}
+            }
+            if let Some(unit) = &sensor_value.unit {
+                check_length(&mut errors, field("unit"), unit, MAX_UNIT_LENGTH);
+            }
+            check_timestamp(&mut errors, field("timestamp"), &sensor_value.timestamp);
+        }
+    }
+
+    if let Some(system) = &checkin_data.system {
+        let field = |name: &str| format!("{}system.{}", field_prefix, name);
+
+        check_finite(&mut errors, field("cpu_temperature"), &system.cpu_temperature);
+        check_finite(&mut errors, field("load_1m"), &system.load_1m);
+        check_finite(&mut errors, field("load_5m"), &system.load_5m);
+        check_finite(&mut errors, field("load_15m"), &system.load_15m);
+        check_finite(&mut errors, field("disk_used_percent"), &system.disk_used_percent);
+        check_finite(&mut errors, field("memory_used_percent"), &system.memory_used_percent);
+        if let Some(os) = &system.os {
+            check_length(&mut errors, field("os"), os, MAX_OS_LENGTH);
+        }
+        if let Some(agent_version) = &system.agent_version {
+            check_length(&mut errors, field("agent_version"), agent_version, MAX_AGENT_VERSION_LENGTH);
+        }
+    }
+
+    errors
+}
+
+/// All field errors of a batch checkin.
+pub fn validate_batch_checkin(checkins: &[CheckinData]) -> Vec<FieldError> {
+    let mut errors: Vec<FieldError> = Vec::new();
+
+    if checkins.len() > MAX_CHECKINS_PER_BATCH {
+        errors.push(FieldError {
+            field: "checkins".to_string(),
+            error: format!("must have at most {} entries. Got {}", MAX_CHECKINS_PER_BATCH, checkins.len()),
+        });
+    }
+    for (index, checkin_data) in checkins.iter().enumerate() {
+        errors.extend(validate_checkin(checkin_data, &format!("checkins[{}].", index)));
+    }
+
+    errors
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SensorData;
+
+    fn reading(id: &str, value: SensorValue) -> SensorData {
+        SensorData { id: id.to_string(), sensor_name: "temperature".to_string(), value, unit: None, timestamp: None }
+    }
+
+    fn checkin(node_id: &str, sensor_data: Vec<SensorData>) -> CheckinData {
+        CheckinData {
+            api_key: "key".to_string(),
+            node_id: node_id.to_string(),
+            sensor_data: Some(sensor_data),
+            system: None,
+            device_timestamp: None,
+            checkin_id: None,
+        }
+    }
+
+    fn fields(errors: Vec<FieldError>) -> Vec<String> {
+        errors.into_iter().map(|x| x.field).collect()
+    }
+
+    #[test]
+    fn id_charset_and_length() {
+        assert!(is_valid_id("pi-01.garage:28-000001_a"));
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id("pi 01"));
+        assert!(!is_valid_id("pi/01"));
+        assert!(is_valid_id(&"a".repeat(MAX_ID_LENGTH)));
+        assert!(!is_valid_id(&"a".repeat(MAX_ID_LENGTH + 1)));
+    }
+
+    #[test]
+    fn all_field_errors_are_listed() {
+        let mut checkin_data = checkin("pi 01", vec![
+            reading("28-000001", SensorValue::Number(f64::NAN)),
+            reading("28-000001", SensorValue::Number(21.5)),
+            reading("state", SensorValue::Text("x".repeat(MAX_TEXT_VALUE_LENGTH + 1))),
+            SensorData { timestamp: DateTime::from_timestamp(MAX_TIMESTAMP_SECONDS, 0), ..reading("door", SensorValue::Boolean(true)) },
+        ]);
+        checkin_data.checkin_id = Some("".to_string());
+        checkin_data.device_timestamp = Some(DateTime::<Utc>::MIN_UTC);
+
+        assert_eq!(fields(validate_checkin(&checkin_data, "")), vec![
+            "node_id".to_string(),
+            "checkin_id".to_string(),
+            "device_timestamp".to_string(),
+            "sensor_data[0].value".to_string(),
+            "sensor_data[1].id".to_string(),
+            "sensor_data[2].value".to_string(),
+            "sensor_data[3].timestamp".to_string(),
+        ]);
+        assert!(validate_checkin(&checkin("pi-01", vec![reading("door", SensorValue::Boolean(true))]), "").is_empty());
+    }
+
+    #[test]
+    fn sensor_count_and_batch_prefix() {
+        let sensor_data = (0..=MAX_SENSORS_PER_CHECKIN).map(|x| reading(&format!("s{}", x), SensorValue::Number(1.0))).collect();
+
+        assert_eq!(fields(validate_batch_checkin(&[checkin("pi-01", Vec::new()), checkin("pi-02", sensor_data)])), vec!["checkins[1].sensor_data".to_string()]);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::app_config::AppConfig;
+use crate::models::NodeExport;
+use actix_web::web;
+use chrono::Utc;
+use clap::{Parser, Subcommand, ValueEnum};
+use deadpool_postgres::{Object, Pool};
+use rand::distr::{Alphanumeric, SampleString};
+use std::io::{Error, Result};
+
+use log::error;
+use log::info;
+
+use crate::alert_sweep;
+use crate::migrations;
+use crate::node_export;
+use crate::node_registration;
+use crate::send_email;
+use crate::send_telegram;
+use crate::system_health;
+
+pub const API_KEY_LENGTH: usize = 32;
+
+
+#[derive(Parser)]
+#[command(version, about = "Remote Pi monitor: checkin API, alerting and operations")]
+pub struct Cli {
+    /// TOML configuration file. Default: CONFIG_FILE or remote-pi-monitor.toml
+    #[arg(long, global = true)]
+    pub config: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Runs the HTTP server (default)
+    Serve,
+    /// Applies pending database migrations
+    Migrate,
+    /// Loads the configuration and reports all missing / invalid settings
+    CheckConfig,
+    /// Sends a test message through one notification channel
+    SendTestNotification {
+        #[arg(long, value_enum)]
+        channel: Channel,
+        /// Recipient(s) of the test email, separated by ';'
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Creates an API key and prints it
+    CreateApiKey {
+        /// Key to create. A random key is generated when not set
+        #[arg(long)]
+        key: Option<String>,
+        /// What a checkin of an unknown node does: reject, pending or auto_enable
+        #[arg(long, default_value = node_registration::NEW_NODE_POLICY_PENDING)]
+        new_node_policy: String,
+        /// Node group whose latest monitored node is the template of auto-enabled nodes
+        #[arg(long)]
+        template_node_group: Option<String>,
+    },
+    /// Prints the nodes of an API key with their latest system health as JSON
+    ListNodes {
+        #[arg(long)]
+        api_key: String,
+    },
+    /// Opens offline incidents and sends their notifications, like GET /alert-sender
+    RunAlertSweep {
+        /// Runs one sweep and exits instead of repeating it
+        #[arg(long)]
+        once: bool,
+        #[arg(long, default_value_t = 60)]
+        interval_seconds: u64,
+    },
+    /// Writes the nodes and sensor triggers of an API key as JSON
+    Export {
+        #[arg(long)]
+        api_key: String,
+        /// Output file. Default: stdout
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Creates / updates nodes and sensor triggers of an API key from an `export` file
+    Import {
+        #[arg(long)]
+        api_key: String,
+        #[arg(long)]
+        input: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Channel {
+    Email,
+    Telegram,
+}
+
+impl Cli {
+    pub fn config_file(&self) -> String {
+        self.config.clone()
+            .or_else(|| std::env::var("CONFIG_FILE").ok())
+            .unwrap_or_else(|| crate::app_config::DEFAULT_CONFIG_FILE.to_string())
+    }
+}
+
+/// Result of a command: the error is printed as a message and the process exits with 1.
+pub fn exit_on_error(result: Result<()>) -> Result<()> {
+    if let Err(e) = &result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    result
+}
+
+fn command_error(message: String) -> Error {
+    Error::other(message)
+}
+
+fn database_error(e: tokio_postgres::Error) -> Error {
+    command_error(format!("database: {}", e))
+}
+
+/// Connection of the pool, an unreachable database is a command error.
+async fn connection(pool: &Pool) -> Result<Object> {
+    pool.get().await.map_err(|e| command_error(format!("database: {}", e)))
+}
+
+async fn api_key_id(pool: &Pool, api_key: &str) -> Result<i32> {
+    let client = connection(pool).await?;
+    let stmt_api_key = client.prepare_cached("SELECT id FROM api_keys WHERE api_key = $1;").await.map_err(database_error)?;
+    let rows = client.query(&stmt_api_key, &[&api_key]).await.map_err(database_error)?;
+
+    rows.first().map(|row| row.get(0)).ok_or_else(|| command_error(format!("api_key = {} is not found", api_key)))
+}
+
+/// Prints the configuration report. Fails when settings are missing or invalid.
+pub fn check_config(config_file: &str) -> Result<()> {
+    match AppConfig::load(config_file) {
+        Ok(app_config) => {
+            for line in app_config.report() {
+                println!("{}", line);
+            }
+            println!("Configuration is valid");
+            Ok(())
+        }
+        Err(errors) => {
+            for e in &errors {
+                println!("{}", e);
+            }
+            Err(command_error(format!("{} setting(s) missing or invalid", errors.len())))
+        }
+    }
+}
+
+pub async fn migrate(app_config: &AppConfig, pool: &Pool) -> Result<()> {
+    let mut client = connection(pool).await?;
+    let applied_versions = migrations::run_migrations(&mut client, &app_config.database.schema).await
+        .map_err(|e| command_error(format!("migration failed: {}", e)))?;
+    info!("Database schema {} is up to date. applied migrations = {:?}", app_config.database.schema, applied_versions);
+
+    Ok(())
+}
+
+/// Commands other than `serve`, `migrate` and `check-config`.
+pub async fn run_command(command: Command, app_config: &AppConfig, pool: &Pool) -> Result<()> {
+    match command {
+        Command::Serve | Command::Migrate | Command::CheckConfig => unreachable!("handled by main"),
+
+        Command::SendTestNotification { channel, to } => {
+            let message = format!("Remote Pi monitor test notification {}", Utc::now().format("%Y-%m-%d %H:%M:%S"));
+            match channel {
+                Channel::Email => {
+                    let to = to.ok_or_else(|| command_error("--to is required for the email channel".to_string()))?;
+                    if !app_config.email.is_enabled() {
+                        return Err(command_error("email is not configured (email_smtp_server)".to_string()));
+                    }
+                    send_email::send_email_generic(
+                        &to,
+                        "Remote Pi monitor test notification",
+                        &message,
+                        &message,
+                        &web::Data::new(app_config.email.clone()),
+                        &web::Data::new(app_config.telegram.clone()),
+                    ).await.map_err(command_error)?;
+                }
+                Channel::Telegram => {
+                    if !app_config.telegram.is_enabled() {
+                        return Err(command_error("telegram is not configured (telegram_config_bot_token)".to_string()));
+                    }
+                    send_telegram::send_telegram_msg(&message, &app_config.telegram).await.map_err(command_error)?;
+                }
+            }
+            println!("Test notification sent");
+        }
+
+        Command::CreateApiKey { key, new_node_policy, template_node_group } => {
+            let policies = [node_registration::NEW_NODE_POLICY_REJECT, node_registration::NEW_NODE_POLICY_PENDING, node_registration::NEW_NODE_POLICY_AUTO_ENABLE];
+            if !policies.contains(&new_node_policy.as_str()) {
+                return Err(command_error(format!("unknown new node policy '{}'. Expected one of {:?}", new_node_policy, policies)));
+            }
+            let api_key = key.unwrap_or_else(|| Alphanumeric.sample_string(&mut rand::rng(), API_KEY_LENGTH));
+
+            let client = connection(pool).await?;
+            let stmt_api_key_insert = client.prepare_cached("INSERT INTO api_keys(api_key, new_node_policy, template_node_group)
+	VALUES ($1, $2, $3) ON CONFLICT (api_key) DO NOTHING RETURNING id;").await.map_err(database_error)?;
+            let rows = client.query(&stmt_api_key_insert, &[&api_key, &new_node_policy, &template_node_group]).await.map_err(database_error)?;
+            if rows.is_empty() {
+                return Err(command_error("api key already exists".to_string()));
+            }
+            let api_key_id: i32 = rows[0].get(0);
+            info!("Created api key. api_keys.id = {}", api_key_id);
+            println!("{}", api_key);
+        }
+
+        Command::ListNodes { api_key } => {
+            let api_key_id = api_key_id(pool, &api_key).await?;
+            let client = connection(pool).await?;
+            let nodes = system_health::list_nodes(&client, &api_key_id, &None).await.map_err(database_error)?;
+            println!("{}", serde_json::to_string_pretty(&nodes).unwrap());
+        }
+
+        Command::RunAlertSweep { once, interval_seconds } => {
+            let email_config = web::Data::new(app_config.email.clone());
+            let telegram_config = web::Data::new(app_config.telegram.clone());
+            let incident_config = web::Data::new(app_config.incident.clone());
+            let sensor_history_config = web::Data::new(app_config.sensor_history.clone());
+            loop {
+                match connection(pool).await {
+                    Ok(client) => match alert_sweep::run_alert_sweep(&client, &email_config, &telegram_config, &incident_config, &sensor_history_config).await {
+                        Ok(result) => info!(
+                            "Alert sweep done. offline_nodes_count = {} expired_checkin_ids = {} expired_sensor_readings = {}",
+                            result.offline_nodes_count, result.expired_checkin_ids, result.expired_sensor_readings,
+                        ),
+                        Err(e) if !once => error!("Alert sweep failed: {}", database_error(e)),
+                        Err(e) => return Err(database_error(e)),
+                    },
+                    // a repeating sweep waits for the database to come back
+                    Err(e) if !once => error!("Alert sweep skipped: {}", e),
+                    Err(e) => return Err(e),
+                }
+                if once {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(interval_seconds)).await;
+            }
+        }
+
+        Command::Export { api_key, output } => {
+            let api_key_id = api_key_id(pool, &api_key).await?;
+            let client = connection(pool).await?;
+            let node_export = node_export::export_nodes(&client, &api_key_id).await.map_err(database_error)?;
+            let json = serde_json::to_string_pretty(&node_export).unwrap();
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, json)?;
+                    info!("Exported {} nodes to {}", node_export.nodes.len(), output);
+                }
+                None => println!("{}", json),
+            }
+        }
+
+        Command::Import { api_key, input } => {
+            let node_export: NodeExport = serde_json::from_str(&std::fs::read_to_string(&input)?)
+                .map_err(|e| command_error(format!("{}: {}", input, e)))?;
+            let errors = node_export::check_node_export(&node_export);
+            if !errors.is_empty() {
+                for e in &errors {
+                    println!("{}", e);
+                }
+                return Err(command_error(format!("{}: {} error(s), nothing imported", input, errors.len())));
+            }
+
+            let api_key_id = api_key_id(pool, &api_key).await?;
+            let mut client = connection(pool).await?;
+            let transaction = client.transaction().await.map_err(database_error)?;
+            let (added, deleted) = node_export::import_nodes(&transaction, &api_key_id, &node_export, &Utc::now()).await.map_err(database_error)?;
+            transaction.commit().await.map_err(database_error)?;
+            println!("Imported {} nodes. sensor triggers added = {} deleted = {}", node_export.nodes.len(), added, deleted);
+        }
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn subcommands_and_default() {
+        assert!(Cli::try_parse_from(["rust-remote-pi-monitor"]).unwrap().command.is_none());
+
+        let cli = Cli::try_parse_from(["rust-remote-pi-monitor", "run-alert-sweep", "--once", "--config", "lab.toml"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::RunAlertSweep { once: true, interval_seconds: 60 })));
+        assert_eq!(cli.config_file(), "lab.toml");
+
+        assert!(Cli::try_parse_from(["rust-remote-pi-monitor", "send-test-notification", "--channel", "sms"]).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use crate::models::{SensorData, SystemHealthConfig};
+use actix_web::web;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::GenericClient;
+
+use log::debug;
+
+use crate::incident_functions;
+use crate::system_health;
+use crate::notification_outbox::Outbox;
+
+
+/// Offset of the device clock against the server clock in seconds (positive = device clock is ahead).
+pub fn clock_offset_seconds(device_timestamp: &DateTime<Utc>, server_timestamp: &DateTime<Utc>) -> f64 {
+    device_timestamp.signed_duration_since(*server_timestamp).num_milliseconds() as f64 / 1000.0
+}
+
+pub fn clock_skew_exceeded(clock_offset_seconds: f64, clock_skew_warning_seconds: f64) -> bool {
+    clock_offset_seconds.abs() > clock_skew_warning_seconds
+}
+
+/// Reading timestamps are device time. With a known offset they are moved to server time.
+pub fn corrected_sensor_data(
+    sensor_data: &Option<Vec<SensorData>>,
+    clock_offset_seconds: Option<f64>,
+) -> Option<Vec<SensorData>> {
+    let offset = match clock_offset_seconds.and_then(|x| Duration::try_milliseconds((x * 1000.0).round() as i64)) {
+        Some(x) => x,
+        None => return sensor_data.clone(),
+    };
+
+    // validated timestamps can not overflow, a timestamp that would is kept as sent
+    sensor_data.as_ref().map(|sensor_data| {
+        sensor_data
+            .iter()
+            .map(|x| SensorData { timestamp: x.timestamp.map(|timestamp| timestamp.checked_sub_signed(offset).unwrap_or(timestamp)), ..x.clone() })
+            .collect()
+    })
+}
+
+pub async fn record_clock_offset(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    clock_offset_seconds: &f64,
+    measured_at: &DateTime<Utc>,
+) {
+    debug!("Clock offset: nodes.id = {} offset = {} seconds", node_id_db, clock_offset_seconds);
+
+    let stmt_offset_update = dbconnection.prepare_cached("UPDATE nodes SET clock_offset_seconds = $2, clock_offset_measured_at = $3 WHERE id = $1;").await.unwrap();
+    let _result = dbconnection.query(&stmt_offset_update, &[node_id_db, clock_offset_seconds, measured_at]).await.unwrap();
+}
+
+/// Opens a 'clock_skew' incident while the device clock is off by more than clock_skew_warning_seconds.
+#[allow(clippy::too_many_arguments)]
+pub async fn clock_skew_check(
+    node_id_db: &i32,
+    clock_offset_seconds: f64,
+    node_id_external: &str,
+    notification_email_list: &str,
+    node_checkin_timestamp: &DateTime<Utc>,
+    dbconnection: &impl GenericClient,
+    outbox: &mut Outbox,
+    system_health_config: &web::Data<SystemHealthConfig>,
+) {
+    system_health::node_condition_check(
+        node_id_db,
+        incident_functions::INCIDENT_TYPE_CLOCK_SKEW,
+        Some(clock_skew_exceeded(clock_offset_seconds, system_health_config.clock_skew_warning_seconds)),
+        &format!("Clock skew above {} seconds", system_health_config.clock_skew_warning_seconds),
+        &format!("Device clock offset: {} seconds", clock_offset_seconds),
+        &Some(clock_offset_seconds),
+        node_id_external,
+        notification_email_list,
+        node_checkin_timestamp,
+        dbconnection,
+        outbox,
+    ).await;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SensorValue;
+
+    #[test]
+    fn offset_and_threshold() {
+        let server = Utc::now();
+
+        assert_eq!(clock_offset_seconds(&(server + Duration::seconds(90)), &server), 90.0);
+        assert_eq!(clock_offset_seconds(&(server - Duration::milliseconds(1500)), &server), -1.5);
+        assert!(clock_skew_exceeded(-121.0, 120.0));
+        assert!(!clock_skew_exceeded(120.0, 120.0));
+    }
+
+    #[test]
+    fn reading_timestamps_moved_to_server_time() {
+        let server = Utc::now();
+        let reading = SensorData {
+            id: "28-000001".to_string(),
+            sensor_name: "temperature".to_string(),
+            value: SensorValue::Number(21.5),
+            unit: None,
+            timestamp: Some(server + Duration::seconds(3600)),
+        };
+        let sensor_data = Some(vec![reading.clone()]);
+
+        let corrected = corrected_sensor_data(&sensor_data, Some(3600.0)).unwrap();
+        assert_eq!(corrected[0].timestamp, Some(server));
+        assert_eq!(corrected_sensor_data(&sensor_data, None).unwrap()[0].timestamp, Some(server + Duration::seconds(3600)));
+
+        let sensor_data = Some(vec![SensorData { timestamp: Some(DateTime::<Utc>::MIN_UTC), ..reading }]);
+        assert_eq!(corrected_sensor_data(&sensor_data, Some(3600.0)).unwrap()[0].timestamp, Some(DateTime::<Utc>::MIN_UTC));
+        assert_eq!(corrected_sensor_data(&sensor_data, Some(f64::MAX)).unwrap()[0].timestamp, Some(DateTime::<Utc>::MIN_UTC));
+    }
+}
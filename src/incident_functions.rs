@@ -0,0 +1,150 @@
+use crate::models::{Incident, IncidentConfig};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio_pg_mapper::FromTokioPostgresRow;
+
+use log::debug;
+
+pub const INCIDENT_TYPE_NODE_OFFLINE: &str = "node_offline";
+pub const INCIDENT_TYPE_SENSOR_TRIGGER: &str = "sensor_trigger";
+pub const INCIDENT_TYPE_SENSOR_MISSING: &str = "sensor_missing";
+pub const INCIDENT_TYPE_DISK_FULL: &str = "disk_full";
+pub const INCIDENT_TYPE_CPU_THROTTLED: &str = "cpu_throttled";
+// a reboot is an event: the incident is opened and resolved at once
+pub const INCIDENT_TYPE_NODE_REBOOTED: &str = "node_rebooted";
+pub const INCIDENT_TYPE_CLOCK_SKEW: &str = "clock_skew";
+
+type HmacSha256 = Hmac<Sha256>;
+
+
+pub async fn find_open_incident(
+    dbconnection: &impl GenericClient,
+    incident_type: &str,
+    node_id_db: &i32,
+    sensor_triggers_id: &Option<i32>,
+) -> Option<Incident> {
+    let stmt_open_incident = dbconnection.prepare_cached("SELECT id, incident_type, node_id, sensor_triggers_id, opened_at, acknowledged_at, acknowledged_by, resolved_at, last_value
+	FROM incidents WHERE incident_type = $1 AND node_id = $2 AND sensor_triggers_id IS NOT DISTINCT FROM $3 AND resolved_at IS NULL
+	ORDER BY opened_at DESC LIMIT 1;").await.unwrap();
+    let rows = dbconnection.query(&stmt_open_incident, &[&incident_type, node_id_db, sensor_triggers_id]).await.unwrap();
+
+    rows.into_iter().next().map(|row| Incident::from_row(row).unwrap())
+}
+
+pub async fn open_incident(
+    dbconnection: &impl GenericClient,
+    incident_type: &str,
+    node_id_db: &i32,
+    sensor_triggers_id: &Option<i32>,
+    opened_at: &DateTime<Utc>,
+    last_value: &Option<f64>,
+) -> Result<Incident, tokio_postgres::Error> {
+    debug!("Opening incident type={} node_id={} sensor_triggers_id={:?}", incident_type, node_id_db, sensor_triggers_id);
+
+    let stmt_incident_insert = dbconnection.prepare_cached("INSERT INTO incidents(
+	id, incident_type, node_id, sensor_triggers_id, opened_at, last_value)
+	VALUES (DEFAULT, $1, $2, $3, $4, $5)
+	RETURNING id, incident_type, node_id, sensor_triggers_id, opened_at, acknowledged_at, acknowledged_by, resolved_at, last_value;").await?;
+    let row = dbconnection.query_one(&stmt_incident_insert, &[&incident_type, node_id_db, sensor_triggers_id, opened_at, last_value]).await?;
+
+    Ok(Incident::from_row(row).unwrap())
+}
+
+pub async fn update_incident_last_value(
+    dbconnection: &impl GenericClient,
+    incident_id: &i32,
+    last_value: &Option<f64>,
+) {
+    let stmt_last_value_update = dbconnection.prepare_cached("UPDATE incidents SET last_value = $2 WHERE id = $1;").await.unwrap();
+    let _result = dbconnection.query(&stmt_last_value_update, &[incident_id, last_value]).await.unwrap();
+}
+
+pub async fn resolve_incident(
+    dbconnection: &impl GenericClient,
+    incident_id: &i32,
+    resolved_at: &DateTime<Utc>,
+    last_value: &Option<f64>,
+) {
+    debug!("Resolving incident id={}", incident_id);
+
+    let stmt_incident_resolve = dbconnection.prepare_cached("UPDATE incidents SET resolved_at = $2, last_value = COALESCE($3, last_value) WHERE id = $1;").await.unwrap();
+    let _result = dbconnection.query(&stmt_incident_resolve, &[incident_id, resolved_at, last_value]).await.unwrap();
+}
+
+/// Marks the incident as acknowledged. Returns false when the incident does not exist
+/// or was already acknowledged earlier.
+pub async fn acknowledge_incident(
+    dbconnection: &impl GenericClient,
+    incident_id: &i32,
+    acknowledged_by: &str,
+    acknowledged_at: &DateTime<Utc>,
+) -> bool {
+    let stmt_incident_acknowledge = dbconnection.prepare_cached("UPDATE incidents SET acknowledged_at = $2, acknowledged_by = $3 WHERE id = $1 AND acknowledged_at IS NULL;").await.unwrap();
+    let updated = dbconnection.execute(&stmt_incident_acknowledge, &[incident_id, acknowledged_at, &acknowledged_by]).await.unwrap();
+
+    updated == 1
+}
+
+
+/// HMAC-SHA256 over "<incident id>:<recipient>", hex encoded.
+pub fn sign_acknowledge_token(
+    incident_id: &i32,
+    acknowledged_by: &str,
+    incident_config: &IncidentConfig,
+) -> String {
+    let mut mac = HmacSha256::new_from_slice(incident_config.ack_secret.as_bytes()).unwrap();
+    mac.update(format!("{}:{}", incident_id, acknowledged_by).as_bytes());
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn verify_acknowledge_token(
+    incident_id: &i32,
+    acknowledged_by: &str,
+    token: &str,
+    incident_config: &IncidentConfig,
+) -> bool {
+    let token_bytes: Option<Vec<u8>> = (0..token.len())
+        .step_by(2)
+        .map(|i| token.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect();
+
+    match token_bytes {
+        Some(token_bytes) => {
+            let mut mac = HmacSha256::new_from_slice(incident_config.ack_secret.as_bytes()).unwrap();
+            mac.update(format!("{}:{}", incident_id, acknowledged_by).as_bytes());
+            mac.verify_slice(&token_bytes).is_ok()
+        }
+        None => false,
+    }
+}
+
+pub fn acknowledge_link(
+    incident_id: &i32,
+    acknowledged_by: &str,
+    incident_config: &IncidentConfig,
+) -> String {
+    format!(
+        "{}/incident/{}/acknowledge?by={}&token={}",
+        incident_config.public_base_url.trim_end_matches('/'),
+        incident_id,
+        url_encode(acknowledged_by),
+        sign_acknowledge_token(incident_id, acknowledged_by, incident_config),
+    )
+}
+
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
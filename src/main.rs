@@ -35,105 +35,379 @@ mod errors {
 
 mod handlers {
     use actix_web::{web, Error, HttpResponse};
-    use deadpool_postgres::{ Pool};
+    use deadpool_postgres::{ GenericClient, Pool};
     use log::debug;
     use log::error;
     use log::info;
     use crate::send_email;
     use crate::node_sensor_functions;
+    use crate::incident_functions;
+    use crate::uptime_report;
+    use crate::sensor_history;
+    use crate::sensor_registry;
+    use crate::system_health;
+    use crate::batch_checkin;
+    use crate::clock_skew;
+    use crate::checkin_validation;
+    use crate::node_registration;
+    use crate::checkin_idempotency;
+    use crate::alert_sweep;
+    use crate::notification_outbox::Outbox;
+    use crate::metrics;
+    use crate::readiness;
+
+    use chrono::{DateTime, Utc};
+
+    use crate::{ models::CheckinData,models::Email,models::TelegramConfig,models::IncidentConfig,models::IncidentAcknowledgeQuery,models::UptimeReportQuery,models::ReportConfig,models::NewSensorTrigger,models::SensorTrigger,models::ErrorResponse,models::SensorListQuery,models::SensorLabelUpdate,models::NodeListQuery,models::SystemHealthConfig,models::BatchCheckinData,models::BatchCheckinResult,models::ValidationErrorResponse,models::AdminQuery,models::AdminConfig,models::NodeApproval,models::ReadinessConfig,models::ReadinessReport,models::SensorHistoryConfig};
+    use crate::rate_limit::RateLimiter;
+
+    async fn find_api_key_id(client: &impl GenericClient, api_key: &str) -> Option<i32> {
+        let stmt = client.prepare_cached("SELECT id, api_key	FROM api_keys where api_key = $1").await.unwrap();
+        let rows = client.query(&stmt, &[&api_key] ).await.unwrap();
+        rows.first().map(|row| row.get(0))
+    }
 
-    use chrono::{DateTime, Duration, Utc};
+    fn too_many_requests(retry_after_seconds: u64) -> HttpResponse {
+        HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after_seconds.to_string()))
+            .json(ErrorResponse { error: format!("rate limit exceeded. Retry after {} seconds", retry_after_seconds) })
+    }
 
-    use crate::{ models::CheckinData,models::Nodes,models::Email,models::TelegramConfig};
+    fn is_admin(admin_config: &AdminConfig, admin_key: &str) -> bool {
+        !admin_config.admin_api_key.is_empty() && admin_config.admin_api_key == admin_key
+    }
 
     pub async fn status_check( ) -> &'static str {
         "Remote-pi-monitor has started!"
     }
 
+    /// Liveness: the process answers. Dependencies are checked by /readyz, a database outage must not restart the server.
+    pub async fn liveness_check( ) -> Result<HttpResponse, Error> {
+        Ok(HttpResponse::Ok().json(ReadinessReport { status: readiness::STATUS_OK.to_string(), components: Vec::new() }))
+    }
+
+    /// Readiness: 503 while a component is failing (database, pool), 200 when ok or degraded (alert sweep, notification backlog).
+    pub async fn readiness_check (
+        db_pool: web::Data<Pool>,
+        readiness_config: web::Data<ReadinessConfig>,
+    ) -> Result<HttpResponse, Error>
+    {
+        let report = readiness::readiness_report(&db_pool, &readiness_config, &Utc::now()).await;
+
+        if report.status == readiness::STATUS_FAILING {
+            error!("/readyz failing. components = {:?}", report.components);
+            return Ok(HttpResponse::ServiceUnavailable().json(report));
+        }
+        debug!("/readyz done. status = {}", report.status);
+
+        Ok(HttpResponse::Ok().json(report))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn checkin_node (
         checkin_data: web::Json<CheckinData>,
         db_pool: web::Data<Pool>,
         email_config: web::Data<Email>,
         telegram_config: web::Data<TelegramConfig>,
+        incident_config: web::Data<IncidentConfig>,
+        system_health_config: web::Data<SystemHealthConfig>,
+        admin_config: web::Data<AdminConfig>,
+        rate_limiter: web::Data<RateLimiter>,
     ) -> Result<HttpResponse, Error> {
 
         debug!(
         "checkin_data: API-key={} node_id={}",
         checkin_data.api_key, checkin_data.node_id
         );
+
+        if let Err(retry_after_seconds) = rate_limiter.check(&checkin_data.api_key, Some(&checkin_data.node_id)) {
+            error!("Rate limit exceeded. api_key = {} node_id = {}", checkin_data.api_key, checkin_data.node_id);
+            return Ok(too_many_requests(retry_after_seconds));
+        }
+        let validation_errors = checkin_validation::validate_checkin(&checkin_data, "");
+        if !validation_errors.is_empty() {
+            error!("Invalid checkin. node_id = {} errors = {}", checkin_data.node_id, validation_errors.len());
+            return Ok(HttpResponse::UnprocessableEntity().json(ValidationErrorResponse { errors: validation_errors }));
+        }
+
         let mut log_status_message = "".to_string();
-        let mut status_message;
+        let status_message;
+
+        let mut client = db_pool.get().await.unwrap();
+        match find_api_key_id(&client, &checkin_data.api_key).await {
+            None => {
+                error!("API key not found. api_key = {} " , checkin_data.api_key);
+                metrics::increment(metrics::UNKNOWN_API_KEY_TOTAL, &[]);
+                status_message = format!("api_key = {} is not found", checkin_data.api_key);
+                log_status_message.push_str(&status_message );
+            }
+            Some(api_key_id) => { // API key is found. Continue with node checkin
+                status_message = format!("api_key_id = {}", checkin_data.api_key);
+                log_status_message.push_str(&status_message );
 
-        let client = db_pool.get().await.unwrap();
-        let stmt = client.prepare_cached("SELECT id, api_key	FROM remote_pi_monitor.api_keys where api_key = $1").await.unwrap();
-        let rows = client.query(&stmt, &[&checkin_data.api_key] ).await.unwrap();
-        if rows.is_empty() {
-            error!("API key not found. api_key = {} " , checkin_data.api_key);
-            status_message = format!("api_key = {} is not found", checkin_data.api_key);
-            log_status_message.push_str(&status_message );
+                // notifications are sent once the checkin is committed
+                let mut outbox = Outbox::default();
+                let transaction = client.transaction().await.unwrap();
+                let result = process_node_checkin(
+                    &transaction,
+                    &checkin_data,
+                    &api_key_id,
+                    &Utc::now(),
+                    &system_health_config,
+                    &admin_config,
+                    &mut outbox,
+                ).await;
+                log_status_message.push_str(&result.status);
+                if !result.accepted {
+                    info!("/checkin rejected. {} ",log_status_message );
+                    return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: result.status }));
+                }
+                transaction.commit().await.unwrap();
+                metrics::increment(metrics::CHECKINS_TOTAL, &[("api_key_id", &api_key_id.to_string()), ("node_id", &checkin_data.node_id)]);
+                outbox.send(&email_config, &telegram_config, &incident_config).await;
+            }
         }
-        else { // API key is found. Continue with node checkin
-            let api_key_id: i32 = rows[0].get( 0);
-            status_message = format!("api_key_id = {}", checkin_data.api_key);
-            log_status_message.push_str(&status_message );
 
-            // find node in nodes table
-            let stmt_nodes = client.prepare_cached("SELECT id, node_id_external, fk_api_key_id, monitoring_enabled, last_checkin_timestamp, notification_email_list, offline_notification_sent
-	FROM remote_pi_monitor.nodes where fk_api_key_id= $1 AND node_id_external = $2").await.unwrap();
-            let rows = client.query(&stmt_nodes, &[&api_key_id,&checkin_data.node_id] ).await.unwrap();
-            if rows.is_empty() { // node ID is not found. Needs to be added to DB
-                debug!("Node id = {} not found. Adding new node to db" , &checkin_data.node_id);
+        info!("/checkin done. {} ",log_status_message );
 
-                let stmt_node_insert = client.prepare_cached("INSERT INTO remote_pi_monitor.nodes(
-	id, node_id_external, fk_api_key_id, monitoring_enabled, last_checkin_timestamp, notification_email_list, offline_notification_sent)
-	VALUES (DEFAULT, $1, $2, DEFAULT, now(), '', DEFAULT);").await.unwrap();
-                let _rows = client.query(&stmt_node_insert, &[&checkin_data.node_id, &api_key_id] ).await.unwrap();
+        Ok(HttpResponse::Ok().json(checkin_data))
+    }
 
-                status_message = format!(" node id = {} added to db", &checkin_data.node_id);
-                log_status_message.push_str(&status_message );
+    /// Gateways relay checkins of several nodes and nodes send buffered readings in one request.
+    /// A checkin over the node rate limit is not accepted, the others are processed in one transaction.
+    /// Backfilled readings are stored without running triggers.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn checkin_batch (
+        batch_checkin_data: web::Json<BatchCheckinData>,
+        db_pool: web::Data<Pool>,
+        email_config: web::Data<Email>,
+        telegram_config: web::Data<TelegramConfig>,
+        incident_config: web::Data<IncidentConfig>,
+        system_health_config: web::Data<SystemHealthConfig>,
+        admin_config: web::Data<AdminConfig>,
+        rate_limiter: web::Data<RateLimiter>,
+    ) -> Result<HttpResponse, Error> {
+        // one token per api key and request, a gateway relays many nodes
+        let mut api_keys: Vec<&str> = batch_checkin_data.checkins.iter().map(|x| x.api_key.as_str()).collect();
+        api_keys.sort();
+        api_keys.dedup();
+        for api_key in api_keys {
+            if let Err(retry_after_seconds) = rate_limiter.check(api_key, None) {
+                error!("Rate limit exceeded. api_key = {}", api_key);
+                return Ok(too_many_requests(retry_after_seconds));
+            }
+        }
 
-            } else {  // node is found. Need to update checkin timestamp and send online notification in case it was offline
-                debug!("Node id = {} is found. Updating checkin timestamp" , &checkin_data.node_id);
+        let validation_errors = checkin_validation::validate_batch_checkin(&batch_checkin_data.checkins);
+        if !validation_errors.is_empty() {
+            error!("Invalid batch checkin. errors = {}", validation_errors.len());
+            return Ok(HttpResponse::UnprocessableEntity().json(ValidationErrorResponse { errors: validation_errors }));
+        }
 
-                // update checkin timestamp
-                let node_id_db: i32 = rows[0].get( 0);
-                debug!("nodes.id = {}" , &node_id_db);
+        let mut client = db_pool.get().await.unwrap();
+        let mut transaction = client.transaction().await.unwrap();
+        let checkin_timestamp = Utc::now();
+        let mut outbox = Outbox::default();
+
+        let mut results: Vec<BatchCheckinResult> = Vec::new();
+        let mut accepted_checkins: Vec<(i32, String)> = Vec::new();
+        for checkin_data in &batch_checkin_data.checkins {
+            // the node bucket of /checkin, one gateway can not flood the history of a node
+            if let Err(retry_after_seconds) = rate_limiter.check_node(&checkin_data.api_key, &checkin_data.node_id) {
+                error!("Rate limit exceeded. api_key = {} node_id = {}", checkin_data.api_key, checkin_data.node_id);
+                results.push(BatchCheckinResult {
+                    node_id: checkin_data.node_id.clone(),
+                    accepted: false,
+                    status: format!("rate limit exceeded. Retry after {} seconds", retry_after_seconds),
+                    readings_stored: 0,
+                    readings_backfilled: 0,
+                });
+                continue;
+            }
+            let result = match find_api_key_id(&transaction, &checkin_data.api_key).await {
+                Some(api_key_id) => {
+                    // savepoint per checkin, a rejected checkin leaves nothing behind
+                    let checkin_transaction = transaction.transaction().await.unwrap();
+                    let result = process_node_checkin(
+                        &checkin_transaction,
+                        checkin_data,
+                        &api_key_id,
+                        &checkin_timestamp,
+                        &system_health_config,
+                        &admin_config,
+                        &mut outbox,
+                    ).await;
+                    if result.accepted {
+                        checkin_transaction.commit().await.unwrap();
+                        accepted_checkins.push((api_key_id, checkin_data.node_id.clone()));
+                    }
+                    result
+                }
+                None => {
+                    error!("API key not found. api_key = {} " , checkin_data.api_key);
+                    metrics::increment(metrics::UNKNOWN_API_KEY_TOTAL, &[]);
+                    BatchCheckinResult {
+                        node_id: checkin_data.node_id.clone(),
+                        accepted: false,
+                        status: format!("api_key = {} is not found", checkin_data.api_key),
+                        readings_stored: 0,
+                        readings_backfilled: 0,
+                    }
+                }
+            };
+            results.push(result);
+        }
 
-                let email_notification_list: String =  rows[0].get( 5);
+        transaction.commit().await.unwrap();
+        for (api_key_id, node_id) in &accepted_checkins {
+            metrics::increment(metrics::CHECKINS_TOTAL, &[("api_key_id", &api_key_id.to_string()), ("node_id", node_id)]);
+        }
+        outbox.send(&email_config, &telegram_config, &incident_config).await;
 
+        info!("/checkin/batch done. checkins = {} readings = {}", results.len(), results.iter().map(|x| x.readings_stored).sum::<usize>());
 
-                let node_checkin_timestamp = Utc::now();
+        Ok(HttpResponse::Ok().json(results))
+    }
 
-                let stmt_timestamp_update = client.prepare_cached("UPDATE remote_pi_monitor.nodes SET last_checkin_timestamp= $2, offline_notification_sent=false WHERE id= $1;").await.unwrap();
-                let _rows = client.query(&stmt_timestamp_update, &[&node_id_db,&node_checkin_timestamp] ).await.unwrap();
+    /// Checkin of one node: registers new nodes, updates the checkin timestamp, runs the offline / trigger / system health
+    /// checks with the live readings and stores readings, sensors and the system snapshot.
+    /// Runs in the transaction of the caller, notifications are queued in the outbox.
+    async fn process_node_checkin(
+        client: &impl GenericClient,
+        checkin_data: &CheckinData,
+        api_key_id: &i32,
+        checkin_timestamp: &DateTime<Utc>,
+        system_health_config: &web::Data<SystemHealthConfig>,
+        admin_config: &web::Data<AdminConfig>,
+        outbox: &mut Outbox,
+    ) -> BatchCheckinResult {
+        let mut log_status_message = "".to_string();
+        let status_message;
+
+        // a retried checkin is answered like the first one, without storing or alerting again
+        if let Some(checkin_id) = &checkin_data.checkin_id {
+            if !checkin_idempotency::claim_checkin_id(client, api_key_id, &checkin_data.node_id, checkin_id, checkin_timestamp).await {
+                info!("Checkin already processed. node_id = {} checkin_id = {}", checkin_data.node_id, checkin_id);
+                return BatchCheckinResult {
+                    node_id: checkin_data.node_id.clone(),
+                    accepted: true,
+                    status: format!("checkin_id = {} is already processed", checkin_id),
+                    readings_stored: 0,
+                    readings_backfilled: 0,
+                };
+            }
+        }
 
-                status_message = format!(" nodes.id = {} nodes.node_id_external = {}", &node_id_db, &checkin_data.node_id);
-                log_status_message.push_str(&status_message );
+        // device clock offset, reading timestamps are moved to server time with it
+        let clock_offset_seconds = checkin_data.device_timestamp.map(|x| clock_skew::clock_offset_seconds(&x, checkin_timestamp));
+        let sensor_data = clock_skew::corrected_sensor_data(&checkin_data.sensor_data, clock_offset_seconds);
+
+        // buffered readings update last seen, but only live readings are validated
+        let node_checkin_timestamp = batch_checkin::newest_reading_timestamp(&sensor_data, checkin_timestamp);
+        let live_checkin = batch_checkin::is_live_checkin(&sensor_data, checkin_timestamp);
+        let live_sensor_data = batch_checkin::live_sensor_data(&sensor_data, checkin_timestamp);
+        let readings_stored = sensor_data.as_ref().map_or(0, |x| x.len());
+        let readings_backfilled = readings_stored - live_sensor_data.as_ref().map_or(0, |x| x.len());
+
+        // one upsert, concurrent first checkins of the same node can not create duplicates
+        let node = node_registration::upsert_node(client, api_key_id, &checkin_data.node_id, &node_checkin_timestamp).await;
+        let node_id_db = node.node_id_db;
+        if node.inserted { // node ID was not found. Registered according to the api key policy
+            debug!("Node id = {} not found. Registering new node according to the api key policy" , &checkin_data.node_id);
+
+            let approval_status = match node_registration::apply_new_node_policy(client, api_key_id, &node_id_db).await {
+                Some(x) => x,
+                None => {
+                    error!("Unknown node rejected. api_key_id = {} node_id = {}", api_key_id, checkin_data.node_id);
+                    return BatchCheckinResult {
+                        node_id: checkin_data.node_id.clone(),
+                        accepted: false,
+                        status: format!("node_id = {} is not registered for this api_key", checkin_data.node_id),
+                        readings_stored: 0,
+                        readings_backfilled: 0,
+                    };
+                }
+            };
+            if let Some(clock_offset_seconds) = &clock_offset_seconds {
+                clock_skew::record_clock_offset(client, &node_id_db, clock_offset_seconds, checkin_timestamp).await;
+            }
 
-                // send notification in case node was offline before
-                let node_monitoring_enabled: bool= rows[0].get( 3);
-                let node_offline_notification_sent: bool= rows[0].get( 6);
-                debug!("nodes.monitoring_enabled = {} nodes.node_offline_notification_sent = {}" , &node_monitoring_enabled, &node_offline_notification_sent);
-
-                if node_monitoring_enabled && node_offline_notification_sent {
-                    // node was offline and is now online -> send notification
-                    debug!("Sending node online notification");
-
-                    let node_last_checkin_timestamp:  DateTime<Utc> = rows[0].get( 4);
-
-                    debug!("email_notification_list = {:?}", email_notification_list);
-                    if email_notification_list != "" {
-                        send_email::send_node_online_notification_email(
-                            &checkin_data.node_id,
-                            &email_notification_list,
-                            &node_checkin_timestamp,
-                            &node_last_checkin_timestamp,
-                            &email_config,
-                            &telegram_config,
-                        ).await;
-                    }
-                    else {
-                        error!("Can not send notification. recipient list not defined");
+            uptime_report::record_node_transition(client, &node_id_db, uptime_report::NODE_STATE_ONLINE, &node_checkin_timestamp).await;
+            sensor_history::store_sensor_readings(client, &node_id_db, &sensor_data, checkin_timestamp).await;
+            sensor_registry::register_sensors(client, &node_id_db, &sensor_data, checkin_timestamp).await;
+            if let Some(system) = &checkin_data.system {
+                system_health::store_system_health(client, &node_id_db, system, checkin_timestamp).await;
+            }
+            node_registration::notify_admins_new_node(
+                &checkin_data.node_id,
+                api_key_id,
+                &approval_status,
+                &node_checkin_timestamp,
+                admin_config,
+                outbox,
+            );
+
+            status_message = format!(" node id = {} added to db ({})", &checkin_data.node_id, approval_status);
+            log_status_message.push_str(&status_message );
+
+        } else {  // node is found. Checkin timestamp is updated, send online notification in case it was offline
+            debug!("Node id = {} is found. nodes.id = {}" , &checkin_data.node_id, &node_id_db);
+
+            let email_notification_list = node.notification_email_list;
+
+            status_message = format!(" nodes.id = {} nodes.node_id_external = {}", &node_id_db, &checkin_data.node_id);
+            log_status_message.push_str(&status_message );
+
+            let node_monitoring_enabled = node.monitoring_enabled;
+            if let Some(clock_offset_seconds) = clock_offset_seconds {
+                clock_skew::record_clock_offset(client, &node_id_db, &clock_offset_seconds, checkin_timestamp).await;
+                if node_monitoring_enabled {
+                    clock_skew::clock_skew_check(
+                        &node_id_db,
+                        clock_offset_seconds,
+                        &checkin_data.node_id,
+                        &email_notification_list,
+                        checkin_timestamp,
+                        client,
+                        outbox,
+                        system_health_config,
+                    ).await;
+                }
+            }
+            if live_checkin {
+                // state history of the uptime report, kept for every node whether it is monitored or not
+                if uptime_report::latest_node_state(client, &node_id_db).await.as_deref() != Some(uptime_report::NODE_STATE_ONLINE) {
+                    uptime_report::record_node_transition(client, &node_id_db, uptime_report::NODE_STATE_ONLINE, checkin_timestamp).await;
+                }
+
+                // resolve offline incident and send notification in case node was offline before
+                let offline_incident = incident_functions::find_open_incident(
+                    client,
+                    incident_functions::INCIDENT_TYPE_NODE_OFFLINE,
+                    &node_id_db,
+                    &None,
+                ).await;
+                debug!("nodes.monitoring_enabled = {} offline incident open = {}" , &node_monitoring_enabled, offline_incident.is_some());
+
+                if let Some(incident) = offline_incident {
+                    incident_functions::resolve_incident(client, &incident.id, checkin_timestamp, &None).await;
+
+                    if node_monitoring_enabled {
+                        // node was offline and is now online -> send notification
+                        debug!("Sending node online notification");
+
+                        debug!("email_notification_list = {:?}", email_notification_list);
+                        if !email_notification_list.is_empty() {
+                            outbox.push(send_email::node_online_email(
+                                &checkin_data.node_id,
+                                &email_notification_list,
+                                checkin_timestamp,
+                                &incident.opened_at,
+                            ));
+                        }
+                        else {
+                            error!("Can not send notification. recipient list not defined");
+                        }
                     }
 
                 }
@@ -141,86 +415,396 @@ mod handlers {
                 // perform sensor data validation
                 node_sensor_functions::sensor_trigger_check(
                     &node_id_db,
-                    &checkin_data.sensor_data,
+                    &live_sensor_data,
                     &checkin_data.node_id,
                     &email_notification_list,
-                    &node_checkin_timestamp,
-                    &client,
-                    &email_config,
-                    &telegram_config,
+                    checkin_timestamp,
+                    client,
+                    outbox,
                 ).await;
-
             }
 
-
+            // stored after the trigger check so change triggers compare against the previous checkin
+            sensor_history::store_sensor_readings(client, &node_id_db, &sensor_data, checkin_timestamp).await;
+            sensor_registry::register_sensors(client, &node_id_db, &sensor_data, checkin_timestamp).await;
+
+            // built-in checks compare against the previous snapshot (reboot detection), so it is replaced afterwards
+            if let Some(system) = checkin_data.system.as_ref().filter(|_| live_checkin) {
+                if node_monitoring_enabled {
+                    let previous_system = system_health::latest_system_health(client, &node_id_db).await.map(|(_, x)| x);
+                    system_health::system_health_check(
+                        &node_id_db,
+                        system,
+                        &previous_system,
+                        &checkin_data.node_id,
+                        &email_notification_list,
+                        checkin_timestamp,
+                        client,
+                        outbox,
+                        system_health_config,
+                    ).await;
+                }
+                system_health::store_system_health(client, &node_id_db, system, checkin_timestamp).await;
+            }
 
         }
 
-        info!("/checkin done. {} ",log_status_message );
-
-        Ok(HttpResponse::Ok().json(checkin_data))
+        BatchCheckinResult {
+            node_id: checkin_data.node_id.clone(),
+            accepted: true,
+            status: log_status_message,
+            readings_stored,
+            readings_backfilled,
+        }
     }
 
     pub async fn alert_sender (
         db_pool: web::Data<Pool>,
         email_config: web::Data<Email>,
         telegram_config: web::Data<TelegramConfig>,
+        incident_config: web::Data<IncidentConfig>,
+        sensor_history_config: web::Data<SensorHistoryConfig>,
+    ) -> Result<HttpResponse, Error>
+    {
+        let client = db_pool.get().await.unwrap();
+
+        let result = alert_sweep::run_alert_sweep(&client, &email_config, &telegram_config, &incident_config, &sensor_history_config).await.unwrap();
+
+        info!("/alert-sender done. offline_nodes_count = {:?} expired_checkin_ids = {} expired_sensor_readings = {}.", result.offline_nodes_count, result.expired_checkin_ids, result.expired_sensor_readings );
+
+        Ok(HttpResponse::Ok().body("OK"))
+    }
+
+    /// Prometheus scrape endpoint. Counters are kept since startup, node / sensor gauges are read from the database
+    /// and left out while the database is not usable.
+    pub async fn metrics_report (
+        query: web::Query<AdminQuery>,
+        db_pool: web::Data<Pool>,
+        admin_config: web::Data<AdminConfig>,
+    ) -> Result<HttpResponse, Error>
+    {
+        if !is_admin(&admin_config, &query.admin_key) {
+            error!("Invalid admin key");
+            return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "admin_key is not valid".to_string() }));
+        }
+
+        let mut samples = metrics::process_samples();
+        samples.extend(metrics::pool_samples(&db_pool.status()));
+        match db_pool.get().await {
+            Ok(client) => match metrics::database_samples(&client).await {
+                Ok(database_samples) => samples.extend(database_samples),
+                Err(e) => error!("/metrics without database samples: {}", e),
+            },
+            Err(e) => error!("/metrics without database samples: {}", e),
+        }
+
+        debug!("/metrics done. samples = {}", samples.len());
+
+        Ok(HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(metrics::render(&samples)))
+    }
+
+    pub async fn create_sensor_trigger (
+        new_sensor_trigger: web::Json<NewSensorTrigger>,
+        db_pool: web::Data<Pool>,
     ) -> Result<HttpResponse, Error>
     {
         use tokio_pg_mapper::FromTokioPostgresRow;
 
+        if let Err(e) = node_sensor_functions::check_trigger_definition(&new_sensor_trigger) {
+            error!("Invalid trigger definition: {}", e);
+            return Ok(HttpResponse::UnprocessableEntity().json(ErrorResponse { error: e }));
+        }
+
+        let client = db_pool.get().await.unwrap();
+        let stmt_nodes = client.prepare_cached("SELECT n.id FROM nodes n JOIN api_keys k ON k.id = n.fk_api_key_id
+	WHERE k.api_key = $1 AND n.node_id_external = $2").await.unwrap();
+        let rows = client.query(&stmt_nodes, &[&new_sensor_trigger.api_key, &new_sensor_trigger.node_id] ).await.unwrap();
+        if rows.is_empty() {
+            error!("Node not found. api_key = {} node_id = {}", new_sensor_trigger.api_key, new_sensor_trigger.node_id);
+            return Ok(HttpResponse::NotFound().json(ErrorResponse { error: format!("node_id = {} is not found for this api_key", new_sensor_trigger.node_id) }));
+        }
+        let node_id_db: i32 = rows[0].get( 0);
+
+        let stmt_trigger_insert = client.prepare_cached("INSERT INTO sensor_triggers(
+	sensor_triggers_id, node_id, sensor_id, monitoring_enabled, validation_function, validation_parameter_1, validation_parameter_2, validation_parameter_text, validation_expression,
+	deadband, deadband_type, fail_after_count, fail_after_seconds, recover_after_count, missing_grace_seconds)
+	VALUES (DEFAULT, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+	RETURNING sensor_triggers_id, node_id, sensor_id, monitoring_enabled, validation_function, validation_parameter_1, validation_parameter_2, validation_parameter_text, validation_expression, deadband, deadband_type,
+	fail_after_count, fail_after_seconds, recover_after_count, consecutive_fail_count, consecutive_ok_count, fail_streak_started_at, missing_grace_seconds, missing_since;").await.unwrap();
+        let row = client.query_one(&stmt_trigger_insert, &[
+            &node_id_db,
+            &new_sensor_trigger.sensor_id,
+            &new_sensor_trigger.monitoring_enabled.unwrap_or(true),
+            &new_sensor_trigger.validation_function,
+            &new_sensor_trigger.validation_parameter_1,
+            &new_sensor_trigger.validation_parameter_2,
+            &new_sensor_trigger.validation_parameter_text,
+            &new_sensor_trigger.validation_expression,
+            &new_sensor_trigger.deadband.unwrap_or(0.05),
+            &new_sensor_trigger.deadband_type.clone().unwrap_or_else(|| node_sensor_functions::DEADBAND_TYPE_ABSOLUTE.to_string()),
+            &new_sensor_trigger.fail_after_count.unwrap_or(1),
+            &new_sensor_trigger.fail_after_seconds.unwrap_or(0),
+            &new_sensor_trigger.recover_after_count.unwrap_or(1),
+            &new_sensor_trigger.missing_grace_seconds.unwrap_or(0),
+        ] ).await.unwrap();
+        let sensor_trigger = SensorTrigger::from_row(row).unwrap();
+
+        info!("/triggers done. sensor_triggers_id = {} nodes.id = {}", sensor_trigger.sensor_triggers_id, node_id_db);
+
+        Ok(HttpResponse::Created().json(sensor_trigger))
+    }
+
+    pub async fn list_node_sensors (
+        query: web::Query<SensorListQuery>,
+        db_pool: web::Data<Pool>,
+    ) -> Result<HttpResponse, Error>
+    {
         let client = db_pool.get().await.unwrap();
+        let api_key_id = match find_api_key_id(&client, &query.api_key).await {
+            Some(x) => x,
+            None => {
+                error!("API key not found. api_key = {} " , query.api_key);
+                return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: format!("api_key = {} is not found", query.api_key) }));
+            }
+        };
 
-        let offline_select_timestamp =  Utc::now() - Duration::minutes(5);
-        debug!("selecting nodes with monitoring enabled and checkin time < than {:?}" , &offline_select_timestamp);
+        let sensors = sensor_registry::list_sensors(&client, &api_key_id, &query.node_id).await;
 
-        let stmt_offline_nodes_list = client.prepare_cached("SELECT id, node_id_external, fk_api_key_id, monitoring_enabled, last_checkin_timestamp, notification_email_list, offline_notification_sent \
-        FROM remote_pi_monitor.nodes where monitoring_enabled = true AND notification_email_list <> '' AND offline_notification_sent = false AND last_checkin_timestamp < $1;").await.unwrap();
-        let rows_offline_nodes_list = client.query(&stmt_offline_nodes_list, &[&offline_select_timestamp] ).await.unwrap();
+        info!("/sensors done. api_key_id = {} sensors = {}", api_key_id, sensors.len());
 
-        let offline_nodes_count = rows_offline_nodes_list.len();
+        Ok(HttpResponse::Ok().json(sensors))
+    }
 
-        for row_offline_nodes in rows_offline_nodes_list {
-            let offline_node = Nodes::from_row(row_offline_nodes).unwrap();
-            debug!("Offline node: id = {} last_checkin_timestamp= {:?}", &offline_node.id, &offline_node.last_checkin_timestamp);
+    pub async fn list_nodes (
+        query: web::Query<NodeListQuery>,
+        db_pool: web::Data<Pool>,
+    ) -> Result<HttpResponse, Error>
+    {
+        let client = db_pool.get().await.unwrap();
+        let api_key_id = match find_api_key_id(&client, &query.api_key).await {
+            Some(x) => x,
+            None => {
+                error!("API key not found. api_key = {} " , query.api_key);
+                return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: format!("api_key = {} is not found", query.api_key) }));
+            }
+        };
 
-            send_email::send_node_offline_notification_email(
-                &offline_node.node_id_external,
-                &offline_node.notification_email_list,
-                &offline_node.last_checkin_timestamp,
-                &email_config,
-                &telegram_config,
-            ).await;
+        let nodes = system_health::list_nodes(&client, &api_key_id, &query.node_id).await.unwrap();
 
-            // udpate status in db
-            let stmt_node_status_update = client.prepare_cached("UPDATE remote_pi_monitor.nodes SET offline_notification_sent = true WHERE id = $1").await.unwrap();
-            let _update = client.query(&stmt_node_status_update, &[&offline_node.id] ).await.unwrap();
+        info!("/nodes done. api_key_id = {} nodes = {}", api_key_id, nodes.len());
 
+        Ok(HttpResponse::Ok().json(nodes))
+    }
 
+    pub async fn update_sensor_label (
+        sensor_label_update: web::Json<SensorLabelUpdate>,
+        db_pool: web::Data<Pool>,
+    ) -> Result<HttpResponse, Error>
+    {
+        let client = db_pool.get().await.unwrap();
+        let api_key_id = match find_api_key_id(&client, &sensor_label_update.api_key).await {
+            Some(x) => x,
+            None => {
+                error!("API key not found. api_key = {} " , sensor_label_update.api_key);
+                return Ok(HttpResponse::Unauthorized().json(ErrorResponse { error: format!("api_key = {} is not found", sensor_label_update.api_key) }));
+            }
+        };
+
+        let updated = sensor_registry::update_sensor_label(
+            &client,
+            &api_key_id,
+            &sensor_label_update.node_id,
+            &sensor_label_update.sensor_id,
+            &sensor_label_update.label,
+        ).await;
+
+        info!("/sensors/label done. node_id = {} sensor_id = {} updated = {}", sensor_label_update.node_id, sensor_label_update.sensor_id, updated);
+
+        if updated {
+            let sensors = sensor_registry::list_sensors(&client, &api_key_id, &Some(sensor_label_update.node_id.clone())).await;
+            Ok(HttpResponse::Ok().json(sensors.into_iter().find(|x| x.sensor_id == sensor_label_update.sensor_id)))
+        } else {
+            Ok(HttpResponse::NotFound().json(ErrorResponse { error: format!("sensor_id = {} is not registered for node_id = {}", sensor_label_update.sensor_id, sensor_label_update.node_id) }))
         }
+    }
 
-        info!("/alert-sender done. offline_nodes_count = {:?}.", offline_nodes_count );
+    pub async fn node_uptime_report (
+        query: web::Query<UptimeReportQuery>,
+        db_pool: web::Data<Pool>,
+    ) -> Result<HttpResponse, Error>
+    {
+        let client = db_pool.get().await.unwrap();
+        let api_key_id = match find_api_key_id(&client, &query.api_key).await {
+            Some(x) => x,
+            None => {
+                error!("API key not found. api_key = {} " , query.api_key);
+                return Ok(HttpResponse::Unauthorized().body(format!("api_key = {} is not found", query.api_key)));
+            }
+        };
+
+        let (previous_month_from, previous_month_to) = uptime_report::previous_month_period(&Utc::now());
+        let from = query.from.unwrap_or(previous_month_from);
+        let to = query.to.unwrap_or(previous_month_to);
+
+        let report = uptime_report::build_uptime_report(&client, &Some(api_key_id), &from, &to).await;
+
+        info!("/reports/uptime done. api_key_id = {} nodes = {}", api_key_id, report.nodes.len());
+
+        Ok(HttpResponse::Ok().json(report))
+    }
+
+    pub async fn report_sender (
+        db_pool: web::Data<Pool>,
+        email_config: web::Data<Email>,
+        telegram_config: web::Data<TelegramConfig>,
+        report_config: web::Data<ReportConfig>,
+    ) -> Result<HttpResponse, Error>
+    {
+        if report_config.email_list.is_empty() {
+            error!("Can not send uptime report. recipient list not defined");
+            return Ok(HttpResponse::Ok().body("OK"));
+        }
+
+        let client = db_pool.get().await.unwrap();
+
+        // called periodically; the report for the previous month is sent only once
+        let (from, to) = uptime_report::previous_month_period(&Utc::now());
+        let mut report_sent = uptime_report::claim_monthly_report(&client, &from).await;
+        if report_sent {
+            let report = uptime_report::build_uptime_report(&client, &None, &from, &to).await;
+            if let Err(e) = send_email::send_uptime_report_email(&report_config.email_list, &report, &email_config, &telegram_config).await {
+                error!("Uptime report not sent, it is retried on the next call: {}", e);
+                uptime_report::release_monthly_report(&client, &from).await;
+                report_sent = false;
+            }
+        }
+
+        info!("/report-sender done. report_sent = {:?}.", report_sent);
 
         Ok(HttpResponse::Ok().body("OK"))
     }
 
+    pub async fn rate_limit_report (
+        query: web::Query<AdminQuery>,
+        admin_config: web::Data<AdminConfig>,
+        rate_limiter: web::Data<RateLimiter>,
+    ) -> Result<HttpResponse, Error>
+    {
+        if !is_admin(&admin_config, &query.admin_key) {
+            error!("Invalid admin key");
+            return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "admin_key is not valid".to_string() }));
+        }
+
+        let rejected_requests = rate_limiter.rejected_requests();
+
+        info!("/admin/rate-limits done. api keys with rejected requests = {}", rejected_requests.len());
+
+        Ok(HttpResponse::Ok().json(rejected_requests))
+    }
+
+    pub async fn approve_node (
+        node_approval: web::Json<NodeApproval>,
+        db_pool: web::Data<Pool>,
+        admin_config: web::Data<AdminConfig>,
+    ) -> Result<HttpResponse, Error>
+    {
+        if !is_admin(&admin_config, &node_approval.admin_key) {
+            error!("Invalid admin key");
+            return Ok(HttpResponse::Forbidden().json(ErrorResponse { error: "admin_key is not valid".to_string() }));
+        }
+
+        let client = db_pool.get().await.unwrap();
+        let approved = node_registration::approve_node(&client, &node_approval.api_key, &node_approval.node_id).await;
+
+        info!("/admin/nodes/approve done. node_id = {} approved = {}", node_approval.node_id, approved);
+
+        if approved {
+            Ok(HttpResponse::Ok().body(format!("node_id = {} approved, monitoring enabled", node_approval.node_id)))
+        } else {
+            Ok(HttpResponse::NotFound().json(ErrorResponse { error: format!("node_id = {} is not pending approval for this api_key", node_approval.node_id) }))
+        }
+    }
+
+    pub async fn acknowledge_incident (
+        incident_id: web::Path<i32>,
+        query: web::Query<IncidentAcknowledgeQuery>,
+        db_pool: web::Data<Pool>,
+        incident_config: web::Data<IncidentConfig>,
+    ) -> Result<HttpResponse, Error>
+    {
+        let incident_id = incident_id.into_inner();
+
+        if !incident_functions::verify_acknowledge_token(&incident_id, &query.by, &query.token, &incident_config) {
+            error!("Invalid acknowledge token. incident id = {} by = {}", incident_id, query.by);
+            return Ok(HttpResponse::Forbidden().body("Invalid acknowledge link"));
+        }
+
+        let client = db_pool.get().await.unwrap();
+        let acknowledged = incident_functions::acknowledge_incident(&client, &incident_id, &query.by, &Utc::now()).await;
+
+        info!("/incident/{}/acknowledge done. by = {} acknowledged = {}", incident_id, query.by, acknowledged);
+
+        if acknowledged {
+            Ok(HttpResponse::Ok().body(format!("Incident #{} acknowledged by {}", incident_id, query.by)))
+        } else {
+            Ok(HttpResponse::Ok().body(format!("Incident #{} was already acknowledged", incident_id)))
+        }
+    }
+
 }
 
 pub mod send_email;
 pub mod node_sensor_functions;
+pub mod incident_functions;
+pub mod uptime_report;
+pub mod sensor_history;
+pub mod trigger_expression;
+pub mod sensor_registry;
+pub mod system_health;
+pub mod batch_checkin;
+pub mod clock_skew;
+pub mod checkin_validation;
+pub mod rate_limit;
+pub mod node_registration;
+pub mod migrations;
+pub mod notification_outbox;
+pub mod checkin_idempotency;
+pub mod app_config;
+pub mod alert_sweep;
+pub mod node_export;
+pub mod cli;
+pub mod metrics;
+pub mod readiness;
 
 
 use actix_web::{ web, App, HttpServer};
 use dotenv::dotenv;
-use ::config::Config;
+use deadpool_postgres::Runtime;
 use tokio_postgres::NoTls;
 use handlers::status_check;
 use handlers::checkin_node;
+use handlers::checkin_batch;
 use handlers::alert_sender;
+use handlers::acknowledge_incident;
+use handlers::report_sender;
+use handlers::node_uptime_report;
+use handlers::create_sensor_trigger;
+use handlers::list_node_sensors;
+use handlers::update_sensor_label;
+use handlers::list_nodes;
+use handlers::rate_limit_report;
+use handlers::approve_node;
+use handlers::metrics_report;
+use handlers::liveness_check;
+use handlers::readiness_check;
 use env_logger::{Builder, Target};
-use log::{info};
+use log::{error, info, warn};
 use crate::models::TelegramConfig;
 use crate::models::Email;
+use crate::app_config::AppConfig;
+use crate::cli::{Cli, Command};
+use clap::Parser;
+use crate::rate_limit::RateLimiter;
 
 
 #[actix_web::main] // or #[tokio::main]
@@ -231,74 +815,109 @@ async fn main() -> std::io::Result<()> {
     builder.target(Target::Stdout);
     builder.init();
 
-/*  debug print env. values
-for (key, value) in std::env::vars() {
-    println!("{} = {}", key, value);
-}
-*/
-
-
-    let config_ = Config::builder()
-        .add_source(::config::Environment::default())
-        .build()
-        .unwrap();
-
-
- //   println!("config: {:?} ", config_);
-
-  
-    let email_config = Email {
-    smtp_server: config_.get("email_smtp_server").unwrap(),
-    username:  config_.get("email_username").unwrap(),
-    password: config_.get("email_password").unwrap(),
-};
-
-  // println!("email config: {:?} ", email_config);
-
+    let cli = Cli::parse();
+    let config_file = cli.config_file();
+    if let Some(Command::CheckConfig) = cli.command {
+        return cli::exit_on_error(cli::check_config(&config_file));
+    }
 
-    let telegram_config = TelegramConfig {
-        bot_token: config_.get("telegram_config_bot_token").unwrap(),
-        channel_id: config_.get("telegram_config_channel_id").unwrap(),
+    let app_config = match AppConfig::load(&config_file) {
+        Ok(x) => x,
+        Err(errors) => {
+            for e in &errors {
+                error!("Configuration: {}", e);
+            }
+            return Err(std::io::Error::other(format!("invalid configuration: {} setting(s) missing or invalid", errors.len())));
+        }
     };
+    for line in app_config.report() {
+        info!("Configuration: {}", line);
+    }
 
- // println!("tel config: {:?} ", telegram_config);
+    let email_config = app_config.email.clone();
+    let telegram_config = app_config.telegram.clone();
+    let incident_config = app_config.incident.clone();
+    let report_config = app_config.report.clone();
+    let system_health_config = app_config.system_health.clone();
+    let sensor_history_config = app_config.sensor_history.clone();
+    let admin_config = app_config.admin.clone();
+    let readiness_config = app_config.readiness.clone();
+    let server_addr = app_config.server_addr.clone();
 
-  let server_addr:String = config_.get("server_addr").unwrap();
- 
- let pgconfig = deadpool_postgres::Config {
-        user:config_.get("pg_user").unwrap(),
-        password:config_.get("pg_password").unwrap(),
-        host:config_.get("pg_host").unwrap(),
-        port:config_.get("pg_port").unwrap(),
-        dbname:config_.get("pg_dbname").unwrap(),
-        ..Default::default()
-       // pool.max_size:config_.get("pg_pool_max_size").unwrap(),
- };
+    let telegram_config_parameter: TelegramConfig = telegram_config.clone();
+    let email_config_parameter: Email = email_config.clone();
 
-    //let config: AppConfig = config_.try_deserialize().unwrap();
-    info!("Email configuration: {} {}",email_config.smtp_server,email_config.username ) ;
+    let pool = app_config.pg_pool_config().create_pool(Some(Runtime::Tokio1), NoTls).unwrap();
 
-    let telegram_config_parameter: TelegramConfig = telegram_config.clone();
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            if app_config.database.migrate_on_startup {
+                cli::migrate(&app_config, &pool).await?;
+            }
+        }
+        // `migrate` only applies pending migrations and exits
+        Command::Migrate => return cli::exit_on_error(cli::migrate(&app_config, &pool).await),
+        command => return cli::exit_on_error(cli::run_command(command, &app_config, &pool).await),
+    }
 
-    let pool = pgconfig.create_pool(None, NoTls).unwrap();
+    // shared by all workers
+    let rate_limiter = web::Data::new(RateLimiter::new(app_config.rate_limit.clone()));
 
     let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
             .app_data( web::Data::new( email_config.clone()))
             .app_data( web::Data::new( telegram_config.clone()))
+            .app_data( web::Data::new( incident_config.clone()))
+            .app_data( web::Data::new( report_config.clone()))
+            .app_data( web::Data::new( system_health_config.clone()))
+            .app_data( web::Data::new( sensor_history_config.clone()))
+            .app_data( web::Data::new( admin_config.clone()))
+            .app_data( web::Data::new( readiness_config.clone()))
+            .app_data( rate_limiter.clone())
             .service(web::resource("/").route(web::get().to(status_check)))
+            .service(web::resource("/healthz").route(web::get().to(liveness_check)))
+            .service(web::resource("/readyz").route(web::get().to(readiness_check)))
             .service(web::resource("/checkin").route(web::post().to(checkin_node)))
+            .service(web::resource("/checkin/batch").route(web::post().to(checkin_batch)))
             .service(web::resource("/alert-sender").route(web::get().to(alert_sender)))
+            .service(web::resource("/metrics").route(web::get().to(metrics_report)))
+            .service(web::resource("/nodes").route(web::get().to(list_nodes)))
+            .service(web::resource("/sensors").route(web::get().to(list_node_sensors)))
+            .service(web::resource("/sensors/label").route(web::post().to(update_sensor_label)))
+            .service(web::resource("/triggers").route(web::post().to(create_sensor_trigger)))
+            .service(web::resource("/report-sender").route(web::get().to(report_sender)))
+            .service(web::resource("/reports/uptime").route(web::get().to(node_uptime_report)))
+            .service(web::resource("/admin/rate-limits").route(web::get().to(rate_limit_report)))
+            .service(web::resource("/admin/nodes/approve").route(web::post().to(approve_node)))
+            .service(web::resource("/incident/{incident_id}/acknowledge").route(web::get().to(acknowledge_incident)))
     })
         .bind(server_addr.clone())?
         .run();
     info!("Server running at http://{}/", server_addr);
     
    
-    let startup_message:String = format!("Server startup complete");
-    send_telegram::send_telegram_msg(&startup_message,&telegram_config_parameter).await;
+    // notification channels are optional. A failing channel is reported, the server keeps running without it
+    let email_check = match email_config_parameter.is_enabled() {
+        true => Some(send_email::check_email_channel(&email_config_parameter).await),
+        false => None,
+    };
+    let startup_message:String = "Server startup complete".to_string();
+    let telegram_check = match telegram_config_parameter.is_enabled() {
+        true => Some(send_telegram::send_telegram_msg(&startup_message,&telegram_config_parameter).await),
+        false => None,
+    };
+    let channel_status = |check: &Option<Result<(), String>>| match check {
+        None => "disabled".to_string(),
+        Some(Ok(())) => "ok".to_string(),
+        Some(Err(e)) => format!("failing ({})", e),
+    };
+    if matches!(email_check, Some(Ok(()))) && !matches!(telegram_check, Some(Err(_))) {
+        info!("Notification channels: email = {} telegram = {}", channel_status(&email_check), channel_status(&telegram_check));
+    } else {
+        warn!("Notification channels: email = {} telegram = {}. Alerts are only logged while email does not work",
+            channel_status(&email_check), channel_status(&telegram_check));
+    }
 
-    
     server.await
 }
\ No newline at end of file
@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{GenericClient, Status};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::incident_functions;
+use crate::notification_outbox;
+
+
+pub const CHECKINS_TOTAL: &str = "remote_pi_monitor_checkins_total";
+pub const UNKNOWN_API_KEY_TOTAL: &str = "remote_pi_monitor_unknown_api_key_total";
+pub const TRIGGERS_EVALUATED_TOTAL: &str = "remote_pi_monitor_sensor_triggers_evaluated_total";
+pub const TRIGGERS_FIRED_TOTAL: &str = "remote_pi_monitor_sensor_triggers_fired_total";
+pub const NOTIFICATIONS_SENT_TOTAL: &str = "remote_pi_monitor_notifications_sent_total";
+pub const NOTIFICATIONS_FAILED_TOTAL: &str = "remote_pi_monitor_notifications_failed_total";
+pub const ALERT_SWEEP_DURATION_SECONDS: &str = "remote_pi_monitor_alert_sweep_duration_seconds";
+pub const ALERT_SWEEP_LAST_RUN: &str = "remote_pi_monitor_alert_sweep_last_run_timestamp_seconds";
+pub const DB_POOL_CONNECTIONS: &str = "remote_pi_monitor_db_pool_connections";
+pub const DB_POOL_MAX_CONNECTIONS: &str = "remote_pi_monitor_db_pool_max_connections";
+pub const DB_POOL_WAITING: &str = "remote_pi_monitor_db_pool_waiting";
+pub const NOTIFICATION_BACKLOG: &str = "remote_pi_monitor_notification_backlog";
+pub const NODES: &str = "remote_pi_monitor_nodes";
+pub const SENSOR_VALUE: &str = "remote_pi_monitor_sensor_value";
+
+pub const CHANNEL_EMAIL: &str = "email";
+pub const CHANNEL_TELEGRAM: &str = "telegram";
+
+/// Metric families in the order of the /metrics output: name, type and help.
+const FAMILIES: [(&str, &str, &str); 14] = [
+    (CHECKINS_TOTAL, "counter", "Accepted checkins per API key and node"),
+    (UNKNOWN_API_KEY_TOTAL, "counter", "Checkins with an API key that is not found"),
+    (TRIGGERS_EVALUATED_TOTAL, "counter", "Sensor trigger validations with a result"),
+    (TRIGGERS_FIRED_TOTAL, "counter", "Sensor trigger incidents opened"),
+    (NOTIFICATIONS_SENT_TOTAL, "counter", "Notifications sent per channel, emails are counted per recipient"),
+    (NOTIFICATIONS_FAILED_TOTAL, "counter", "Notifications that could not be sent per channel"),
+    (NOTIFICATION_BACKLOG, "gauge", "Notifications queued and not sent yet"),
+    (ALERT_SWEEP_DURATION_SECONDS, "summary", "Duration of the offline node alert sweeps"),
+    (ALERT_SWEEP_LAST_RUN, "gauge", "Time the last alert sweep finished"),
+    (DB_POOL_CONNECTIONS, "gauge", "Database connections of the pool by state"),
+    (DB_POOL_MAX_CONNECTIONS, "gauge", "Maximum size of the database pool"),
+    (DB_POOL_WAITING, "gauge", "Requests waiting for a database connection"),
+    (NODES, "gauge", "Monitored nodes by state, a node is offline while its offline incident is open"),
+    (SENSOR_VALUE, "gauge", "Latest numeric value of each sensor"),
+];
+
+// counters and summaries of this process, keyed by sample name and rendered labels
+static PROCESS_METRICS: Mutex<BTreeMap<(&'static str, String), f64>> = Mutex::new(BTreeMap::new());
+
+
+pub struct Sample {
+    pub name: &'static str,
+    pub labels: String,
+    pub value: f64,
+}
+
+/// `{name="value",...}` with the Prometheus escapes, empty without labels.
+pub fn labels(pairs: &[(&str, &str)]) -> String {
+    if pairs.is_empty() {
+        return String::new();
+    }
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+pub fn increment(name: &'static str, label_pairs: &[(&str, &str)]) {
+    *PROCESS_METRICS.lock().unwrap().entry((name, labels(label_pairs))).or_insert(0.0) += 1.0;
+}
+
+pub fn observe_alert_sweep(duration: Duration, finished_at: &DateTime<Utc>) {
+    let mut process_metrics = PROCESS_METRICS.lock().unwrap();
+    *process_metrics.entry(("remote_pi_monitor_alert_sweep_duration_seconds_sum", String::new())).or_insert(0.0) += duration.as_secs_f64();
+    *process_metrics.entry(("remote_pi_monitor_alert_sweep_duration_seconds_count", String::new())).or_insert(0.0) += 1.0;
+    process_metrics.insert((ALERT_SWEEP_LAST_RUN, String::new()), finished_at.timestamp() as f64);
+}
+
+pub fn process_samples() -> Vec<Sample> {
+    let mut samples: Vec<Sample> = PROCESS_METRICS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|((name, labels), value)| Sample { name, labels: labels.clone(), value: *value })
+        .collect();
+    samples.push(Sample { name: NOTIFICATION_BACKLOG, labels: String::new(), value: notification_outbox::backlog() as f64 });
+    samples
+}
+
+pub fn pool_samples(status: &Status) -> Vec<Sample> {
+    vec![
+        Sample { name: DB_POOL_CONNECTIONS, labels: labels(&[("state", "idle")]), value: status.available as f64 },
+        Sample { name: DB_POOL_CONNECTIONS, labels: labels(&[("state", "in_use")]), value: status.size.saturating_sub(status.available) as f64 },
+        Sample { name: DB_POOL_MAX_CONNECTIONS, labels: String::new(), value: status.max_size as f64 },
+        Sample { name: DB_POOL_WAITING, labels: String::new(), value: status.waiting as f64 },
+    ]
+}
+
+/// Node state and sensor value gauges, read from the database on every scrape.
+pub async fn database_samples(dbconnection: &impl GenericClient) -> Result<Vec<Sample>, tokio_postgres::Error> {
+    let mut samples = Vec::new();
+
+    let stmt_node_states = dbconnection.prepare_cached("SELECT count(*) FILTER (WHERE incidents.id IS NULL), count(*) FILTER (WHERE incidents.id IS NOT NULL)
+	FROM nodes LEFT JOIN incidents ON incidents.node_id = nodes.id AND incidents.incident_type = $1 AND incidents.resolved_at IS NULL
+	WHERE nodes.monitoring_enabled = true;").await?;
+    let row_node_states = dbconnection.query_one(&stmt_node_states, &[&incident_functions::INCIDENT_TYPE_NODE_OFFLINE]).await?;
+    samples.push(Sample { name: NODES, labels: labels(&[("state", "online")]), value: row_node_states.get::<_, i64>(0) as f64 });
+    samples.push(Sample { name: NODES, labels: labels(&[("state", "offline")]), value: row_node_states.get::<_, i64>(1) as f64 });
+
+    let stmt_sensor_values = dbconnection.prepare_cached("SELECT nodes.fk_api_key_id, nodes.node_id_external, sensors.sensor_id, sensors.sensor_name, sensors.last_value
+	FROM sensors JOIN nodes ON nodes.id = sensors.node_id
+	WHERE sensors.value_type = 'number' AND sensors.last_value IS NOT NULL
+	ORDER BY nodes.fk_api_key_id, nodes.node_id_external, sensors.sensor_id;").await?;
+    for row in dbconnection.query(&stmt_sensor_values, &[]).await? {
+        let api_key_id: i32 = row.get(0);
+        let node_id: String = row.get(1);
+        let sensor_id: String = row.get(2);
+        let sensor_name: String = row.get(3);
+        samples.push(Sample {
+            name: SENSOR_VALUE,
+            labels: labels(&[("api_key_id", &api_key_id.to_string()), ("node_id", &node_id), ("sensor_id", &sensor_id), ("sensor_name", &sensor_name)]),
+            value: row.get(4),
+        });
+    }
+
+    Ok(samples)
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf".to_string() } else { "-Inf".to_string() }
+    } else {
+        value.to_string()
+    }
+}
+
+/// Prometheus text format. Samples are grouped under the HELP / TYPE lines of their family.
+pub fn render(samples: &[Sample]) -> String {
+    let mut output = String::new();
+    for (family, metric_type, help) in FAMILIES {
+        let family_samples: Vec<&Sample> = samples
+            .iter()
+            .filter(|x| x.name == family || matches!(x.name.strip_prefix(family), Some("_sum") | Some("_count")))
+            .collect();
+        if family_samples.is_empty() {
+            continue;
+        }
+        output.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n", family, help, family, metric_type));
+        for sample in family_samples {
+            output.push_str(&format!("{}{} {}\n", sample.name, sample.labels, format_value(sample.value)));
+        }
+    }
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(labels(&[]), "");
+        assert_eq!(labels(&[("node_id", "pi-1"), ("sensor_name", "say \"hi\"\\\n")]), "{node_id=\"pi-1\",sensor_name=\"say \\\"hi\\\"\\\\\\n\"}");
+    }
+
+    #[test]
+    fn samples_grouped_by_family() {
+        let samples = vec![
+            Sample { name: NODES, labels: labels(&[("state", "online")]), value: 3.0 },
+            Sample { name: CHECKINS_TOTAL, labels: labels(&[("api_key_id", "1"), ("node_id", "pi-1")]), value: 2.0 },
+            Sample { name: "remote_pi_monitor_alert_sweep_duration_seconds_sum", labels: String::new(), value: 0.25 },
+            Sample { name: "remote_pi_monitor_alert_sweep_duration_seconds_count", labels: String::new(), value: 1.0 },
+            Sample { name: SENSOR_VALUE, labels: labels(&[("sensor_id", "t1")]), value: f64::INFINITY },
+        ];
+        assert_eq!(render(&samples), "\
+# HELP remote_pi_monitor_checkins_total Accepted checkins per API key and node
+# TYPE remote_pi_monitor_checkins_total counter
+remote_pi_monitor_checkins_total{api_key_id=\"1\",node_id=\"pi-1\"} 2
+# HELP remote_pi_monitor_alert_sweep_duration_seconds Duration of the offline node alert sweeps
+# TYPE remote_pi_monitor_alert_sweep_duration_seconds summary
+remote_pi_monitor_alert_sweep_duration_seconds_sum 0.25
+remote_pi_monitor_alert_sweep_duration_seconds_count 1
+# HELP remote_pi_monitor_nodes Monitored nodes by state, a node is offline while its offline incident is open
+# TYPE remote_pi_monitor_nodes gauge
+remote_pi_monitor_nodes{state=\"online\"} 3
+# HELP remote_pi_monitor_sensor_value Latest numeric value of each sensor
+# TYPE remote_pi_monitor_sensor_value gauge
+remote_pi_monitor_sensor_value{sensor_id=\"t1\"} +Inf
+");
+    }
+
+    #[test]
+    fn process_counters() {
+        increment(UNKNOWN_API_KEY_TOTAL, &[]);
+        increment(UNKNOWN_API_KEY_TOTAL, &[]);
+        let unknown_api_key = process_samples().into_iter().find(|x| x.name == UNKNOWN_API_KEY_TOTAL).unwrap();
+        assert!(unknown_api_key.value >= 2.0);
+    }
+}
@@ -0,0 +1,120 @@
+use deadpool_postgres::Client;
+use tokio_postgres::SimpleQueryMessage;
+
+use log::{info, warn};
+
+
+/// Column name of the rows a migration returns to report the data it changed.
+pub const MIGRATION_REPORT_COLUMN: &str = "migration_report";
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Embedded migrations, applied in version order. Statements are not schema qualified: tables live in the schema
+/// of the connection search_path. Never edit an applied migration, add a new one.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration { version: 1, name: "initial_schema", sql: include_str!("../sql/migrations/0001_initial_schema.sql") },
+    Migration { version: 2, name: "constraints_and_indexes", sql: include_str!("../sql/migrations/0002_constraints_and_indexes.sql") },
+    Migration { version: 3, name: "processed_checkins", sql: include_str!("../sql/migrations/0003_processed_checkins.sql") },
+    Migration { version: 4, name: "alert_sweep_status", sql: include_str!("../sql/migrations/0004_alert_sweep_status.sql") },
+    Migration { version: 5, name: "api_key_id_default", sql: include_str!("../sql/migrations/0005_api_key_id_default.sql") },
+    Migration { version: 6, name: "sensor_readings_retention", sql: include_str!("../sql/migrations/0006_sensor_readings_retention.sql") },
+];
+
+
+/// Schema names are put into SQL and connection options, so only lowercase identifiers are accepted.
+pub fn is_valid_schema_name(schema: &str) -> bool {
+    schema.chars().next().is_some_and(|x| x.is_ascii_lowercase() || x == '_')
+        && schema.len() <= 63
+        && schema.chars().all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '_')
+}
+
+/// Connection option that makes unqualified table names resolve to the configured schema.
+pub fn search_path_option(schema: &str) -> String {
+    format!("-c search_path={}", schema)
+}
+
+/// Migrations that are not applied yet, in version order.
+pub fn pending_migrations(applied_versions: &[i32]) -> Vec<&'static Migration> {
+    MIGRATIONS.iter().filter(|x| !applied_versions.contains(&x.version)).collect()
+}
+
+/// Creates the schema and the schema_migrations table and applies pending migrations, each in its own transaction.
+/// An advisory lock keeps concurrently starting instances from applying the same migration twice.
+/// Rows of a `migration_report` column report the data a migration changed and are logged as warnings.
+/// Returns the versions applied now.
+pub async fn run_migrations(
+    client: &mut Client,
+    schema: &str,
+) -> Result<Vec<i32>, tokio_postgres::Error> {
+    client.batch_execute(&format!("CREATE SCHEMA IF NOT EXISTS {};
+	CREATE TABLE IF NOT EXISTS {}.schema_migrations
+	(
+	    version integer NOT NULL,
+	    name character varying(100) COLLATE pg_catalog.\"default\" NOT NULL,
+	    applied_at timestamp with time zone NOT NULL DEFAULT now(),
+	    CONSTRAINT schema_migrations_pkey PRIMARY KEY (version)
+	);", schema, schema)).await?;
+
+    let mut applied_now: Vec<i32> = Vec::new();
+    loop {
+        let transaction = client.transaction().await?;
+        transaction.batch_execute("SELECT pg_advisory_xact_lock(hashtext('schema_migrations'));").await?;
+
+        let rows = transaction.query(&format!("SELECT version FROM {}.schema_migrations;", schema), &[]).await?;
+        let applied_versions: Vec<i32> = rows.iter().map(|row| row.get(0)).collect();
+        let migration = match pending_migrations(&applied_versions).first() {
+            Some(x) => *x,
+            None => break,
+        };
+
+        info!("Applying migration {} {}", migration.version, migration.name);
+        transaction.batch_execute(&format!("SET LOCAL search_path = {};", schema)).await?;
+        for message in transaction.simple_query(migration.sql).await? {
+            match message {
+                SimpleQueryMessage::Row(row) if row.columns()[0].name() == MIGRATION_REPORT_COLUMN => {
+                    warn!("Migration {} {}: {}", migration.version, migration.name, row.get(0).unwrap_or_default());
+                }
+                _ => {}
+            }
+        }
+        transaction.execute(&format!("INSERT INTO {}.schema_migrations(version, name) VALUES ($1, $2);", schema), &[&migration.version, &migration.name]).await?;
+        transaction.commit().await?;
+
+        applied_now.push(migration.version);
+    }
+
+    Ok(applied_now)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migration_versions_increase() {
+        assert!(MIGRATIONS.windows(2).all(|x| x[0].version < x[1].version));
+        assert_eq!(MIGRATIONS[0].version, 1);
+        assert!(MIGRATIONS.iter().all(|x| !x.sql.contains("remote_pi_monitor.")));
+    }
+
+    #[test]
+    fn pending_after_applied() {
+        assert_eq!(pending_migrations(&[]).len(), MIGRATIONS.len());
+        assert!(pending_migrations(&MIGRATIONS.iter().map(|x| x.version).collect::<Vec<i32>>()).is_empty());
+    }
+
+    #[test]
+    fn schema_names() {
+        assert!(is_valid_schema_name("remote_pi_monitor"));
+        assert!(is_valid_schema_name("_staging2"));
+        assert!(!is_valid_schema_name(""));
+        assert!(!is_valid_schema_name("2monitor"));
+        assert!(!is_valid_schema_name("monitor; DROP TABLE nodes"));
+        assert!(!is_valid_schema_name("Monitor"));
+    }
+}
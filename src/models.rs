@@ -8,13 +8,110 @@
         pub api_key: String,
         pub node_id: String,
         pub sensor_data: Option<Vec<SensorData>>,
+        pub system: Option<SystemHealth>,
+        pub device_timestamp: Option<chrono::DateTime<Utc>>,
+        // set by the node, a retried checkin with the same id is not processed again
+        pub checkin_id: Option<String>,
     }
 
-    #[derive(Deserialize, Serialize)]
+    #[derive(Deserialize)]
+    pub struct BatchCheckinData {
+        pub checkins: Vec<CheckinData>,
+    }
+
+    #[derive(Serialize)]
+    pub struct BatchCheckinResult {
+        pub node_id: String,
+        pub accepted: bool,
+        pub status: String,
+        pub readings_stored: usize,
+        pub readings_backfilled: usize,
+    }
+
+    /// Optional system health block of a checkin. Every field is optional, nodes report what they can.
+    #[derive(Deserialize, Serialize, Clone, Debug, Default)]
+    pub struct SystemHealth {
+        pub uptime_seconds: Option<i64>,
+        pub cpu_temperature: Option<f64>,
+        pub cpu_throttled: Option<bool>,
+        pub load_1m: Option<f64>,
+        pub load_5m: Option<f64>,
+        pub load_15m: Option<f64>,
+        pub disk_used_percent: Option<f64>,
+        pub memory_used_percent: Option<f64>,
+        pub ip_addresses: Option<Vec<String>>,
+        pub os: Option<String>,
+        pub agent_version: Option<String>,
+    }
+
+    /// `timestamp` is set for buffered readings (batch checkin). Without it the reading belongs to the checkin time.
+    #[derive(Deserialize, Serialize, Clone)]
     pub struct SensorData {
         pub id: String,
         pub sensor_name: String,
-        pub value: f32,
+        pub value: SensorValue,
+        pub unit: Option<String>,
+        pub timestamp: Option<chrono::DateTime<Utc>>,
+    }
+
+    /// Sensor value as sent by the node. The JSON type decides the value type, so existing nodes sending plain numbers keep working.
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    #[serde(untagged)]
+    pub enum SensorValue {
+        Number(f64),
+        Boolean(bool),
+        Text(String),
+    }
+
+    impl SensorValue {
+        pub const TYPE_NUMBER: &'static str = "number";
+        pub const TYPE_BOOLEAN: &'static str = "boolean";
+        pub const TYPE_STRING: &'static str = "string";
+
+        pub fn value_type(&self) -> &'static str {
+            match self {
+                SensorValue::Number(_) => SensorValue::TYPE_NUMBER,
+                SensorValue::Boolean(_) => SensorValue::TYPE_BOOLEAN,
+                SensorValue::Text(_) => SensorValue::TYPE_STRING,
+            }
+        }
+
+        /// Numeric value used by numeric triggers, history aggregates and expressions. Booleans are 1 / 0, strings have none.
+        pub fn as_number(&self) -> Option<f64> {
+            match self {
+                SensorValue::Number(x) => Some(*x),
+                SensorValue::Boolean(x) => Some(if *x { 1.0 } else { 0.0 }),
+                SensorValue::Text(_) => None,
+            }
+        }
+
+        /// Text stored next to the numeric value. Numbers have none.
+        pub fn as_text(&self) -> Option<String> {
+            match self {
+                SensorValue::Number(_) => None,
+                SensorValue::Boolean(x) => Some(x.to_string()),
+                SensorValue::Text(x) => Some(x.clone()),
+            }
+        }
+
+        /// Rebuilds the value from the value_type / value / value_text database columns.
+        pub fn from_db(value_type: &str, value: Option<f64>, value_text: Option<String>) -> Option<SensorValue> {
+            match value_type {
+                SensorValue::TYPE_BOOLEAN => value_text.map(|x| SensorValue::Boolean(x == "true")),
+                SensorValue::TYPE_STRING => value_text.map(SensorValue::Text),
+                _ => value.map(SensorValue::Number),
+            }
+        }
+    }
+
+    impl std::fmt::Display for SensorValue {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                SensorValue::Number(x) => write!(f, "{}", x),
+                SensorValue::Boolean(x) => write!(f, "{}", x),
+                SensorValue::Text(x) => write!(f, "\"{}\"", x),
+            }
+        }
     }
 
     #[derive(Deserialize, PostgresMapper, Serialize)]
@@ -24,10 +121,153 @@
         pub node_id: i32,
         pub sensor_id: String,
         pub monitoring_enabled: bool,
-        pub trigger_notification_sent: bool,
         pub  validation_function: String,
-        pub validation_parameter_1: Option<f32>,
-        pub validation_parameter_2: Option<f32>,
+        pub validation_parameter_1: Option<f64>,
+        pub validation_parameter_2: Option<f64>,
+        pub validation_parameter_text: Option<String>,
+        pub validation_expression: Option<String>,
+        pub deadband: f64,
+        pub deadband_type: String,
+        pub fail_after_count: i32,
+        pub fail_after_seconds: i32,
+        pub recover_after_count: i32,
+        pub consecutive_fail_count: i32,
+        pub consecutive_ok_count: i32,
+        pub fail_streak_started_at: Option<chrono::DateTime<Utc>>,
+        pub missing_grace_seconds: i32,
+        pub missing_since: Option<chrono::DateTime<Utc>>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct NewSensorTrigger {
+        pub api_key: String,
+        pub node_id: String,
+        pub sensor_id: String,
+        pub monitoring_enabled: Option<bool>,
+        pub validation_function: String,
+        pub validation_parameter_1: Option<f64>,
+        pub validation_parameter_2: Option<f64>,
+        pub validation_parameter_text: Option<String>,
+        pub validation_expression: Option<String>,
+        pub deadband: Option<f64>,
+        pub deadband_type: Option<String>,
+        pub fail_after_count: Option<i32>,
+        pub fail_after_seconds: Option<i32>,
+        pub recover_after_count: Option<i32>,
+        pub missing_grace_seconds: Option<i32>,
+    }
+
+    // `export` / `import` file: nodes of an API key with their trigger definitions
+    #[derive(Deserialize, Serialize)]
+    pub struct NodeExport {
+        pub nodes: Vec<ExportedNode>,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct ExportedNode {
+        pub node_id: String,
+        pub node_group: Option<String>,
+        pub monitoring_enabled: bool,
+        pub approval_status: String,
+        pub notification_email_list: String,
+        pub sensor_triggers: Vec<ExportedTrigger>,
+    }
+
+    #[derive(Deserialize, Serialize, PartialEq, Debug)]
+    pub struct ExportedTrigger {
+        pub sensor_id: String,
+        pub monitoring_enabled: bool,
+        pub validation_function: String,
+        pub validation_parameter_1: Option<f64>,
+        pub validation_parameter_2: Option<f64>,
+        pub validation_parameter_text: Option<String>,
+        pub validation_expression: Option<String>,
+        pub deadband: f64,
+        pub deadband_type: String,
+        pub fail_after_count: i32,
+        pub fail_after_seconds: i32,
+        pub recover_after_count: i32,
+        pub missing_grace_seconds: i32,
+    }
+
+    #[derive(Serialize)]
+    pub struct RegisteredSensor {
+        pub node_id: String,
+        pub sensor_id: String,
+        pub sensor_name: String,
+        pub label: Option<String>,
+        pub first_seen: chrono::DateTime<Utc>,
+        pub last_seen: chrono::DateTime<Utc>,
+        pub last_value: Option<SensorValue>,
+        pub value_type: String,
+        pub unit: Option<String>,
+        pub has_trigger: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SensorListQuery {
+        pub api_key: String,
+        pub node_id: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct NodeInfo {
+        pub node_id: String,
+        pub node_group: Option<String>,
+        pub monitoring_enabled: bool,
+        pub approval_status: String,
+        pub last_checkin_timestamp: chrono::DateTime<Utc>,
+        pub clock_offset_seconds: Option<f64>,
+        pub system_reported_at: Option<chrono::DateTime<Utc>>,
+        pub system: Option<SystemHealth>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct NodeListQuery {
+        pub api_key: String,
+        pub node_id: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SensorLabelUpdate {
+        pub api_key: String,
+        pub node_id: String,
+        pub sensor_id: String,
+        pub label: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ErrorResponse {
+        pub error: String,
+    }
+
+    #[derive(Serialize)]
+    pub struct RejectedRequests {
+        pub api_key: String,
+        pub rejected_requests: u64,
+    }
+
+    #[derive(Deserialize)]
+    pub struct AdminQuery {
+        pub admin_key: String,
+    }
+
+    #[derive(Deserialize)]
+    pub struct NodeApproval {
+        pub admin_key: String,
+        pub api_key: String,
+        pub node_id: String,
+    }
+
+    #[derive(Serialize)]
+    pub struct FieldError {
+        pub field: String,
+        pub error: String,
+    }
+
+    #[derive(Serialize)]
+    pub struct ValidationErrorResponse {
+        pub errors: Vec<FieldError>,
     }
 
     #[derive(Deserialize, PostgresMapper, Serialize)]
@@ -39,7 +279,34 @@
         pub monitoring_enabled: bool,
         pub last_checkin_timestamp: chrono::DateTime<Utc>,
         pub notification_email_list:  String,
-        pub offline_notification_sent: bool,
+        pub node_group: Option<String>,
+    }
+
+    #[derive(Deserialize, PostgresMapper, Serialize)]
+    #[pg_mapper(table = "incidents")]
+    pub struct Incident {
+        pub id: i32,
+        pub incident_type: String,
+        pub node_id: i32,
+        pub sensor_triggers_id: Option<i32>,
+        pub opened_at: chrono::DateTime<Utc>,
+        pub acknowledged_at: Option<chrono::DateTime<Utc>>,
+        pub acknowledged_by: Option<String>,
+        pub resolved_at: Option<chrono::DateTime<Utc>>,
+        pub last_value: Option<f64>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct IncidentAcknowledgeQuery {
+        pub by: String,
+        pub token: String,
+    }
+
+    #[derive(Deserialize)]
+    pub struct UptimeReportQuery {
+        pub api_key: String,
+        pub from: Option<chrono::DateTime<Utc>>,
+        pub to: Option<chrono::DateTime<Utc>>,
     }
 
     #[derive(Debug, Default, Deserialize,Clone)]
@@ -50,9 +317,77 @@
     }
 
 
+    // email notifications are disabled when smtp_server is not set
+    impl Email {
+        pub fn is_enabled(&self) -> bool {
+            !self.smtp_server.is_empty()
+        }
+    }
+
     #[derive(Debug, Default, Deserialize,Clone)]
     pub struct TelegramConfig {
         pub bot_token: String,
         pub channel_id: String,
+    }
+
+    // telegram messages are disabled when bot_token is not set
+    impl TelegramConfig {
+        pub fn is_enabled(&self) -> bool {
+            !self.bot_token.is_empty()
+        }
+    }
+
+    #[derive(Debug, Default, Deserialize,Clone)]
+    pub struct IncidentConfig {
+        pub ack_secret: String,
+        pub public_base_url: String,
+    }
+
+    #[derive(Debug, Default, Deserialize,Clone)]
+    pub struct ReportConfig {
+        pub email_list: String,
+    }
 
-    }
\ No newline at end of file
+    #[derive(Debug, Default, Deserialize,Clone)]
+    pub struct SystemHealthConfig {
+        pub disk_used_percent_limit: f64,
+        pub clock_skew_warning_seconds: f64,
+    }
+
+    #[derive(Debug, Default, Deserialize,Clone)]
+    pub struct RateLimitConfig {
+        pub api_key_per_second: f64,
+        pub api_key_burst: f64,
+        pub node_per_second: f64,
+        pub node_burst: f64,
+    }
+
+    #[derive(Debug, Default, Deserialize,Clone)]
+    pub struct AdminConfig {
+        pub admin_api_key: String,
+        pub admin_email_list: String,
+    }
+
+    #[derive(Debug, Default, Deserialize,Clone)]
+    pub struct SensorHistoryConfig {
+        pub retention_days: i64,
+    }
+
+    #[derive(Debug, Default, Deserialize,Clone)]
+    pub struct ReadinessConfig {
+        pub alert_sweep_max_age_seconds: i64,
+        pub notification_backlog_limit: usize,
+    }
+
+    #[derive(Serialize, Debug, PartialEq)]
+    pub struct ComponentStatus {
+        pub component: String,
+        pub status: String,
+        pub detail: String,
+    }
+
+    #[derive(Serialize)]
+    pub struct ReadinessReport {
+        pub status: String,
+        pub components: Vec<ComponentStatus>,
+    }
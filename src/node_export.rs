@@ -0,0 +1,213 @@
+use crate::models::{ExportedNode, ExportedTrigger, NewSensorTrigger, NodeExport};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+
+use log::debug;
+
+use crate::checkin_validation;
+use crate::node_registration;
+use crate::node_sensor_functions;
+
+
+/// Nodes of the API key with their trigger definitions. Trigger state (debounce counters, incidents) is not exported.
+/// Database errors are returned, the `export` / `import` commands report them.
+pub async fn export_nodes(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+) -> Result<NodeExport, tokio_postgres::Error> {
+    let stmt_nodes = dbconnection.prepare_cached("SELECT id, node_id_external, node_group, monitoring_enabled, approval_status, notification_email_list
+	FROM nodes WHERE fk_api_key_id = $1 ORDER BY node_id_external;").await?;
+    let rows = dbconnection.query(&stmt_nodes, &[api_key_id]).await?;
+
+    let mut nodes: Vec<ExportedNode> = Vec::new();
+    for row in rows {
+        let node_id_db: i32 = row.get(0);
+        nodes.push(ExportedNode {
+            node_id: row.get(1),
+            node_group: row.get(2),
+            monitoring_enabled: row.get(3),
+            approval_status: row.get(4),
+            notification_email_list: row.get::<_, Option<String>>(5).unwrap_or_default(),
+            sensor_triggers: node_triggers(dbconnection, &node_id_db).await?.into_iter().map(|(_, x)| x).collect(),
+        });
+    }
+
+    Ok(NodeExport { nodes })
+}
+
+async fn node_triggers(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+) -> Result<Vec<(i32, ExportedTrigger)>, tokio_postgres::Error> {
+    let stmt_triggers = dbconnection.prepare_cached("SELECT sensor_triggers_id, sensor_id, monitoring_enabled, validation_function, validation_parameter_1, validation_parameter_2,
+	validation_parameter_text, validation_expression, deadband, deadband_type, fail_after_count, fail_after_seconds, recover_after_count, missing_grace_seconds
+	FROM sensor_triggers WHERE node_id = $1 ORDER BY sensor_triggers_id;").await?;
+    let rows = dbconnection.query(&stmt_triggers, &[node_id_db]).await?;
+
+    Ok(rows.iter()
+        .map(|row| (row.get(0), ExportedTrigger {
+            sensor_id: row.get(1),
+            monitoring_enabled: row.get(2),
+            validation_function: row.get(3),
+            validation_parameter_1: row.get(4),
+            validation_parameter_2: row.get(5),
+            validation_parameter_text: row.get(6),
+            validation_expression: row.get(7),
+            deadband: row.get(8),
+            deadband_type: row.get(9),
+            fail_after_count: row.get(10),
+            fail_after_seconds: row.get(11),
+            recover_after_count: row.get(12),
+            missing_grace_seconds: row.get(13),
+        }))
+        .collect())
+}
+
+/// All errors of the file. Node ids are checked like checkins, triggers like POST /triggers.
+pub fn check_node_export(node_export: &NodeExport) -> Vec<String> {
+    let mut errors: Vec<String> = Vec::new();
+    for node in &node_export.nodes {
+        if !checkin_validation::is_valid_id(&node.node_id) {
+            errors.push(format!("node {}: invalid node id", node.node_id));
+        }
+        if ![node_registration::NODE_APPROVAL_PENDING, node_registration::NODE_APPROVAL_APPROVED].contains(&node.approval_status.as_str()) {
+            errors.push(format!("node {}: unknown approval_status '{}'", node.node_id, node.approval_status));
+        }
+        for trigger in &node.sensor_triggers {
+            let new_sensor_trigger = NewSensorTrigger {
+                api_key: String::new(),
+                node_id: node.node_id.clone(),
+                sensor_id: trigger.sensor_id.clone(),
+                monitoring_enabled: Some(trigger.monitoring_enabled),
+                validation_function: trigger.validation_function.clone(),
+                validation_parameter_1: trigger.validation_parameter_1,
+                validation_parameter_2: trigger.validation_parameter_2,
+                validation_parameter_text: trigger.validation_parameter_text.clone(),
+                validation_expression: trigger.validation_expression.clone(),
+                deadband: Some(trigger.deadband),
+                deadband_type: Some(trigger.deadband_type.clone()),
+                fail_after_count: Some(trigger.fail_after_count),
+                fail_after_seconds: Some(trigger.fail_after_seconds),
+                recover_after_count: Some(trigger.recover_after_count),
+                missing_grace_seconds: Some(trigger.missing_grace_seconds),
+            };
+            if let Err(e) = node_sensor_functions::check_trigger_definition(&new_sensor_trigger) {
+                errors.push(format!("node {} sensor {}: {}", node.node_id, trigger.sensor_id, e));
+            }
+        }
+    }
+
+    errors
+}
+
+/// Creates / updates the nodes of the file. Triggers that are in the file but not on the node are added, triggers
+/// of the node that are not in the file are deleted. Unchanged triggers keep their state and incidents.
+/// Returns the number of added and deleted triggers.
+pub async fn import_nodes(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+    node_export: &NodeExport,
+    imported_at: &DateTime<Utc>,
+) -> Result<(usize, usize), tokio_postgres::Error> {
+    let stmt_node_upsert = dbconnection.prepare_cached("INSERT INTO nodes(
+	id, node_id_external, fk_api_key_id, monitoring_enabled, last_checkin_timestamp, notification_email_list, node_group, approval_status)
+	VALUES (DEFAULT, $1, $2, $3, $4, $5, $6, $7)
+	ON CONFLICT (fk_api_key_id, node_id_external) DO UPDATE SET monitoring_enabled = EXCLUDED.monitoring_enabled,
+	    notification_email_list = EXCLUDED.notification_email_list, node_group = EXCLUDED.node_group, approval_status = EXCLUDED.approval_status
+	RETURNING id;").await?;
+    let stmt_trigger_delete = dbconnection.prepare_cached("DELETE FROM sensor_triggers WHERE sensor_triggers_id = $1;").await?;
+    let stmt_trigger_insert = dbconnection.prepare_cached("INSERT INTO sensor_triggers(
+	node_id, sensor_id, monitoring_enabled, validation_function, validation_parameter_1, validation_parameter_2, validation_parameter_text, validation_expression,
+	deadband, deadband_type, fail_after_count, fail_after_seconds, recover_after_count, missing_grace_seconds)
+	VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14);").await?;
+
+    let mut added = 0;
+    let mut deleted = 0;
+    for node in &node_export.nodes {
+        let row = dbconnection.query_one(&stmt_node_upsert, &[
+            &node.node_id,
+            api_key_id,
+            &node.monitoring_enabled,
+            imported_at,
+            &node.notification_email_list,
+            &node.node_group,
+            &node.approval_status,
+        ]).await?;
+        let node_id_db: i32 = row.get(0);
+
+        let existing_triggers = node_triggers(dbconnection, &node_id_db).await?;
+        for (sensor_triggers_id, trigger) in &existing_triggers {
+            if !node.sensor_triggers.contains(trigger) {
+                dbconnection.execute(&stmt_trigger_delete, &[sensor_triggers_id]).await?;
+                deleted += 1;
+            }
+        }
+        for trigger in &node.sensor_triggers {
+            if existing_triggers.iter().any(|(_, x)| x == trigger) {
+                continue;
+            }
+            dbconnection.execute(&stmt_trigger_insert, &[
+                &node_id_db,
+                &trigger.sensor_id,
+                &trigger.monitoring_enabled,
+                &trigger.validation_function,
+                &trigger.validation_parameter_1,
+                &trigger.validation_parameter_2,
+                &trigger.validation_parameter_text,
+                &trigger.validation_expression,
+                &trigger.deadband,
+                &trigger.deadband_type,
+                &trigger.fail_after_count,
+                &trigger.fail_after_seconds,
+                &trigger.recover_after_count,
+                &trigger.missing_grace_seconds,
+            ]).await?;
+            added += 1;
+        }
+        debug!("imported node {} nodes.id = {}", node.node_id, node_id_db);
+    }
+
+    Ok((added, deleted))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigger(validation_function: &str) -> ExportedTrigger {
+        ExportedTrigger {
+            sensor_id: "28-000001".to_string(),
+            monitoring_enabled: true,
+            validation_function: validation_function.to_string(),
+            validation_parameter_1: Some(30.0),
+            validation_parameter_2: None,
+            validation_parameter_text: None,
+            validation_expression: None,
+            deadband: 0.05,
+            deadband_type: "absolute".to_string(),
+            fail_after_count: 1,
+            fail_after_seconds: 0,
+            recover_after_count: 1,
+            missing_grace_seconds: 0,
+        }
+    }
+
+    #[test]
+    fn invalid_triggers_of_the_file_are_listed() {
+        let node_export = NodeExport {
+            nodes: vec![ExportedNode {
+                node_id: "pi-01".to_string(),
+                node_group: None,
+                monitoring_enabled: true,
+                approval_status: "approved".to_string(),
+                notification_email_list: "ops@example.com".to_string(),
+                sensor_triggers: vec![trigger(">"), trigger("??")],
+            }],
+        };
+
+        let errors = check_node_export(&node_export);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("node pi-01 sensor 28-000001: unknown validation_function"));
+    }
+}
@@ -0,0 +1,183 @@
+use crate::models::AdminConfig;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+
+use log::debug;
+use log::error;
+
+use crate::send_email;
+use crate::notification_outbox::Outbox;
+
+pub const NEW_NODE_POLICY_REJECT: &str = "reject";
+pub const NEW_NODE_POLICY_PENDING: &str = "pending";
+pub const NEW_NODE_POLICY_AUTO_ENABLE: &str = "auto_enable";
+
+pub const NODE_APPROVAL_PENDING: &str = "pending";
+pub const NODE_APPROVAL_APPROVED: &str = "approved";
+
+
+pub struct NewNodePolicy {
+    pub policy: String,
+    pub template_node_id: Option<i32>,
+    pub template_node_group: Option<String>,
+}
+
+/// Defaults of an auto-enabled node.
+pub struct NodeTemplate {
+    pub node_id_db: i32,
+    pub notification_email_list: String,
+    pub node_group: Option<String>,
+}
+
+pub async fn new_node_policy(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+) -> NewNodePolicy {
+    let stmt_policy = dbconnection.prepare_cached("SELECT new_node_policy, template_node_id, template_node_group FROM api_keys WHERE id = $1;").await.unwrap();
+    let row = dbconnection.query_one(&stmt_policy, &[api_key_id]).await.unwrap();
+
+    NewNodePolicy { policy: row.get(0), template_node_id: row.get(1), template_node_group: row.get(2) }
+}
+
+/// Template node of the API key: template_node_id, otherwise the monitored node of template_node_group that checked in last.
+pub async fn node_template(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+    new_node_policy: &NewNodePolicy,
+) -> Option<NodeTemplate> {
+    let stmt_template = dbconnection.prepare_cached("SELECT id, notification_email_list, node_group FROM nodes
+	WHERE fk_api_key_id = $1 AND (id = $2 OR ($2::integer IS NULL AND node_group = $3 AND monitoring_enabled = true))
+	ORDER BY last_checkin_timestamp DESC LIMIT 1;").await.unwrap();
+    let rows = dbconnection.query(&stmt_template, &[api_key_id, &new_node_policy.template_node_id, &new_node_policy.template_node_group]).await.unwrap();
+
+    rows.first().map(|row| NodeTemplate {
+        node_id_db: row.get(0),
+        notification_email_list: row.get::<_, Option<String>>(1).unwrap_or_default(),
+        node_group: row.get(2),
+    })
+}
+
+/// Node row of a checkin. `inserted` is true when the checkin registered the node.
+pub struct UpsertedNode {
+    pub node_id_db: i32,
+    pub monitoring_enabled: bool,
+    pub notification_email_list: String,
+    pub inserted: bool,
+}
+
+/// Inserts an unknown node as pending or moves the checkin timestamp of a known node forward in one statement.
+/// Concurrent first checkins of the same node end up in the same row, only one of them sees `inserted`.
+pub async fn upsert_node(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+    node_id_external: &str,
+    node_checkin_timestamp: &DateTime<Utc>,
+) -> UpsertedNode {
+    let stmt_node_upsert = dbconnection.prepare_cached("INSERT INTO nodes(
+	id, node_id_external, fk_api_key_id, monitoring_enabled, last_checkin_timestamp, notification_email_list, approval_status)
+	VALUES (DEFAULT, $1, $2, false, $3, '', $4)
+	ON CONFLICT (fk_api_key_id, node_id_external) DO UPDATE SET last_checkin_timestamp = GREATEST(nodes.last_checkin_timestamp, EXCLUDED.last_checkin_timestamp)
+	RETURNING id, monitoring_enabled, notification_email_list, (xmax = 0) AS inserted;").await.unwrap();
+    let row = dbconnection.query_one(&stmt_node_upsert, &[
+        &node_id_external,
+        api_key_id,
+        node_checkin_timestamp,
+        &NODE_APPROVAL_PENDING,
+    ]).await.unwrap();
+
+    UpsertedNode {
+        node_id_db: row.get(0),
+        monitoring_enabled: row.get(1),
+        notification_email_list: row.get::<_, Option<String>>(2).unwrap_or_default(),
+        inserted: row.get(3),
+    }
+}
+
+/// Applies the policy of the API key to a node inserted by `upsert_node`. Returns None when the policy rejects
+/// unknown nodes, the caller rolls the checkin transaction back. Otherwise returns the approval status of the node.
+pub async fn apply_new_node_policy(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+    node_id_db: &i32,
+) -> Option<String> {
+    let new_node_policy = new_node_policy(dbconnection, api_key_id).await;
+    debug!("New node policy = {} for api_key_id = {}", new_node_policy.policy, api_key_id);
+
+    let template = match new_node_policy.policy.as_str() {
+        NEW_NODE_POLICY_REJECT => return None,
+        NEW_NODE_POLICY_AUTO_ENABLE => {
+            let template = node_template(dbconnection, api_key_id, &new_node_policy).await;
+            if template.is_none() {
+                error!("Template node not found for api_key_id = {}. Node is registered as pending", api_key_id);
+            }
+            template
+        }
+        _ => None,
+    };
+
+    let Some(template) = template else {
+        return Some(NODE_APPROVAL_PENDING.to_string());
+    };
+
+    let stmt_node_enable = dbconnection.prepare_cached("UPDATE nodes SET monitoring_enabled = true, notification_email_list = $2, node_group = $3, approval_status = $4
+	WHERE id = $1;").await.unwrap();
+    dbconnection.execute(&stmt_node_enable, &[
+        node_id_db,
+        &template.notification_email_list,
+        &template.node_group,
+        &NODE_APPROVAL_APPROVED,
+    ]).await.unwrap();
+    copy_sensor_triggers(dbconnection, &template.node_id_db, node_id_db).await;
+
+    Some(NODE_APPROVAL_APPROVED.to_string())
+}
+
+pub async fn copy_sensor_triggers(
+    dbconnection: &impl GenericClient,
+    template_node_id_db: &i32,
+    node_id_db: &i32,
+) {
+    let stmt_trigger_copy = dbconnection.prepare_cached("INSERT INTO sensor_triggers(
+	node_id, sensor_id, monitoring_enabled, validation_function, validation_parameter_1, validation_parameter_2, validation_parameter_text, validation_expression,
+	deadband, deadband_type, fail_after_count, fail_after_seconds, recover_after_count, missing_grace_seconds)
+	SELECT $2, sensor_id, monitoring_enabled, validation_function, validation_parameter_1, validation_parameter_2, validation_parameter_text, validation_expression,
+	deadband, deadband_type, fail_after_count, fail_after_seconds, recover_after_count, missing_grace_seconds
+	FROM sensor_triggers WHERE node_id = $1;").await.unwrap();
+    let copied = dbconnection.execute(&stmt_trigger_copy, &[template_node_id_db, node_id_db]).await.unwrap();
+    debug!("copied {} sensor triggers from nodes.id = {} to nodes.id = {}", copied, template_node_id_db, node_id_db);
+}
+
+/// Approves a pending node and enables its monitoring. Returns false when there is no such pending node.
+pub async fn approve_node(
+    dbconnection: &impl GenericClient,
+    api_key: &str,
+    node_id_external: &str,
+) -> bool {
+    let stmt_node_approve = dbconnection.prepare_cached("UPDATE nodes n SET approval_status = $3, monitoring_enabled = true
+	FROM api_keys k WHERE k.id = n.fk_api_key_id AND k.api_key = $1 AND n.node_id_external = $2 AND n.approval_status = $4;").await.unwrap();
+    let updated = dbconnection.execute(&stmt_node_approve, &[&api_key, &node_id_external, &NODE_APPROVAL_APPROVED, &NODE_APPROVAL_PENDING]).await.unwrap();
+
+    updated == 1
+}
+
+pub fn notify_admins_new_node(
+    node_id_external: &str,
+    api_key_id: &i32,
+    approval_status: &str,
+    node_checkin_timestamp: &DateTime<Utc>,
+    admin_config: &web::Data<AdminConfig>,
+    outbox: &mut Outbox,
+) {
+    if admin_config.admin_email_list.is_empty() {
+        debug!("New node notification not sent. admin email list not set");
+        return;
+    }
+    outbox.push(send_email::new_node_registered_email(
+        &admin_config.admin_email_list,
+        node_id_external,
+        api_key_id,
+        approval_status,
+        node_checkin_timestamp,
+    ));
+}
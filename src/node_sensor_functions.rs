@@ -1,36 +1,65 @@
-use crate::models::TelegramConfig;
-use crate::{ models::SensorData, models::SensorTrigger,models::Email};
+use crate::{ models::SensorData, models::SensorValue, models::SensorTrigger,models::NewSensorTrigger};
 use chrono::{DateTime,Utc};
-use deadpool_postgres::{Client};
-use actix_web::{web};
+use deadpool_postgres::GenericClient;
 
 use log::debug;
 use log::error;
 
 use crate::send_email;
+use crate::notification_outbox::Outbox;
+use crate::incident_functions;
+use crate::metrics;
+use crate::sensor_history;
+use crate::trigger_expression;
+use std::collections::HashMap;
 
+pub const DEADBAND_TYPE_ABSOLUTE: &str = "absolute";
+pub const DEADBAND_TYPE_PERCENT: &str = "percent";
 
+// change validation functions: "roc"/"ro%" compare against the oldest reading within validation_parameter_2 seconds,
+// "dlt"/"dl%" against the previous reading. validation_parameter_1 is the allowed rise (positive) or drop (negative).
+pub const VALIDATION_FUNCTION_RATE_OF_CHANGE: &str = "roc";
+pub const VALIDATION_FUNCTION_RATE_OF_CHANGE_PERCENT: &str = "ro%";
+pub const VALIDATION_FUNCTION_DELTA: &str = "dlt";
+pub const VALIDATION_FUNCTION_DELTA_PERCENT: &str = "dl%";
+// validation_expression holds the alert condition, see trigger_expression
+pub const VALIDATION_FUNCTION_EXPRESSION: &str = "exp";
+// stuck value: fails when the value stayed within validation_parameter_1 (epsilon) for validation_parameter_2 seconds
+pub const VALIDATION_FUNCTION_STUCK: &str = "stk";
+// like "exp" but not bound to one sensor. The expression references sensors with sensor("id") / delta("id")
+pub const VALIDATION_FUNCTION_COMPOSITE: &str = "cmp";
+
+pub const VALIDATION_FUNCTIONS: [&str; 12] = [
+    ">", "<", "==", "!=", "b",
+    VALIDATION_FUNCTION_RATE_OF_CHANGE, VALIDATION_FUNCTION_RATE_OF_CHANGE_PERCENT,
+    VALIDATION_FUNCTION_DELTA, VALIDATION_FUNCTION_DELTA_PERCENT, VALIDATION_FUNCTION_STUCK,
+    VALIDATION_FUNCTION_EXPRESSION, VALIDATION_FUNCTION_COMPOSITE,
+];
+
+
+ #[allow(clippy::too_many_arguments)]
  pub async fn sensor_trigger_check(
         node_id_db: &i32,
         sensor_data: &Option<Vec<SensorData>>,
-        node_id_external: &String,
-        notification_email_list: &String,
+        node_id_external: &str,
+        notification_email_list: &str,
         node_checkin_timestamp: &DateTime<Utc>,
-        dbconnection: &Client,
-        email_config: &web::Data<Email>,
-        telegram_config:&web::Data<TelegramConfig>,
+        dbconnection: &impl GenericClient,
+        outbox: &mut Outbox,
     ) {
         // check sensor values
         // 1. find list of sensor that should be monitored from table sensor_triggers
         // 2. match against sensor data present in checkin data object
-        //    2.1 send alerts if necessary
+        //    2.1 open / resolve incidents and send alerts if necessary
 
         use tokio_pg_mapper::FromTokioPostgresRow;
 
-        log_sensor_data(&sensor_data); // log to console
+        log_sensor_data(sensor_data); // log to console
 
-        let stmt_trigger_list = dbconnection.prepare_cached("SELECT sensor_triggers_id, node_id, sensor_id, monitoring_enabled, trigger_notification_sent, validation_function, validation_parameter_1, validation_parameter_2
-	FROM remote_pi_monitor.sensor_triggers where node_id = $1 AND monitoring_enabled = true ;").await.unwrap();
+        let stmt_trigger_list = dbconnection.prepare_cached("SELECT sensor_triggers_id, node_id, sensor_id, monitoring_enabled, validation_function, validation_parameter_1, validation_parameter_2, validation_parameter_text, validation_expression, deadband, deadband_type,
+	fail_after_count, fail_after_seconds, recover_after_count, consecutive_fail_count, consecutive_ok_count, fail_streak_started_at,
+	missing_grace_seconds, missing_since
+	FROM sensor_triggers where node_id = $1 AND monitoring_enabled = true ;").await.unwrap();
         let rows_trigger_list = dbconnection.query(&stmt_trigger_list, &[&node_id_db] ).await.unwrap();
 
 
@@ -38,83 +67,136 @@ use crate::send_email;
         for sensor_trigger_row in rows_trigger_list {
             let sensor_trigger = SensorTrigger::from_row(sensor_trigger_row).unwrap();
                     debug!(
-                    "Trigger check: sensor_triggers_id={} sensor_id={} monitoring_enabled={} trigger_notification_sent={} validation_function={} validation_parameter_1={:?} validation_parameter_2={:?} ",
+                    "Trigger check: sensor_triggers_id={} sensor_id={} monitoring_enabled={} validation_function={} validation_parameter_1={:?} validation_parameter_2={:?} ",
                     sensor_trigger.sensor_triggers_id,
                     sensor_trigger.sensor_id,
                     sensor_trigger.monitoring_enabled,
-                    sensor_trigger.trigger_notification_sent,
                     sensor_trigger.validation_function,
                     sensor_trigger.validation_parameter_1,
                     sensor_trigger.validation_parameter_2);
 
+                    let open_incident = incident_functions::find_open_incident(
+                        dbconnection,
+                        incident_functions::INCIDENT_TYPE_SENSOR_TRIGGER,
+                        node_id_db,
+                        &Some(sensor_trigger.sensor_triggers_id),
+                    ).await;
+
                     // find sensor data in sensor_data list that matches the sensor_trigger and perform validation
                     let mut validation_result: (Option<bool>, String) = (None, "".to_string());
                     let mut sensor_name_email= "".to_string();
+                    let mut sensor_value: Option<f64> = None;
 
-                    // find sensor data in sensor_data list
-                    let sensor_data_found = find_sensor_data_by_id(&sensor_trigger.sensor_id, sensor_data);
+                    // find sensor data in sensor_data list. Composite triggers are not bound to one sensor,
+                    // sensor_id is their name and the sensors are referenced from the expression
+                    let is_composite = sensor_trigger.validation_function == VALIDATION_FUNCTION_COMPOSITE;
+                    let sensor_data_found = if is_composite { None } else { find_sensor_data_by_id(&sensor_trigger.sensor_id, sensor_data) };
 
-                    match sensor_data_found {
-                        None => { // case when sensor data is not present for this trigger
-                            if sensor_trigger.trigger_notification_sent == false { // checking if notification has not been sent already
-                                println!("sensor value not present. Sending notification");
-                                validation_result.0 = Some(false);
-                                validation_result.1 = "Sensor value is missing ".to_string();
-                                sensor_name_email = "".to_string()
-                            }
-                        }
-                        Some(x) => { // case when sensor data IS found and we need to validate the data against trigger validation function + parameters
-                            validation_result = validate_sensor_data(
-                                &sensor_trigger.validation_function,
-                                &sensor_trigger.validation_parameter_1,
-                                &sensor_trigger.validation_parameter_2,
-                                x.value,
-                            );
+                    let missing_sensor_ids = if is_composite {
+                        composite_missing_sensor_ids(&sensor_trigger, sensor_data)
+                    } else if sensor_data_found.is_none() {
+                        vec![sensor_trigger.sensor_id.clone()]
+                    } else {
+                        Vec::new()
+                    };
+
+                    // missing sensors have their own alert state. The validation state is kept until the sensor reports again
+                    sensor_missing_check(
+                        node_id_db,
+                        &sensor_trigger,
+                        &missing_sensor_ids,
+                        sensor_data_found.map(|x| x.sensor_name.as_str()),
+                        node_id_external,
+                        notification_email_list,
+                        node_checkin_timestamp,
+                        dbconnection,
+                        outbox,
+                    ).await;
+                    if !missing_sensor_ids.is_empty() {
+                        continue;
+                    }
+
+                    if is_composite {
+                        let variables = expression_variables(dbconnection, node_id_db, &sensor_trigger, None, sensor_data, node_checkin_timestamp).await;
+                        validation_result = validate_sensor_expression(&sensor_trigger.validation_expression, &variables);
+                        sensor_name_email = sensor_trigger.sensor_id.clone();
+                        debug!("Validation result = {:?}", validation_result.0);
+                        debug!("Validation email message = {}", validation_result.1);
+                    } else if let Some(x) = sensor_data_found { // sensor data IS found and we need to validate the data against trigger validation function + parameters
+                            validation_result = if let Some(validation_parameter_text) = &sensor_trigger.validation_parameter_text {
+                                validate_sensor_text(&sensor_trigger.validation_function, validation_parameter_text, &x.value)
+                            } else if sensor_trigger.validation_function == VALIDATION_FUNCTION_EXPRESSION {
+                                let variables = expression_variables(dbconnection, node_id_db, &sensor_trigger, Some(x), sensor_data, node_checkin_timestamp).await;
+                                validate_sensor_expression(&sensor_trigger.validation_expression, &variables)
+                            } else if let Some(numeric_value) = x.value.as_number() {
+                                validate_numeric_sensor(dbconnection, node_id_db, &sensor_trigger, numeric_value, node_checkin_timestamp).await
+                            } else {
+                                error!("can not validate. sensor value {} is not numeric", x.value);
+                                (None, "".to_string())
+                            };
                             sensor_name_email = x.sensor_name.clone();
+                            sensor_value = x.value.as_number();
                             debug!("Validation result = {:?}", validation_result.0);
                             debug!("Validation email message = {}", validation_result.1);
-                        }
                     }
-                    // send e-mail notifications (if needed)  and update status in DB
-                    if (validation_result.0 == Some(false)) & (sensor_trigger.trigger_notification_sent == false) {
-                        if  notification_email_list != "" {
-                            send_email::sensor_validation_failed_email(
-                                node_id_external,
-                                notification_email_list,
+                    // only sustained failures / recoveries change the incident state
+                    let (debounce_state, debounced_result) = debounce_validation_result(&sensor_trigger, validation_result.0, node_checkin_timestamp);
+                    if validation_result.0.is_some() {
+                        metrics::increment(metrics::TRIGGERS_EVALUATED_TOTAL, &[]);
+                        update_trigger_debounce_state(dbconnection, &sensor_trigger.sensor_triggers_id, &debounce_state).await;
+                    }
+                    debug!("Debounced validation result = {:?} fail count = {} ok count = {}", debounced_result, debounce_state.consecutive_fail_count, debounce_state.consecutive_ok_count);
+
+                    // open / resolve incident and send e-mail notifications (if needed)
+                    match (debounced_result, open_incident) {
+                        (Some(false), None) => {
+                            metrics::increment(metrics::TRIGGERS_FIRED_TOTAL, &[]);
+                            let incident = incident_functions::open_incident(
+                                dbconnection,
+                                incident_functions::INCIDENT_TYPE_SENSOR_TRIGGER,
+                                node_id_db,
+                                &Some(sensor_trigger.sensor_triggers_id),
                                 node_checkin_timestamp,
-                                &validation_result.1,
-                                &sensor_trigger.sensor_id,
-                                &sensor_name_email,
-                                &email_config,
-                                &telegram_config
-                            ).await;
-                            // update DB
-                            let stmt_trigger_notification_status_update = dbconnection.prepare_cached("UPDATE remote_pi_monitor.sensor_triggers SET trigger_notification_sent=true WHERE sensor_triggers_id= $1;").await.unwrap();
-                            let _result = dbconnection.query(&stmt_trigger_notification_status_update, &[&sensor_trigger.sensor_triggers_id] ).await.unwrap();
+                                &sensor_value,
+                            ).await.unwrap();
 
-                        } else {
-                            error!("Can not send notification. recipient list not set");
+                            if !notification_email_list.is_empty() {
+                                outbox.push(send_email::sensor_validation_failed_email(
+                                    node_id_external,
+                                    notification_email_list,
+                                    node_checkin_timestamp,
+                                    &validation_result.1,
+                                    &sensor_trigger.sensor_id,
+                                    &sensor_name_email,
+                                    &incident,
+                                ));
+                            } else {
+                                error!("Can not send notification. recipient list not set");
+                            }
                         }
-                    } else if (validation_result.0 == Some(true)) & (sensor_trigger.trigger_notification_sent == true) {
-                        debug!("sensor value is OK (was not OK) -> send notification");
-                        if notification_email_list != "" {
-                            send_email::sensor_validation_ok_email(
-                                node_id_external,
-                                notification_email_list,
-                                node_checkin_timestamp,
-                                &validation_result.1,
-                                &sensor_trigger.sensor_id,
-                                &sensor_name_email,
-                                &email_config,
-                                &telegram_config
-                            ).await;
-                            // update DB
-                            let stmt_trigger_notification_status_update2 = dbconnection.prepare_cached("UPDATE remote_pi_monitor.sensor_triggers SET trigger_notification_sent=false WHERE sensor_triggers_id= $1;").await.unwrap();
-                            let _result = dbconnection.query(&stmt_trigger_notification_status_update2, &[&sensor_trigger.sensor_triggers_id] ).await.unwrap();
+                        (Some(true), Some(incident)) => {
+                            debug!("sensor value is OK (was not OK) -> resolve incident and send notification");
+                            incident_functions::resolve_incident(dbconnection, &incident.id, node_checkin_timestamp, &sensor_value).await;
 
-                        } else {
-                            error!("Can not send notification. recipient list not set");
+                            if !notification_email_list.is_empty() {
+                                outbox.push(send_email::sensor_validation_ok_email(
+                                    node_id_external,
+                                    notification_email_list,
+                                    node_checkin_timestamp,
+                                    &incident.opened_at,
+                                    &validation_result.1,
+                                    &sensor_trigger.sensor_id,
+                                    &sensor_name_email,
+                                ));
+                            } else {
+                                error!("Can not send notification. recipient list not set");
+                            }
+                        }
+                        (_, Some(incident)) if validation_result.0 == Some(false) => {
+                            // still failing -> keep the latest value on the open incident
+                            incident_functions::update_incident_last_value(dbconnection, &incident.id, &sensor_value).await;
                         }
+                        _ => {}
                     }
                 }
 
@@ -122,6 +204,208 @@ use crate::send_email;
     }
 
 
+    /// Numeric validation functions: stuck value, change functions and the classic comparisons.
+    pub async fn validate_numeric_sensor(
+        dbconnection: &impl GenericClient,
+        node_id_db: &i32,
+        sensor_trigger: &SensorTrigger,
+        sensor_value: f64,
+        node_checkin_timestamp: &DateTime<Utc>,
+    ) -> (Option<bool>, String) {
+        if sensor_trigger.validation_function == VALIDATION_FUNCTION_STUCK {
+            let unchanged_since = match sensor_trigger.validation_parameter_1 {
+                Some(epsilon) => sensor_history::sensor_value_unchanged_since(dbconnection, node_id_db, &sensor_trigger.sensor_id, sensor_value, epsilon, node_checkin_timestamp).await,
+                None => None,
+            };
+            validate_sensor_stuck(
+                &sensor_trigger.validation_parameter_1,
+                &sensor_trigger.validation_parameter_2,
+                sensor_value,
+                &unchanged_since,
+                node_checkin_timestamp,
+            )
+        } else if is_change_validation_function(&sensor_trigger.validation_function) {
+            let reference_value = change_reference_value(dbconnection, node_id_db, sensor_trigger, node_checkin_timestamp).await;
+            validate_sensor_change(
+                &sensor_trigger.validation_function,
+                &sensor_trigger.validation_parameter_1,
+                &sensor_trigger.validation_parameter_2,
+                sensor_trigger.deadband,
+                &sensor_trigger.deadband_type,
+                sensor_value,
+                &reference_value,
+            )
+        } else {
+            validate_sensor_data(
+                &sensor_trigger.validation_function,
+                &sensor_trigger.validation_parameter_1,
+                &sensor_trigger.validation_parameter_2,
+                sensor_trigger.deadband,
+                &sensor_trigger.deadband_type,
+                sensor_value,
+            )
+        }
+    }
+
+
+    /// Opens a 'sensor_missing' incident once the sensor(s) of the trigger are absent from checkins for longer than
+    /// missing_grace_seconds and resolves it with a "reporting again" notification when they are back.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn sensor_missing_check(
+        node_id_db: &i32,
+        sensor_trigger: &SensorTrigger,
+        missing_sensor_ids: &[String],
+        sensor_name: Option<&str>,
+        node_id_external: &str,
+        notification_email_list: &str,
+        node_checkin_timestamp: &DateTime<Utc>,
+        dbconnection: &impl GenericClient,
+        outbox: &mut Outbox,
+    ) {
+        let missing_incident = incident_functions::find_open_incident(
+            dbconnection,
+            incident_functions::INCIDENT_TYPE_SENSOR_MISSING,
+            node_id_db,
+            &Some(sensor_trigger.sensor_triggers_id),
+        ).await;
+
+        if missing_sensor_ids.is_empty() {
+            if sensor_trigger.missing_since.is_some() {
+                update_trigger_missing_since(dbconnection, &sensor_trigger.sensor_triggers_id, &None).await;
+            }
+            if let Some(incident) = missing_incident {
+                debug!("sensor is reporting again -> resolve incident and send notification");
+                incident_functions::resolve_incident(dbconnection, &incident.id, node_checkin_timestamp, &None).await;
+
+                if !notification_email_list.is_empty() {
+                    outbox.push(send_email::sensor_reporting_again_email(
+                        node_id_external,
+                        notification_email_list,
+                        node_checkin_timestamp,
+                        &incident.opened_at,
+                        &sensor_trigger.sensor_id,
+                        sensor_name.unwrap_or(&sensor_trigger.sensor_id),
+                    ));
+                } else {
+                    error!("Can not send notification. recipient list not set");
+                }
+            }
+            return;
+        }
+
+        let missing_since = match sensor_trigger.missing_since {
+            Some(x) => x,
+            None => {
+                update_trigger_missing_since(dbconnection, &sensor_trigger.sensor_triggers_id, &Some(*node_checkin_timestamp)).await;
+                *node_checkin_timestamp
+            }
+        };
+        let missing_seconds = node_checkin_timestamp.signed_duration_since(missing_since).num_seconds();
+        debug!("sensor values not present: {:?} missing for {} seconds", missing_sensor_ids, missing_seconds);
+
+        if missing_incident.is_some() || missing_seconds < i64::from(sensor_trigger.missing_grace_seconds) {
+            return;
+        }
+
+        // last known sensor name and value come from history, the checkin does not have them
+        let last_reading = sensor_history::last_sensor_reading(dbconnection, node_id_db, &missing_sensor_ids[0]).await;
+        let incident = incident_functions::open_incident(
+            dbconnection,
+            incident_functions::INCIDENT_TYPE_SENSOR_MISSING,
+            node_id_db,
+            &Some(sensor_trigger.sensor_triggers_id),
+            node_checkin_timestamp,
+            &last_reading.as_ref().and_then(|(_, value, _)| *value),
+        ).await.unwrap();
+
+        if !notification_email_list.is_empty() {
+            outbox.push(send_email::sensor_missing_email(
+                node_id_external,
+                notification_email_list,
+                &missing_since,
+                &missing_sensor_ids.join(", "),
+                last_reading.as_ref().map(|(name, _, _)| name.as_str()).unwrap_or(""),
+                &last_reading.as_ref().map(|(_, _, timestamp)| *timestamp),
+                &incident,
+            ));
+        } else {
+            error!("Can not send notification. recipient list not set");
+        }
+    }
+
+    pub async fn update_trigger_missing_since(
+        dbconnection: &impl GenericClient,
+        sensor_triggers_id: &i32,
+        missing_since: &Option<DateTime<Utc>>,
+    ) {
+        let stmt_missing_since_update = dbconnection.prepare_cached("UPDATE sensor_triggers SET missing_since = $2 WHERE sensor_triggers_id = $1;").await.unwrap();
+        let _result = dbconnection.query(&stmt_missing_since_update, &[sensor_triggers_id, missing_since]).await.unwrap();
+    }
+
+
+    pub struct TriggerDebounceState {
+        pub consecutive_fail_count: i32,
+        pub consecutive_ok_count: i32,
+        pub fail_streak_started_at: Option<DateTime<Utc>>,
+    }
+
+    /// Counts consecutive failing / OK readings of a trigger. Returns the new counter state and
+    /// Some(false) when the failure is sustained (both fail_after_count readings and fail_after_seconds reached),
+    /// Some(true) after recover_after_count OK readings, None otherwise.
+    /// A reading inside the deadband (validation result None) does not change the counters.
+    pub fn debounce_validation_result(
+        sensor_trigger: &SensorTrigger,
+        validation_result: Option<bool>,
+        checkin_timestamp: &DateTime<Utc>,
+    ) -> (TriggerDebounceState, Option<bool>) {
+        match validation_result {
+            Some(false) => {
+                let consecutive_fail_count = sensor_trigger.consecutive_fail_count.saturating_add(1);
+                let fail_streak_started_at = sensor_trigger.fail_streak_started_at.unwrap_or(*checkin_timestamp);
+                let fail_streak_seconds = checkin_timestamp.signed_duration_since(fail_streak_started_at).num_seconds();
+
+                let failure_sustained = consecutive_fail_count >= sensor_trigger.fail_after_count
+                    && fail_streak_seconds >= i64::from(sensor_trigger.fail_after_seconds);
+
+                (
+                    TriggerDebounceState { consecutive_fail_count, consecutive_ok_count: 0, fail_streak_started_at: Some(fail_streak_started_at) },
+                    if failure_sustained { Some(false) } else { None },
+                )
+            }
+            Some(true) => {
+                let consecutive_ok_count = sensor_trigger.consecutive_ok_count.saturating_add(1);
+
+                (
+                    TriggerDebounceState { consecutive_fail_count: 0, consecutive_ok_count, fail_streak_started_at: None },
+                    if consecutive_ok_count >= sensor_trigger.recover_after_count { Some(true) } else { None },
+                )
+            }
+            None => (
+                TriggerDebounceState {
+                    consecutive_fail_count: sensor_trigger.consecutive_fail_count,
+                    consecutive_ok_count: sensor_trigger.consecutive_ok_count,
+                    fail_streak_started_at: sensor_trigger.fail_streak_started_at,
+                },
+                None,
+            ),
+        }
+    }
+
+    pub async fn update_trigger_debounce_state(
+        dbconnection: &impl GenericClient,
+        sensor_triggers_id: &i32,
+        debounce_state: &TriggerDebounceState,
+    ) {
+        let stmt_debounce_state_update = dbconnection.prepare_cached("UPDATE sensor_triggers SET consecutive_fail_count = $2, consecutive_ok_count = $3, fail_streak_started_at = $4 WHERE sensor_triggers_id = $1;").await.unwrap();
+        let _result = dbconnection.query(&stmt_debounce_state_update, &[
+            sensor_triggers_id,
+            &debounce_state.consecutive_fail_count,
+            &debounce_state.consecutive_ok_count,
+            &debounce_state.fail_streak_started_at,
+        ]).await.unwrap();
+    }
+
+
         pub fn log_sensor_data(sensor_data: &Option<Vec<SensorData>>)
     {
         match sensor_data {
@@ -142,19 +426,19 @@ use crate::send_email;
 
 
         pub fn find_sensor_data_by_id<'a>(
-        trigger_sensor_id: &String,
+        trigger_sensor_id: &str,
         sensor_data: &'a Option<Vec<SensorData>>,
     ) -> Option<&'a SensorData> {
         let mut function_return: Option<&SensorData> = None;
         match sensor_data {
             Some(x) => {
                 for sensor_data_iter in x {
-                    if sensor_data_iter.id == *trigger_sensor_id {
+                    if sensor_data_iter.id == trigger_sensor_id {
                         debug!(
                         "trigger-sensor-id match. Sensor value = {:?}",
                         sensor_data_iter.value
                     );
-                        function_return = Some(&sensor_data_iter);
+                        function_return = Some(sensor_data_iter);
                         break;
                     }
                 }
@@ -167,25 +451,42 @@ use crate::send_email;
 
 
 
+    /// Deadband around a validation parameter. `deadband_type` is 'absolute' (same unit as the sensor value)
+    /// or 'percent' (percentage of the validation parameter).
+    pub fn validation_deadband(
+        deadband: f64,
+        deadband_type: &str,
+        validation_parameter: f64,
+    ) -> f64 {
+        match deadband_type {
+            DEADBAND_TYPE_PERCENT => (validation_parameter * deadband / 100.0).abs(),
+            _ => deadband.abs(),
+        }
+    }
+
+    /// For '>', '<' and 'b' values within the deadband of a limit keep the previous state (None is returned).
+    /// For '==' and '!=' the deadband is the tolerance of the comparison.
     pub fn validate_sensor_data(
-        validation_function: &String,
-        validation_parameter_1: &Option<f32>,
-        validation_parameter_2: &Option<f32>,
-        sensor_value: f32,
+        validation_function: &str,
+        validation_parameter_1: &Option<f64>,
+        validation_parameter_2: &Option<f64>,
+        deadband: f64,
+        deadband_type: &str,
+        sensor_value: f64,
     ) -> (Option<bool>, String) {
         debug!(
-        "Sensor data validation. Function '{}' parameter1 '{:?}' parameter2 '{:?}' sensor value '{}'",
-        *validation_function, validation_parameter_1, validation_parameter_2,sensor_value
+        "Sensor data validation. Function '{}' parameter1 '{:?}' parameter2 '{:?}' deadband '{} {}' sensor value '{}'",
+        validation_function, validation_parameter_1, validation_parameter_2, deadband, deadband_type, sensor_value
     );
 
         let mut validation_result: (Option<bool>, String) = (None, "".to_string());
-        let validation_delta: f32 = 0.05;
 
-        match validation_function.as_str() {
+        match validation_function {
             ">" => {
                 debug!("validation against > ");
                 match *validation_parameter_1 {
                     Some(x) => {
+                        let validation_delta = validation_deadband(deadband, deadband_type, x);
                         if sensor_value > ( x + validation_delta) {
                             validation_result.0 = Some(true); // OK result
                             validation_result.1 = format!(
@@ -204,9 +505,10 @@ use crate::send_email;
                 }
             }
             "<" => {
-                debug!("validation against > ");
+                debug!("validation against < ");
                 match *validation_parameter_1 {
                     Some(x) => {
+                        let validation_delta = validation_deadband(deadband, deadband_type, x);
                         if sensor_value < ( x - validation_delta) {
                             validation_result.0 = Some(true); // OK result
                             validation_result.1 = format!(
@@ -228,39 +530,41 @@ use crate::send_email;
                 debug!("validation against == ");
                 match *validation_parameter_1 {
                     Some(x) => {
-                        if sensor_value == x {
+                        let validation_delta = validation_deadband(deadband, deadband_type, x);
+                        if (sensor_value - x).abs() <= validation_delta {
                             validation_result.0 = Some(true);  // OK result
                             validation_result.1 = format!(
-                                "expected value {} {:?}. Got {}",
-                                validation_function, x, sensor_value
+                                "expected value {} {:?} (+/- {:?}). Got {}",
+                                validation_function, x, validation_delta, sensor_value
                             );
                         } else {
                             validation_result.0 = Some(false); // validation failed
                             validation_result.1 = format!(
-                                "expected value {} {:?}. Got {}",
-                                validation_function, x, sensor_value
+                                "expected value {} {:?} (+/- {:?}). Got {}",
+                                validation_function, x, validation_delta, sensor_value
                             );
                         }
 
                     }
-                    None => debug!("can not validate. parameter missing"),
+                    None => error!("can not validate. parameter missing"),
                 }
             }
             "!=" => {
                 debug!("validation against != ");
                 match *validation_parameter_1 {
                     Some(x) => {
-                        if sensor_value != x {
+                        let validation_delta = validation_deadband(deadband, deadband_type, x);
+                        if (sensor_value - x).abs() > validation_delta {
                             validation_result.0 = Some(true);  // OK result
                             validation_result.1 = format!(
-                                "expected value {} {:?}. Got {}",
-                                validation_function, x, sensor_value
+                                "expected value {} {:?} (+/- {:?}). Got {}",
+                                validation_function, x, validation_delta, sensor_value
                             );
                         } else {
                             validation_result.0 = Some(false); // validation failed
                             validation_result.1 = format!(
-                                "expected value {} {:?}. Got {}",
-                                validation_function, x, sensor_value
+                                "expected value {} {:?} (+/- {:?}). Got {}",
+                                validation_function, x, validation_delta, sensor_value
                             );
                         }
 
@@ -273,12 +577,14 @@ use crate::send_email;
                 match *validation_parameter_1 {
                     Some(x) => match *validation_parameter_2 {
                         Some(y) => {
-                            if (sensor_value > (x + validation_delta )) & (sensor_value < (y - validation_delta) ) {
+                            let validation_delta_1 = validation_deadband(deadband, deadband_type, x);
+                            let validation_delta_2 = validation_deadband(deadband, deadband_type, y);
+                            if (sensor_value > (x + validation_delta_1 )) & (sensor_value < (y - validation_delta_2) ) {
                                 validation_result.0 = Some(true);  // value OK
-                                validation_result.1 = format!("expected {} < Sensor value < {}. Got sensor value = {}", x + validation_delta , y - validation_delta, sensor_value);
-                            } else if (sensor_value < (x - validation_delta)) | (sensor_value > (y + validation_delta ))  {
+                                validation_result.1 = format!("expected {} < Sensor value < {}. Got sensor value = {}", x + validation_delta_1 , y - validation_delta_2, sensor_value);
+                            } else if (sensor_value < (x - validation_delta_1)) | (sensor_value > (y + validation_delta_2 ))  {
                                 validation_result.0 = Some(false);  // check failed
-                                validation_result.1 = format!("Sensor value {} is  < {} OR  > {}", sensor_value, x - validation_delta , y + validation_delta );
+                                validation_result.1 = format!("Sensor value {} is  < {} OR  > {}", sensor_value, x - validation_delta_1 , y + validation_delta_2 );
                             }
 
                         }
@@ -293,5 +599,552 @@ use crate::send_email;
     }
 
 
+    /// '==' and '!=' against validation_parameter_text for boolean and string sensors, e.g. state == "FAULT".
+    /// Booleans compare case-insensitively with "true" / "false", strings exactly, numbers by their decimal text.
+    pub fn validate_sensor_text(
+        validation_function: &str,
+        validation_parameter_text: &str,
+        sensor_value: &SensorValue,
+    ) -> (Option<bool>, String) {
+        debug!(
+        "Sensor text validation. Function '{}' parameter '{}' sensor value '{}'",
+        validation_function, validation_parameter_text, sensor_value
+    );
+
+        let equal = match sensor_value {
+            SensorValue::Boolean(x) => x.to_string().eq_ignore_ascii_case(validation_parameter_text),
+            SensorValue::Text(x) => x == validation_parameter_text,
+            SensorValue::Number(x) => x.to_string() == validation_parameter_text,
+        };
+        let message = format!("expected value {} \"{}\". Got {}", validation_function, validation_parameter_text, sensor_value);
+
+        match validation_function {
+            "==" => (Some(equal), message),
+            "!=" => (Some(!equal), message),
+            &_ => {
+                error!("Validation function '{}' can not compare text", validation_function);
+                (None, "".to_string())
+            }
+        }
+    }
+
+
+    pub fn is_change_validation_function(validation_function: &str) -> bool {
+        matches!(
+            validation_function,
+            VALIDATION_FUNCTION_RATE_OF_CHANGE | VALIDATION_FUNCTION_RATE_OF_CHANGE_PERCENT | VALIDATION_FUNCTION_DELTA | VALIDATION_FUNCTION_DELTA_PERCENT
+        )
+    }
+
+    /// Previous sensor value the change validation functions compare against.
+    pub async fn change_reference_value(
+        dbconnection: &impl GenericClient,
+        node_id_db: &i32,
+        sensor_trigger: &SensorTrigger,
+        node_checkin_timestamp: &DateTime<Utc>,
+    ) -> Option<f64> {
+        let reference = match sensor_trigger.validation_function.as_str() {
+            VALIDATION_FUNCTION_RATE_OF_CHANGE | VALIDATION_FUNCTION_RATE_OF_CHANGE_PERCENT => match sensor_trigger.validation_parameter_2 {
+                Some(window_seconds) => {
+                    let since = *node_checkin_timestamp - chrono::Duration::seconds(window_seconds as i64);
+                    sensor_history::oldest_sensor_value_since(dbconnection, node_id_db, &sensor_trigger.sensor_id, &since, node_checkin_timestamp).await
+                }
+                None => None,
+            },
+            _ => sensor_history::previous_sensor_value(dbconnection, node_id_db, &sensor_trigger.sensor_id, node_checkin_timestamp).await,
+        };
+        debug!("Change reference value = {:?}", reference);
+
+        reference.map(|(value, _reading_timestamp)| value)
+    }
+
+    /// Validates the change of the sensor value against the reference value. Percent functions express the change
+    /// as a percentage of the reference value. The deadband is applied around the allowed change like for '>' and '<'.
+    pub fn validate_sensor_change(
+        validation_function: &str,
+        validation_parameter_1: &Option<f64>,
+        validation_parameter_2: &Option<f64>,
+        deadband: f64,
+        deadband_type: &str,
+        sensor_value: f64,
+        reference_value: &Option<f64>,
+    ) -> (Option<bool>, String) {
+        debug!(
+        "Sensor change validation. Function '{}' parameter1 '{:?}' parameter2 '{:?}' deadband '{} {}' sensor value '{}' reference value '{:?}'",
+        validation_function, validation_parameter_1, validation_parameter_2, deadband, deadband_type, sensor_value, reference_value
+    );
+
+        let mut validation_result: (Option<bool>, String) = (None, "".to_string());
+
+        let (x, reference) = match (*validation_parameter_1, *reference_value) {
+            (Some(x), Some(reference)) => (x, reference),
+            (None, _) => {
+                error!("can not validate. parameter missing");
+                return validation_result;
+            }
+            (_, None) => {
+                debug!("can not validate. no previous sensor value");
+                return validation_result;
+            }
+        };
+
+        let percent = matches!(validation_function, VALIDATION_FUNCTION_RATE_OF_CHANGE_PERCENT | VALIDATION_FUNCTION_DELTA_PERCENT);
+        let change = if percent {
+            if reference == 0.0 {
+                debug!("can not validate. percentage change from 0");
+                return validation_result;
+            }
+            (sensor_value - reference) / reference.abs() * 100.0
+        } else {
+            sensor_value - reference
+        };
+        let unit = if percent { "%" } else { "" };
+        let period = match validation_function {
+            VALIDATION_FUNCTION_RATE_OF_CHANGE | VALIDATION_FUNCTION_RATE_OF_CHANGE_PERCENT => format!("in {} seconds", validation_parameter_2.unwrap_or(0.0)),
+            _ => "since previous checkin".to_string(),
+        };
+
+        let validation_delta = validation_deadband(deadband, deadband_type, x);
+        let (change_ok, change_failed) = if x >= 0.0 {
+            (change < x - validation_delta, change > x + validation_delta)
+        } else {
+            (change > x + validation_delta, change < x - validation_delta)
+        };
+
+        let message = format!(
+            "expected {} of at most {}{} {}. Got change {}{} (from {} to {})",
+            if x >= 0.0 { "rise" } else { "drop" }, x.abs(), unit, period, change, unit, reference, sensor_value
+        );
+        if change_ok {
+            validation_result = (Some(true), message);
+        } else if change_failed {
+            validation_result = (Some(false), message);
+        }
+        validation_result
+    }
+
+
+
+    /// Variables available to expression triggers: `value` of the trigger sensor, values of all payload sensors
+    /// by sensor name and by sensor("id"), delta("id") changes since the previous checkin and the history
+    /// aggregates referenced by the expression. Composite triggers have no trigger sensor, so no `value` or aggregates.
+    /// Boolean sensors are 1 / 0, string sensors are not available as variables.
+    pub async fn expression_variables(
+        dbconnection: &impl GenericClient,
+        node_id_db: &i32,
+        sensor_trigger: &SensorTrigger,
+        trigger_sensor_data: Option<&SensorData>,
+        sensor_data: &Option<Vec<SensorData>>,
+        node_checkin_timestamp: &DateTime<Utc>,
+    ) -> HashMap<String, f64> {
+        let mut variables: HashMap<String, f64> = HashMap::new();
+
+        for sensor_value in sensor_data.iter().flatten() {
+            if let Some(numeric_value) = sensor_value.value.as_number() {
+                variables.insert(sensor_value.sensor_name.clone(), numeric_value);
+                variables.insert(trigger_expression::sensor_variable(&sensor_value.id), numeric_value);
+            }
+        }
+
+        let expression = match trigger_expression::parse(sensor_trigger.validation_expression.as_deref().unwrap_or_default()) {
+            Ok(expression) => expression,
+            Err(_) => return variables,
+        };
+
+        for delta_sensor_id in expression.delta_sensor_ids() {
+            let current_value = find_sensor_data_by_id(&delta_sensor_id, sensor_data);
+            let previous_value = sensor_history::previous_sensor_value(dbconnection, node_id_db, &delta_sensor_id, node_checkin_timestamp).await;
+            if let (Some(current_value), Some((previous_value, _))) = (current_value.and_then(|x| x.value.as_number()), previous_value) {
+                variables.insert(trigger_expression::delta_variable(&delta_sensor_id), current_value - previous_value);
+            }
+        }
+
+        let trigger_sensor_data = match trigger_sensor_data {
+            Some(x) => x,
+            None => return variables,
+        };
+        if let Some(numeric_value) = trigger_sensor_data.value.as_number() {
+            variables.insert("value".to_string(), numeric_value);
+        }
+
+        for history_aggregate in expression.history_aggregates() {
+            let since = *node_checkin_timestamp - chrono::Duration::seconds(history_aggregate.window_seconds);
+            let aggregate_value = sensor_history::sensor_value_aggregate(
+                dbconnection,
+                node_id_db,
+                &sensor_trigger.sensor_id,
+                &history_aggregate.function,
+                &since,
+                node_checkin_timestamp,
+            ).await;
+            if let Some(aggregate_value) = aggregate_value {
+                variables.insert(history_aggregate.identifier, aggregate_value);
+            }
+        }
+        variables
+    }
+
+    /// Fails when the sensor value has not changed by more than epsilon (validation_parameter_1) for at least
+    /// validation_parameter_2 seconds. `unchanged_since` comes from the stored history (None = no history yet).
+    pub fn validate_sensor_stuck(
+        validation_parameter_1: &Option<f64>,
+        validation_parameter_2: &Option<f64>,
+        sensor_value: f64,
+        unchanged_since: &Option<DateTime<Utc>>,
+        node_checkin_timestamp: &DateTime<Utc>,
+    ) -> (Option<bool>, String) {
+        debug!(
+        "Sensor stuck value validation. epsilon '{:?}' duration '{:?}' sensor value '{}' unchanged since '{:?}'",
+        validation_parameter_1, validation_parameter_2, sensor_value, unchanged_since
+    );
+
+        let (epsilon, duration_seconds) = match (*validation_parameter_1, *validation_parameter_2) {
+            (Some(x), Some(y)) => (x, y as i64),
+            _ => {
+                error!("can not validate. parameter missing");
+                return (None, "".to_string());
+            }
+        };
+        let unchanged_seconds = match unchanged_since {
+            Some(x) => node_checkin_timestamp.signed_duration_since(*x).num_seconds(),
+            None => 0,
+        };
+
+        let message = format!(
+            "expected sensor value to change by more than {} within {} seconds. Value {} unchanged for {} seconds",
+            epsilon, duration_seconds, sensor_value, unchanged_seconds
+        );
+        (Some(unchanged_seconds < duration_seconds), message)
+    }
+
+    /// Sensor ids referenced by the composite trigger expression that are not present in the checkin.
+    pub fn composite_missing_sensor_ids(
+        sensor_trigger: &SensorTrigger,
+        sensor_data: &Option<Vec<SensorData>>,
+    ) -> Vec<String> {
+        match trigger_expression::parse(sensor_trigger.validation_expression.as_deref().unwrap_or_default()) {
+            Ok(expression) => expression
+                .sensor_ids()
+                .into_iter()
+                .filter(|sensor_id| find_sensor_data_by_id(sensor_id, sensor_data).is_none())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Validation fails when the alert condition is true. The message lists the sub-expressions that decided the result.
+    pub fn validate_sensor_expression(
+        validation_expression: &Option<String>,
+        variables: &HashMap<String, f64>,
+    ) -> (Option<bool>, String) {
+        debug!("Sensor expression validation. Expression '{:?}' variables '{:?}'", validation_expression, variables);
+
+        let expression = match validation_expression.as_deref().map(trigger_expression::parse_condition) {
+            Some(Ok(expression)) => expression,
+            Some(Err(e)) => {
+                error!("can not validate. invalid expression: {}", e);
+                return (None, "".to_string());
+            }
+            None => {
+                error!("can not validate. expression missing");
+                return (None, "".to_string());
+            }
+        };
+
+        match expression.evaluate(variables) {
+            Ok(trigger_expression::Value::Bool(alert)) => (
+                Some(!alert),
+                format!("alert condition {} is {}: {}", validation_expression.as_deref().unwrap_or_default(), alert, expression.explain(variables)),
+            ),
+            Ok(_) => (None, "".to_string()),
+            Err(e) => {
+                error!("can not validate. expression evaluation failed: {}", e);
+                (None, "".to_string())
+            }
+        }
+    }
+
+
+
+    /// Checks a trigger definition before it is stored. Expression parse errors are reported with their position.
+    pub fn check_trigger_definition(new_sensor_trigger: &NewSensorTrigger) -> Result<(), String> {
+        let validation_function = new_sensor_trigger.validation_function.as_str();
+        if !VALIDATION_FUNCTIONS.contains(&validation_function) {
+            return Err(format!("unknown validation_function '{}'. Expected one of {:?}", validation_function, VALIDATION_FUNCTIONS));
+        }
+
+        if validation_function == VALIDATION_FUNCTION_EXPRESSION || validation_function == VALIDATION_FUNCTION_COMPOSITE {
+            let expression = match &new_sensor_trigger.validation_expression {
+                Some(validation_expression) => trigger_expression::parse_condition(validation_expression)
+                    .map_err(|e| format!("invalid validation_expression: {}", e))?,
+                None => return Err(format!("validation_expression is required for validation_function '{}'", validation_function)),
+            };
+            if validation_function == VALIDATION_FUNCTION_COMPOSITE {
+                if expression.sensor_ids().is_empty() {
+                    return Err("validation_expression of a composite trigger must reference sensors with sensor(\"id\") or delta(\"id\")".to_string());
+                }
+                if !expression.history_aggregates().is_empty() || expression.identifiers().iter().any(|x| x == "value") {
+                    return Err("composite trigger has no own sensor. Use sensor(\"id\") instead of value and history aggregates".to_string());
+                }
+            }
+        } else if new_sensor_trigger.validation_parameter_text.is_some() {
+            if validation_function != "==" && validation_function != "!=" {
+                return Err(format!("validation_parameter_text can only be compared with '==' or '!=', not '{}'", validation_function));
+            }
+        } else if new_sensor_trigger.validation_parameter_1.is_none() {
+            return Err(format!("validation_parameter_1 is required for validation_function '{}'", validation_function));
+        }
+
+        let needs_parameter_2 = matches!(validation_function, "b" | VALIDATION_FUNCTION_RATE_OF_CHANGE | VALIDATION_FUNCTION_RATE_OF_CHANGE_PERCENT | VALIDATION_FUNCTION_STUCK);
+        if needs_parameter_2 && new_sensor_trigger.validation_parameter_2.is_none() {
+            return Err(format!("validation_parameter_2 is required for validation_function '{}'", validation_function));
+        }
+
+        if let Some(deadband_type) = &new_sensor_trigger.deadband_type {
+            if deadband_type != DEADBAND_TYPE_ABSOLUTE && deadband_type != DEADBAND_TYPE_PERCENT {
+                return Err(format!("unknown deadband_type '{}'. Expected '{}' or '{}'", deadband_type, DEADBAND_TYPE_ABSOLUTE, DEADBAND_TYPE_PERCENT));
+            }
+        }
+        Ok(())
+    }
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABSOLUTE: &str = DEADBAND_TYPE_ABSOLUTE;
+
+    fn result_of(validation_function: &str, parameter_1: Option<f64>, parameter_2: Option<f64>, deadband: f64, deadband_type: &str, sensor_value: f64) -> Option<bool> {
+        validate_sensor_data(validation_function, &parameter_1, &parameter_2, deadband, deadband_type, sensor_value).0
+    }
+
+    #[test]
+    fn deadband_absolute_and_percent() {
+        assert_eq!(validation_deadband(0.5, ABSOLUTE, 1000.0), 0.5);
+        assert_eq!(validation_deadband(-0.5, ABSOLUTE, 1000.0), 0.5);
+        assert_eq!(validation_deadband(10.0, DEADBAND_TYPE_PERCENT, 20.0), 2.0);
+        assert_eq!(validation_deadband(10.0, DEADBAND_TYPE_PERCENT, -20.0), 2.0);
+        assert_eq!(validation_deadband(10.0, DEADBAND_TYPE_PERCENT, 0.0), 0.0);
+    }
+
+    #[test]
+    fn greater_than_boundaries() {
+        assert_eq!(result_of(">", Some(10.0), None, 0.5, ABSOLUTE, 10.75), Some(true));
+        assert_eq!(result_of(">", Some(10.0), None, 0.5, ABSOLUTE, 10.5), None);
+        assert_eq!(result_of(">", Some(10.0), None, 0.5, ABSOLUTE, 10.0), None);
+        assert_eq!(result_of(">", Some(10.0), None, 0.5, ABSOLUTE, 9.5), None);
+        assert_eq!(result_of(">", Some(10.0), None, 0.5, ABSOLUTE, 9.25), Some(false));
+    }
+
+    #[test]
+    fn less_than_boundaries() {
+        assert_eq!(result_of("<", Some(10.0), None, 0.5, ABSOLUTE, 9.25), Some(true));
+        assert_eq!(result_of("<", Some(10.0), None, 0.5, ABSOLUTE, 9.5), None);
+        assert_eq!(result_of("<", Some(10.0), None, 0.5, ABSOLUTE, 10.0), None);
+        assert_eq!(result_of("<", Some(10.0), None, 0.5, ABSOLUTE, 10.5), None);
+        assert_eq!(result_of("<", Some(10.0), None, 0.5, ABSOLUTE, 10.75), Some(false));
+    }
+
+    #[test]
+    fn equal_boundaries() {
+        assert_eq!(result_of("==", Some(1.0), None, 0.0, ABSOLUTE, 1.0), Some(true));
+        assert_eq!(result_of("==", Some(1.0), None, 0.0, ABSOLUTE, 1.5), Some(false));
+        assert_eq!(result_of("==", Some(10.0), None, 0.5, ABSOLUTE, 10.5), Some(true));
+        assert_eq!(result_of("==", Some(10.0), None, 0.5, ABSOLUTE, 9.5), Some(true));
+        assert_eq!(result_of("==", Some(10.0), None, 0.5, ABSOLUTE, 10.75), Some(false));
+        assert_eq!(result_of("==", Some(10.0), None, 0.5, ABSOLUTE, 9.25), Some(false));
+    }
+
+    #[test]
+    fn not_equal_boundaries() {
+        assert_eq!(result_of("!=", Some(1.0), None, 0.0, ABSOLUTE, 1.0), Some(false));
+        assert_eq!(result_of("!=", Some(1.0), None, 0.0, ABSOLUTE, 1.5), Some(true));
+        assert_eq!(result_of("!=", Some(10.0), None, 0.5, ABSOLUTE, 10.5), Some(false));
+        assert_eq!(result_of("!=", Some(10.0), None, 0.5, ABSOLUTE, 9.5), Some(false));
+        assert_eq!(result_of("!=", Some(10.0), None, 0.5, ABSOLUTE, 10.75), Some(true));
+        assert_eq!(result_of("!=", Some(10.0), None, 0.5, ABSOLUTE, 9.25), Some(true));
+    }
+
+    #[test]
+    fn between_boundaries() {
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 15.0), Some(true));
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 10.75), Some(true));
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 10.5), None);
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 9.5), None);
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 9.25), Some(false));
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 19.25), Some(true));
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 19.5), None);
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 20.5), None);
+        assert_eq!(result_of("b", Some(10.0), Some(20.0), 0.5, ABSOLUTE, 20.75), Some(false));
+    }
+
+    #[test]
+    fn between_percent_deadband_per_limit() {
+        // 10% of 10 = 1, 10% of 1000 = 100
+        assert_eq!(result_of("b", Some(10.0), Some(1000.0), 10.0, DEADBAND_TYPE_PERCENT, 11.0), None);
+        assert_eq!(result_of("b", Some(10.0), Some(1000.0), 10.0, DEADBAND_TYPE_PERCENT, 11.5), Some(true));
+        assert_eq!(result_of("b", Some(10.0), Some(1000.0), 10.0, DEADBAND_TYPE_PERCENT, 8.5), Some(false));
+        assert_eq!(result_of("b", Some(10.0), Some(1000.0), 10.0, DEADBAND_TYPE_PERCENT, 900.0), None);
+        assert_eq!(result_of("b", Some(10.0), Some(1000.0), 10.0, DEADBAND_TYPE_PERCENT, 1100.0), None);
+        assert_eq!(result_of("b", Some(10.0), Some(1000.0), 10.0, DEADBAND_TYPE_PERCENT, 1100.5), Some(false));
+    }
+
+    #[test]
+    fn greater_than_percent_deadband() {
+        // hPa sensor: 2% of 1000 = 20
+        assert_eq!(result_of(">", Some(1000.0), None, 2.0, DEADBAND_TYPE_PERCENT, 1020.0), None);
+        assert_eq!(result_of(">", Some(1000.0), None, 2.0, DEADBAND_TYPE_PERCENT, 1021.0), Some(true));
+        assert_eq!(result_of(">", Some(1000.0), None, 2.0, DEADBAND_TYPE_PERCENT, 980.0), None);
+        assert_eq!(result_of(">", Some(1000.0), None, 2.0, DEADBAND_TYPE_PERCENT, 979.0), Some(false));
+    }
+
+    #[test]
+    fn missing_parameters_and_unknown_function() {
+        assert_eq!(result_of(">", None, None, 0.5, ABSOLUTE, 1.0), None);
+        assert_eq!(result_of("<", None, None, 0.5, ABSOLUTE, 1.0), None);
+        assert_eq!(result_of("==", None, None, 0.5, ABSOLUTE, 1.0), None);
+        assert_eq!(result_of("!=", None, None, 0.5, ABSOLUTE, 1.0), None);
+        assert_eq!(result_of("b", Some(1.0), None, 0.5, ABSOLUTE, 1.0), None);
+        assert_eq!(result_of("b", None, Some(1.0), 0.5, ABSOLUTE, 1.0), None);
+        assert_eq!(result_of("?", Some(1.0), Some(2.0), 0.5, ABSOLUTE, 1.0), None);
+    }
+
+    fn debounce_trigger(fail_after_count: i32, fail_after_seconds: i32, recover_after_count: i32) -> SensorTrigger {
+        SensorTrigger {
+            sensor_triggers_id: 1,
+            node_id: 1,
+            sensor_id: "28-000001".to_string(),
+            monitoring_enabled: true,
+            validation_function: ">".to_string(),
+            validation_parameter_1: Some(10.0),
+            validation_parameter_2: None,
+            validation_parameter_text: None,
+            validation_expression: None,
+            deadband: 0.05,
+            deadband_type: ABSOLUTE.to_string(),
+            fail_after_count,
+            fail_after_seconds,
+            recover_after_count,
+            consecutive_fail_count: 0,
+            consecutive_ok_count: 0,
+            fail_streak_started_at: None,
+            missing_grace_seconds: 0,
+            missing_since: None,
+        }
+    }
+
+    fn apply(sensor_trigger: &mut SensorTrigger, validation_result: Option<bool>, checkin_timestamp: &DateTime<Utc>) -> Option<bool> {
+        let (state, result) = debounce_validation_result(sensor_trigger, validation_result, checkin_timestamp);
+        sensor_trigger.consecutive_fail_count = state.consecutive_fail_count;
+        sensor_trigger.consecutive_ok_count = state.consecutive_ok_count;
+        sensor_trigger.fail_streak_started_at = state.fail_streak_started_at;
+        result
+    }
+
+    #[test]
+    fn debounce_defaults_fire_immediately() {
+        let now = Utc::now();
+        let mut sensor_trigger = debounce_trigger(1, 0, 1);
+        assert_eq!(apply(&mut sensor_trigger, Some(false), &now), Some(false));
+        assert_eq!(apply(&mut sensor_trigger, Some(true), &now), Some(true));
+    }
+
+    #[test]
+    fn debounce_fail_after_count() {
+        let now = Utc::now();
+        let mut sensor_trigger = debounce_trigger(3, 0, 2);
+        assert_eq!(apply(&mut sensor_trigger, Some(false), &now), None);
+        assert_eq!(apply(&mut sensor_trigger, Some(false), &now), None);
+        assert_eq!(apply(&mut sensor_trigger, None, &now), None); // deadband keeps the count
+        assert_eq!(apply(&mut sensor_trigger, Some(false), &now), Some(false));
+        assert_eq!(apply(&mut sensor_trigger, Some(true), &now), None);
+        assert_eq!(apply(&mut sensor_trigger, Some(false), &now), None); // noisy reading resets recovery
+        assert_eq!(sensor_trigger.consecutive_fail_count, 1);
+        assert_eq!(apply(&mut sensor_trigger, Some(true), &now), None);
+        assert_eq!(apply(&mut sensor_trigger, Some(true), &now), Some(true));
+    }
+
+    #[test]
+    fn debounce_fail_after_seconds() {
+        let start = Utc::now();
+        let mut sensor_trigger = debounce_trigger(1, 600, 1);
+        assert_eq!(apply(&mut sensor_trigger, Some(false), &start), None);
+        assert_eq!(apply(&mut sensor_trigger, Some(false), &(start + chrono::Duration::seconds(599))), None);
+        assert_eq!(apply(&mut sensor_trigger, Some(false), &(start + chrono::Duration::seconds(600))), Some(false));
+        assert_eq!(sensor_trigger.fail_streak_started_at, Some(start));
+        assert_eq!(apply(&mut sensor_trigger, Some(true), &(start + chrono::Duration::seconds(700))), Some(true));
+        assert_eq!(sensor_trigger.fail_streak_started_at, None);
+    }
+
+    fn change_result_of(validation_function: &str, limit: f64, deadband: f64, sensor_value: f64, reference_value: Option<f64>) -> Option<bool> {
+        validate_sensor_change(validation_function, &Some(limit), &Some(600.0), deadband, ABSOLUTE, sensor_value, &reference_value).0
+    }
+
+    #[test]
+    fn change_rise_boundaries() {
+        // "temperature rose more than 3 degrees"
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_RATE_OF_CHANGE, 3.0, 0.5, 22.25, Some(20.0)), Some(true));
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_RATE_OF_CHANGE, 3.0, 0.5, 22.5, Some(20.0)), None);
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_RATE_OF_CHANGE, 3.0, 0.5, 23.5, Some(20.0)), None);
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_RATE_OF_CHANGE, 3.0, 0.5, 23.75, Some(20.0)), Some(false));
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_RATE_OF_CHANGE, 3.0, 0.5, 10.0, Some(20.0)), Some(true));
+    }
+
+    #[test]
+    fn change_drop_percent_boundaries() {
+        // "tank level dropped more than 20% since last checkin"
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_DELTA_PERCENT, -20.0, 0.0, 81.0, Some(100.0)), Some(true));
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_DELTA_PERCENT, -20.0, 0.0, 80.0, Some(100.0)), None);
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_DELTA_PERCENT, -20.0, 0.0, 79.0, Some(100.0)), Some(false));
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_DELTA_PERCENT, -20.0, 0.0, 150.0, Some(100.0)), Some(true));
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_DELTA, -20.0, 0.0, 79.0, Some(100.0)), Some(false));
+    }
 
-        
\ No newline at end of file
+    #[test]
+    fn change_without_reference() {
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_DELTA, 1.0, 0.0, 10.0, None), None);
+        assert_eq!(change_result_of(VALIDATION_FUNCTION_DELTA_PERCENT, 1.0, 0.0, 10.0, Some(0.0)), None);
+        assert_eq!(validate_sensor_change(VALIDATION_FUNCTION_DELTA, &None, &None, 0.0, ABSOLUTE, 10.0, &Some(1.0)).0, None);
+    }
+
+    #[test]
+    fn expression_alert_condition_fails_validation() {
+        let expression = Some("value < 5 || value > 30".to_string());
+        let variables = |value: f64| HashMap::from([("value".to_string(), value)]);
+
+        let (result, message) = validate_sensor_expression(&expression, &variables(31.0));
+        assert_eq!(result, Some(false));
+        assert_eq!(message, "alert condition value < 5 || value > 30 is true: value > 30 is true (31 > 30)");
+        assert_eq!(validate_sensor_expression(&expression, &variables(20.0)).0, Some(true));
+        assert_eq!(validate_sensor_expression(&Some("humidity > 80".to_string()), &variables(20.0)).0, None);
+        assert_eq!(validate_sensor_expression(&Some("value >".to_string()), &variables(20.0)).0, None);
+        assert_eq!(validate_sensor_expression(&None, &variables(20.0)).0, None);
+    }
+
+    #[test]
+    fn stuck_value_boundaries() {
+        let now = Utc::now();
+        let since = |seconds: i64| Some(now - chrono::Duration::seconds(seconds));
+
+        assert_eq!(validate_sensor_stuck(&Some(0.1), &Some(3600.0), 21.5, &since(3599), &now).0, Some(true));
+        assert_eq!(validate_sensor_stuck(&Some(0.1), &Some(3600.0), 21.5, &since(3600), &now).0, Some(false));
+        assert_eq!(validate_sensor_stuck(&Some(0.1), &Some(3600.0), 21.5, &None, &now).0, Some(true));
+        assert_eq!(validate_sensor_stuck(&Some(0.1), &None, 21.5, &since(7200), &now).0, None);
+    }
+
+    #[test]
+    fn text_and_boolean_comparison() {
+        let fault = SensorValue::Text("FAULT".to_string());
+
+        assert_eq!(validate_sensor_text("!=", "FAULT", &fault).0, Some(false));
+        assert_eq!(validate_sensor_text("==", "FAULT", &fault).0, Some(true));
+        assert_eq!(validate_sensor_text("==", "fault", &fault).0, Some(false));
+        assert_eq!(validate_sensor_text("==", "TRUE", &SensorValue::Boolean(true)).0, Some(true));
+        assert_eq!(validate_sensor_text("==", "false", &SensorValue::Boolean(true)).0, Some(false));
+        assert_eq!(validate_sensor_text(">", "FAULT", &fault).0, None);
+        assert_eq!(SensorValue::Boolean(true).as_number(), Some(1.0));
+        assert_eq!(fault.as_number(), None);
+    }
+}
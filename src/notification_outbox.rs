@@ -0,0 +1,140 @@
+use crate::models::{Email, IncidentConfig, TelegramConfig};
+use actix_web::web;
+
+use log::debug;
+use log::warn;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::send_email;
+
+
+// notifications queued in outboxes and not sent yet
+static BACKLOG: AtomicUsize = AtomicUsize::new(0);
+
+/// Notifications waiting to be sent, reported by /readyz and /metrics. Grows while sends are slow or hang.
+pub fn backlog() -> usize {
+    BACKLOG.load(Ordering::Relaxed)
+}
+
+/// Composed notification. `incident_id` adds a per-recipient acknowledge link.
+pub struct PendingEmail {
+    pub recipient_list: String,
+    pub subject: String,
+    pub body_plain: String,
+    pub body_html: String,
+    pub incident_id: Option<i32>,
+}
+
+/// Notifications of a checkin. They are collected while the checkin is processed and sent after its transaction
+/// is committed, a failed send can not leave incidents or trigger states half-updated.
+#[derive(Default)]
+pub struct Outbox {
+    emails: Vec<PendingEmail>,
+}
+
+impl Outbox {
+    pub fn push(&mut self, email: PendingEmail) {
+        BACKLOG.fetch_add(1, Ordering::Relaxed);
+        self.emails.push(email);
+    }
+
+    pub fn len(&self) -> usize {
+        self.emails.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.emails.is_empty()
+    }
+
+    pub async fn send(
+        mut self,
+        email_config: &web::Data<Email>,
+        telegram_config: &web::Data<TelegramConfig>,
+        incident_config: &web::Data<IncidentConfig>,
+    ) {
+        debug!("sending {} queued notifications", self.emails.len());
+        let mut failed = 0;
+        for email in std::mem::take(&mut self.emails) {
+            let result = match email.incident_id {
+                Some(incident_id) => send_email::send_incident_email(
+                    &email.recipient_list,
+                    &email.subject,
+                    &email.body_plain,
+                    &email.body_html,
+                    &incident_id,
+                    email_config,
+                    telegram_config,
+                    incident_config,
+                ).await,
+                None => send_email::send_email_generic(
+                    &email.recipient_list,
+                    &email.subject,
+                    &email.body_plain,
+                    &email.body_html,
+                    email_config,
+                    telegram_config,
+                ).await,
+            };
+            BACKLOG.fetch_sub(1, Ordering::Relaxed);
+            // the reason is logged by send_email_generic
+            if result.is_err() {
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            warn!("{} notifications could not be sent", failed);
+        }
+    }
+}
+
+// an outbox of a rolled back checkin is dropped without sending
+impl Drop for Outbox {
+    fn drop(&mut self) {
+        BACKLOG.fetch_sub(self.emails.len(), Ordering::Relaxed);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(incident_id: Option<i32>) -> PendingEmail {
+        PendingEmail {
+            recipient_list: "ops@example.com".to_string(),
+            subject: "Node OFF-line: pi-1".to_string(),
+            body_plain: "".to_string(),
+            body_html: "".to_string(),
+            incident_id,
+        }
+    }
+
+    // one test: the backlog counter is shared by the whole process
+    #[actix_web::test]
+    async fn queued_until_sent_or_dropped() {
+        let backlog_before = backlog();
+
+        // a committed checkin: queued, nothing is sent until send()
+        let mut outbox = Outbox::default();
+        assert!(outbox.is_empty());
+        outbox.push(email(Some(1)));
+        outbox.push(email(None));
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(backlog(), backlog_before + 2);
+
+        // email is not configured, both sends fail and leave the backlog
+        outbox.send(
+            &web::Data::new(Email::default()),
+            &web::Data::new(TelegramConfig::default()),
+            &web::Data::new(IncidentConfig::default()),
+        ).await;
+        assert_eq!(backlog(), backlog_before);
+
+        // a rolled back checkin: the outbox is dropped unsent
+        let mut outbox = Outbox::default();
+        outbox.push(email(Some(2)));
+        assert_eq!(backlog(), backlog_before + 1);
+        drop(outbox);
+        assert_eq!(backlog(), backlog_before);
+    }
+}
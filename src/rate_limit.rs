@@ -0,0 +1,235 @@
+use crate::models::{RateLimitConfig, RejectedRequests};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+// least recently used buckets are dropped once this many are tracked, so rotating api keys / node ids
+// can not grow the map without limit
+const MAX_TRACKED_BUCKETS: usize = 10000;
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub updated_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn full(burst: f64, now: Instant) -> TokenBucket {
+        TokenBucket { tokens: burst, updated_at: now }
+    }
+
+    /// Tokens available at `now`, refilled with `per_second` up to `burst`.
+    pub fn refill(&self, per_second: f64, burst: f64, now: Instant) -> TokenBucket {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        TokenBucket { tokens: (self.tokens + elapsed * per_second).min(burst), updated_at: now }
+    }
+
+    /// Time until one token is available. Zero when a token is available now.
+    pub fn wait_time(&self, per_second: f64) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / per_second)
+        }
+    }
+}
+
+/// Token buckets per API key and per node of /checkin, with a count of rejected requests per API key.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    max_tracked_buckets: usize,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+    rejected: Mutex<HashMap<String, u64>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> RateLimiter {
+        RateLimiter { config, max_tracked_buckets: MAX_TRACKED_BUCKETS, buckets: Mutex::new(HashMap::new()), rejected: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a token from the API key bucket and, when `node_id` is set, from the node bucket.
+    /// Returns the Retry-After seconds when one of them is empty; no token is taken then.
+    pub fn check(&self, api_key: &str, node_id: Option<&str>) -> Result<(), u64> {
+        self.check_at(api_key, node_id, Instant::now())
+    }
+
+    pub fn check_at(&self, api_key: &str, node_id: Option<&str>, now: Instant) -> Result<(), u64> {
+        let mut limits = vec![(format!("key:{}", api_key), self.config.api_key_per_second, self.config.api_key_burst)];
+        if let Some(node_id) = node_id {
+            limits.push(self.node_limit(api_key, node_id));
+        }
+        self.take_tokens(api_key, limits, now)
+    }
+
+    /// Takes a token from the node bucket only. Batch checkins take one API key token per request and one node token
+    /// per checkin.
+    pub fn check_node(&self, api_key: &str, node_id: &str) -> Result<(), u64> {
+        self.check_node_at(api_key, node_id, Instant::now())
+    }
+
+    pub fn check_node_at(&self, api_key: &str, node_id: &str, now: Instant) -> Result<(), u64> {
+        self.take_tokens(api_key, vec![self.node_limit(api_key, node_id)], now)
+    }
+
+    fn node_limit(&self, api_key: &str, node_id: &str) -> (String, f64, f64) {
+        (format!("node:{}:{}", api_key, node_id), self.config.node_per_second, self.config.node_burst)
+    }
+
+    fn take_tokens(&self, api_key: &str, limits: Vec<(String, f64, f64)>, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() + limits.len() > self.max_tracked_buckets {
+            // updated_at is the last use of a bucket, rejected requests update it too
+            let mut last_used: Vec<(Instant, String)> = buckets.iter().map(|(key, bucket)| (bucket.updated_at, key.clone())).collect();
+            last_used.sort();
+            let evict_count = (buckets.len() + limits.len()).saturating_sub(self.max_tracked_buckets - self.max_tracked_buckets / 10);
+            for (_, key) in last_used.into_iter().filter(|(_, key)| !limits.iter().any(|(x, _, _)| x == key)).take(evict_count) {
+                buckets.remove(&key);
+            }
+        }
+
+        let refilled: Vec<TokenBucket> = limits
+            .iter()
+            .map(|(key, per_second, burst)| match buckets.get(key) {
+                Some(bucket) => bucket.refill(*per_second, *burst, now),
+                None => TokenBucket::full(*burst, now),
+            })
+            .collect();
+        let wait_time = refilled
+            .iter()
+            .zip(limits.iter())
+            .map(|(bucket, (_, per_second, _))| bucket.wait_time(*per_second))
+            .max()
+            .unwrap_or(Duration::ZERO);
+
+        if wait_time.is_zero() {
+            for ((key, _, _), bucket) in limits.into_iter().zip(refilled) {
+                buckets.insert(key, TokenBucket { tokens: bucket.tokens - 1.0, ..bucket });
+            }
+            Ok(())
+        } else {
+            // refilled to now without taking a token, marks the buckets as used
+            for ((key, _, _), bucket) in limits.into_iter().zip(refilled) {
+                buckets.insert(key, bucket);
+            }
+            drop(buckets);
+            self.count_rejected(api_key);
+            Err(wait_time.as_secs_f64().ceil().max(1.0) as u64)
+        }
+    }
+
+    /// The api key is not verified yet, so the map is limited like the buckets: a new key replaces the key with the
+    /// fewest rejected requests and the keys rejected most stay in the report.
+    fn count_rejected(&self, api_key: &str) {
+        let mut rejected = self.rejected.lock().unwrap();
+        if !rejected.contains_key(api_key) && rejected.len() >= self.max_tracked_buckets {
+            if let Some(fewest) = rejected.iter().min_by_key(|(_, count)| **count).map(|(key, _)| key.clone()) {
+                rejected.remove(&fewest);
+            }
+        }
+        *rejected.entry(api_key.to_string()).or_insert(0) += 1;
+    }
+
+    /// Rejected requests per API key since startup, limited to the most rejected keys.
+    pub fn rejected_requests(&self) -> Vec<RejectedRequests> {
+        let mut rejected_requests: Vec<RejectedRequests> = self
+            .rejected
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(api_key, rejected_requests)| RejectedRequests { api_key: api_key.clone(), rejected_requests: *rejected_requests })
+            .collect();
+        rejected_requests.sort_by(|a, b| b.rejected_requests.cmp(&a.rejected_requests).then(a.api_key.cmp(&b.api_key)));
+        rejected_requests
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig { api_key_per_second: 10.0, api_key_burst: 3.0, node_per_second: 0.5, node_burst: 2.0 })
+    }
+
+    #[test]
+    fn node_bucket_limits_and_refills() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("key", Some("pi-01"), now), Ok(()));
+        assert_eq!(limiter.check_at("key", Some("pi-01"), now), Ok(()));
+        assert_eq!(limiter.check_at("key", Some("pi-01"), now), Err(2));
+        assert_eq!(limiter.check_at("key", Some("pi-02"), now), Ok(()));
+        assert_eq!(limiter.check_at("key", Some("pi-01"), now + Duration::from_secs(2)), Ok(()));
+    }
+
+    #[test]
+    fn api_key_bucket_counts_rejections() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        for node_id in ["a", "b", "c"] {
+            assert_eq!(limiter.check_at("key", Some(node_id), now), Ok(()));
+        }
+        assert_eq!(limiter.check_at("key", Some("d"), now), Err(1));
+        assert_eq!(limiter.check_at("key", None, now), Err(1));
+        assert_eq!(limiter.check_at("other", None, now), Ok(()));
+
+        let rejected = limiter.rejected_requests();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!((rejected[0].api_key.as_str(), rejected[0].rejected_requests), ("key", 2));
+    }
+
+    #[test]
+    fn least_recently_used_buckets_are_evicted() {
+        let limiter = RateLimiter { max_tracked_buckets: 10, ..limiter() };
+        let now = Instant::now();
+
+        // pi-01 is out of tokens and keeps being used while other node ids rotate
+        assert_eq!(limiter.check_at("key", Some("pi-01"), now), Ok(()));
+        assert_eq!(limiter.check_at("key", Some("pi-01"), now), Ok(()));
+        for i in 0..100 {
+            let now = now + Duration::from_millis(i * 10);
+            assert_eq!(limiter.check_at("key", Some("pi-01"), now), Err(2));
+            let _ = limiter.check_at("key", Some(&format!("rotating-{}", i)), now);
+            assert!(limiter.buckets.lock().unwrap().len() <= 10);
+        }
+        assert!(limiter.check_at("key", Some("pi-01"), now + Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn rejected_requests_are_limited() {
+        let limiter = RateLimiter { max_tracked_buckets: 10, ..limiter() };
+        let now = Instant::now();
+
+        for _ in 0..5 {
+            let _ = limiter.check_at("key", None, now);
+        }
+        for i in 0..100 {
+            let api_key = format!("random-{}", i);
+            for _ in 0..4 {
+                let _ = limiter.check_at(&api_key, None, now);
+            }
+        }
+        let rejected = limiter.rejected_requests();
+        assert_eq!(rejected.len(), 10);
+        assert_eq!((rejected[0].api_key.as_str(), rejected[0].rejected_requests), ("key", 2));
+    }
+
+    #[test]
+    fn node_bucket_without_api_key_token() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("key", None, now), Ok(()));
+        assert_eq!(limiter.check_node_at("key", "pi-01", now), Ok(()));
+        assert_eq!(limiter.check_node_at("key", "pi-01", now), Ok(()));
+        assert_eq!(limiter.check_node_at("key", "pi-01", now), Err(2));
+        // shared with /checkin
+        assert_eq!(limiter.check_at("key", Some("pi-01"), now), Err(2));
+        assert_eq!(limiter.check_at("key", Some("pi-02"), now), Ok(()));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::models::{ComponentStatus, ReadinessConfig, ReadinessReport};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Pool, Status};
+use std::time::{Duration, Instant};
+
+use crate::alert_sweep;
+use crate::notification_outbox;
+
+pub const STATUS_OK: &str = "ok";
+pub const STATUS_DEGRADED: &str = "degraded";
+pub const STATUS_FAILING: &str = "failing";
+
+/// The database check fails when the pool and the query do not answer within this time.
+pub const DATABASE_CHECK_TIMEOUT_SECONDS: u64 = 2;
+
+
+fn component(component: &str, status: &str, detail: String) -> ComponentStatus {
+    ComponentStatus { component: component.to_string(), status: status.to_string(), detail }
+}
+
+/// Failing when every connection is in use and requests are waiting for one.
+pub fn check_pool(pool_status: &Status) -> ComponentStatus {
+    let in_use = pool_status.size.saturating_sub(pool_status.available);
+    if pool_status.available == 0 && pool_status.size >= pool_status.max_size {
+        if pool_status.waiting > 0 {
+            component("db_pool", STATUS_FAILING, format!("all {} connections in use, {} waiting", pool_status.max_size, pool_status.waiting))
+        } else {
+            component("db_pool", STATUS_DEGRADED, format!("all {} connections in use", pool_status.max_size))
+        }
+    } else {
+        component("db_pool", STATUS_OK, format!("{} of {} connections in use", in_use, pool_status.max_size))
+    }
+}
+
+/// Sweeps are triggered from outside (cron on /alert-sender or `run-alert-sweep`), so a late sweep only degrades:
+/// taking the server out of rotation would stop a scheduler that calls it through the same service.
+pub fn check_alert_sweep(last_alert_sweep_at: &Option<DateTime<Utc>>, now: &DateTime<Utc>, max_age_seconds: i64) -> ComponentStatus {
+    match last_alert_sweep_at {
+        None => component("alert_sweep", STATUS_DEGRADED, "no alert sweep has run yet".to_string()),
+        Some(x) => {
+            let age_seconds = (*now - *x).num_seconds();
+            let status = if age_seconds > max_age_seconds { STATUS_DEGRADED } else { STATUS_OK };
+            component("alert_sweep", status, format!("last sweep finished {} seconds ago", age_seconds))
+        }
+    }
+}
+
+pub fn check_notification_backlog(backlog: usize, limit: usize) -> ComponentStatus {
+    let status = if backlog > limit { STATUS_DEGRADED } else { STATUS_OK };
+    component("notification_backlog", status, format!("{} notifications waiting, limit {}", backlog, limit))
+}
+
+/// The worst status of the components.
+pub fn overall_status(components: &[ComponentStatus]) -> &'static str {
+    if components.iter().any(|x| x.status == STATUS_FAILING) {
+        STATUS_FAILING
+    } else if components.iter().any(|x| x.status == STATUS_DEGRADED) {
+        STATUS_DEGRADED
+    } else {
+        STATUS_OK
+    }
+}
+
+/// Checks of /readyz. The database round trip reads the alert sweep status.
+pub async fn readiness_report(pool: &Pool, readiness_config: &ReadinessConfig, now: &DateTime<Utc>) -> ReadinessReport {
+    // taken before the check uses a connection itself
+    let mut components = vec![check_pool(&pool.status())];
+
+    let started_at = Instant::now();
+    let database_check = tokio::time::timeout(Duration::from_secs(DATABASE_CHECK_TIMEOUT_SECONDS), async {
+        let client = pool.get().await.map_err(|e| e.to_string())?;
+        alert_sweep::last_alert_sweep_at(&client).await.map_err(|e| e.to_string())
+    }).await;
+
+    match database_check {
+        Ok(Ok(last_alert_sweep_at)) => {
+            components.push(component("database", STATUS_OK, format!("round trip {} ms", started_at.elapsed().as_millis())));
+            components.push(check_alert_sweep(&last_alert_sweep_at, now, readiness_config.alert_sweep_max_age_seconds));
+        }
+        Ok(Err(e)) => {
+            components.push(component("database", STATUS_FAILING, e));
+            components.push(component("alert_sweep", STATUS_DEGRADED, "unknown, database is not available".to_string()));
+        }
+        Err(_) => {
+            components.push(component("database", STATUS_FAILING, format!("no response within {} seconds", DATABASE_CHECK_TIMEOUT_SECONDS)));
+            components.push(component("alert_sweep", STATUS_DEGRADED, "unknown, database is not available".to_string()));
+        }
+    }
+    components.push(check_notification_backlog(notification_outbox::backlog(), readiness_config.notification_backlog_limit));
+
+    ReadinessReport { status: overall_status(&components).to_string(), components }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn pool_exhaustion() {
+        let pool_status = |size, available, waiting| Status { max_size: 16, size, available, waiting };
+        assert_eq!(check_pool(&pool_status(4, 2, 0)).status, STATUS_OK);
+        assert_eq!(check_pool(&pool_status(16, 0, 0)).status, STATUS_DEGRADED);
+        assert_eq!(check_pool(&pool_status(16, 0, 3)).status, STATUS_FAILING);
+    }
+
+    #[test]
+    fn alert_sweep_age() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+        assert_eq!(check_alert_sweep(&None, &now, 600).status, STATUS_DEGRADED);
+        assert_eq!(check_alert_sweep(&Some(now - chrono::Duration::seconds(600)), &now, 600).status, STATUS_OK);
+        assert_eq!(check_alert_sweep(&Some(now - chrono::Duration::seconds(601)), &now, 600).status, STATUS_DEGRADED);
+    }
+
+    #[test]
+    fn worst_component_status() {
+        let mut components = vec![check_notification_backlog(0, 100)];
+        assert_eq!(overall_status(&components), STATUS_OK);
+        components.push(check_notification_backlog(101, 100));
+        assert_eq!(overall_status(&components), STATUS_DEGRADED);
+        components.push(component("database", STATUS_FAILING, "connection refused".to_string()));
+        assert_eq!(overall_status(&components), STATUS_FAILING);
+    }
+}
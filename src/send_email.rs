@@ -4,7 +4,7 @@ use chrono_tz::Europe::Riga;
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::transport::smtp::SmtpTransport;
 use lettre::{
-    message::{header, MultiPart, SinglePart},
+    message::{header, Mailbox, MultiPart, SinglePart},
     Message, Transport,
 };
 
@@ -13,79 +13,184 @@ use compound_duration::format_dhms;
 use actix_web::web;
 
 use log::debug;
-use crate::models::{Email, TelegramConfig};
+use log::error;
+use log::warn;
+use crate::models::{Email, Incident, IncidentConfig, TelegramConfig};
 use crate::send_telegram;
-//use log::error;
+use crate::notification_outbox::PendingEmail;
+use crate::incident_functions;
+use crate::metrics;
+use crate::uptime_report::{UptimeReport, UptimeSummary};
 
 
+/// Addresses of a ';' separated recipient list, trimmed. Empty entries (e.g. a trailing ';') are skipped.
+fn recipients(notification_recipient_list: &str) -> impl Iterator<Item = &str> {
+    notification_recipient_list.split(';').map(str::trim).filter(|x| !x.is_empty())
+}
+
+/// Sends the email to every recipient of the list. Failures are logged (and reported to telegram) and returned,
+/// a failed recipient does not stop the others. Nothing is sent when email is not configured.
 pub async fn send_email_generic(
-    notification_recipient_list: &String,
-    subject: &String,
-    body_plain: &String,
-    body_html: &String,
+    notification_recipient_list: &str,
+    subject: &str,
+    body_plain: &str,
+    body_html: &str,
     email_config: &web::Data<Email>,
     telegram_config:&web::Data<TelegramConfig>,
-) {
+) -> Result<(), String> {
+    if !email_config.is_enabled() {
+        warn!("Email is not configured. Not sent to {}: {}", notification_recipient_list, subject);
+        return Err("email is not configured".to_string());
+    }
     debug!("Email configuration: {} {}",email_config.smtp_server,email_config.username, ) ;
 
-    //    println!("Email account password = {}", email_account_password);
-    let creds = Credentials::new(
-        email_config.username.to_string(),
-        email_config.password.to_string(),
-    );
-
-    // Open a remote connection to gmail
-    let mailer = SmtpTransport::starttls_relay(&email_config.smtp_server)
-        .unwrap()
-        .credentials(creds)
-        .build();
-
-    let mut email: Message;
+    let mailer = match smtp_transport(email_config) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Invalid email configuration: {}", e);
+            return Err(e.to_string());
+        }
+    };
+    let sender: Mailbox = match email_config.username.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Invalid email sender {}: {}", email_config.username, e);
+            return Err(e.to_string());
+        }
+    };
 
-    for email_destination in notification_recipient_list.split(";") {
+    let mut failed_recipients: Vec<String> = Vec::new();
+    for email_destination in recipients(notification_recipient_list) {
         debug!("sending email to {}", email_destination);
 
-        email = Message::builder()
-            .from(email_config.username.to_string().parse().unwrap())
-            .reply_to(email_config.username.to_string().parse().unwrap())
+        let recipient: Mailbox = match email_destination.parse() {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Invalid email recipient '{}': {}", email_destination, e);
+                metrics::increment(metrics::NOTIFICATIONS_FAILED_TOTAL, &[("channel", metrics::CHANNEL_EMAIL)]);
+                failed_recipients.push(email_destination.to_string());
+                continue;
+            }
+        };
+        let email = Message::builder()
+            .from(sender.clone())
+            .reply_to(sender.clone())
             .subject(subject)
-            .to(email_destination.parse().unwrap())
+            .to(recipient)
             .multipart(
                 MultiPart::alternative() // This is composed of two parts.
                     .singlepart(
                         SinglePart::builder()
                             .header(header::ContentType::TEXT_PLAIN)
-                            .body(body_plain.clone()), // Every message should have a plain text fallback.
+                            .body(body_plain.to_string()), // Every message should have a plain text fallback.
                     )
                     .singlepart(
                         SinglePart::builder()
                             .header(header::ContentType::TEXT_HTML)
-                            .body(String::from(body_html.clone())),
+                            .body(body_html.to_string()),
                     ),
-            )
-            .unwrap();
-
+            );
+        let email = match email {
+            Ok(x) => x,
+            Err(e) => {
+                error!("Could not build email to {}: {}", email_destination, e);
+                metrics::increment(metrics::NOTIFICATIONS_FAILED_TOTAL, &[("channel", metrics::CHANNEL_EMAIL)]);
+                failed_recipients.push(email_destination.to_string());
+                continue;
+            }
+        };
 
         // Send the email(s)
         match mailer.send(&email) {
-            Ok(_) => debug!("Email sent successfully!"),
+            Ok(_) => {
+                debug!("Email sent successfully!");
+                metrics::increment(metrics::NOTIFICATIONS_SENT_TOTAL, &[("channel", metrics::CHANNEL_EMAIL)]);
+            }
             Err(e) => {
+                error!("Could not send email to {}: {:?}", email_destination, e);
+                metrics::increment(metrics::NOTIFICATIONS_FAILED_TOTAL, &[("channel", metrics::CHANNEL_EMAIL)]);
+                failed_recipients.push(email_destination.to_string());
                 // send message to telegram
-                let message:String = format!("Error sending email");
-                send_telegram::send_telegram_msg(&message,telegram_config).await;
-                panic!("Could not send email: {:?}", e);
+                let message:String = "Error sending email".to_string();
+                let _ = send_telegram::send_telegram_msg(&message,telegram_config).await;
                 },
         }
     }
+
+    if failed_recipients.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("not sent to {}", failed_recipients.join(";")))
+    }
 }
 
-pub async fn send_node_offline_notification_email(
-    node_id: &String,
-    notification_recipient_list: &String,
-    last_checkin_timestamp: &DateTime<Utc>,
+fn smtp_transport(email_config: &Email) -> Result<SmtpTransport, lettre::transport::smtp::Error> {
+    let creds = Credentials::new(
+        email_config.username.to_string(),
+        email_config.password.to_string(),
+    );
+
+    Ok(SmtpTransport::starttls_relay(&email_config.smtp_server)?
+        .credentials(creds)
+        .build())
+}
+
+/// Connects and logs in to the SMTP server without sending anything. Used for the startup channel report.
+pub async fn check_email_channel(email_config: &Email) -> Result<(), String> {
+    let mailer = smtp_transport(email_config).map_err(|e| e.to_string())?;
+    match tokio::task::spawn_blocking(move || mailer.test_connection()).await {
+        Ok(Ok(true)) => Ok(()),
+        Ok(Ok(false)) => Err("connection test failed".to_string()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+/// Sends the notification to each recipient separately so that every recipient gets
+/// an acknowledge link signed for their own address.
+pub async fn send_incident_email(
+    notification_recipient_list: &str,
+    subject: &str,
+    body_plain: &str,
+    body_html: &str,
+    incident_id: &i32,
     email_config: &web::Data<Email>,
     telegram_config: &web::Data<TelegramConfig>,
-) {
+    incident_config: &web::Data<IncidentConfig>,
+) -> Result<(), String> {
+    let mut failed_recipients: Vec<String> = Vec::new();
+    for email_destination in recipients(notification_recipient_list) {
+        let acknowledge_link = incident_functions::acknowledge_link(incident_id, email_destination, incident_config);
+
+        let body_plain_recipient = format!("{}\n\nAcknowledge incident #{}: {}", body_plain, incident_id, acknowledge_link);
+        let body_html_recipient = format!("{}<br><br><a href='{}'>Acknowledge incident #{}</a>", body_html, acknowledge_link, incident_id);
+
+        if send_email_generic(
+            email_destination,
+            subject,
+            &body_plain_recipient,
+            &body_html_recipient,
+            email_config,
+            telegram_config,
+        ).await.is_err() {
+            failed_recipients.push(email_destination.to_string());
+        }
+    }
+
+    if failed_recipients.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("not sent to {}", failed_recipients.join(";")))
+    }
+}
+
+pub fn node_offline_email(
+    node_id: &str,
+    notification_recipient_list: &str,
+    last_checkin_timestamp: &DateTime<Utc>,
+    incident: &Incident,
+) -> PendingEmail {
     let subject = format!("Node OFF-line: {}", node_id);
 
     let checkin_timestamp  = Utc::now();
@@ -96,7 +201,7 @@ pub async fn send_node_offline_notification_email(
     let offline_duration_text = format_dhms(offline_minutes);
 
 
-    let last_checkin_timestamp_riga_time = checkin_timestamp.with_timezone(&Riga);
+    let last_checkin_timestamp_riga_time = last_checkin_timestamp.with_timezone(&Riga);
 
     let body_plain = format!(
         "Node - {} - is OFF-line. It was last seen {} minutes ago on {}.",
@@ -111,70 +216,61 @@ pub async fn send_node_offline_notification_email(
         last_checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
     );
 
-    send_email_generic(
-        notification_recipient_list,
-        &subject,
-        &body_plain,
-        &body_html,
-        email_config,
-        telegram_config,
-    ).await;
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: Some(incident.id),
+    }
 }
 
-pub async fn send_node_online_notification_email(
-    node_id: &String,
-    notification_recipient_list: &String,
+pub fn node_online_email(
+    node_id: &str,
+    notification_recipient_list: &str,
     checkin_timestamp: &DateTime<Utc>,
-    last_checkin_timestamp: &DateTime<Utc>,
-    email_config: &web::Data<Email>,
-    telegram_config:&web::Data<TelegramConfig>,
-) {
+    incident_opened_at: &DateTime<Utc>,
+) -> PendingEmail {
 
-    let offline_minutes = checkin_timestamp
-        .signed_duration_since(*last_checkin_timestamp)
-        .num_seconds();
+    let incident_duration_text = incident_duration_text(checkin_timestamp, incident_opened_at);
 
-    let offline_duration_text = format_dhms(offline_minutes);
-
-    debug!("offline_duration_text = {:?}" , &offline_duration_text);
+    debug!("incident_duration_text = {:?}" , &incident_duration_text);
 
     let checkin_timestamp_riga_time = checkin_timestamp.with_timezone(&Riga);
 
     let subject = format!("Node ON-line: {}", node_id);
 
     let body_plain = format!(
-        "Node - {} - is ON-line since {}. It was offline for {}.",
+        "Node - {} - is ON-line since {}. It was reported offline for {}.",
         node_id,
         checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
-        offline_duration_text
+        incident_duration_text
     );
     let body_html = format!(
-        "Node - <b>{}</b> - is <span style='color:green'><b>ON-line</b></span> since {}. It was offline for {}.",
+        "Node - <b>{}</b> - is <span style='color:green'><b>ON-line</b></span> since {}. It was reported offline for {}.",
         node_id,
         checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
-        offline_duration_text
+        incident_duration_text
     );
 
-    send_email_generic(
-        notification_recipient_list,
-        &subject,
-        &body_plain,
-        &body_html,
-        email_config,
-        telegram_config,
-    ).await;
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: None,
+    }
 }
 
-pub async fn sensor_validation_failed_email(
-    node_id: &String,
-    notification_recipient_list: &String,
+pub fn sensor_validation_failed_email(
+    node_id: &str,
+    notification_recipient_list: &str,
     checkin_timestamp: &DateTime<Utc>,
-    validation_message: &String,
-    sensor_id: &String,
-    sensor_name: &String,
-    email_config: &web::Data<Email>,
-    telegram_config:&web::Data<TelegramConfig>,
-) {
+    validation_message: &str,
+    sensor_id: &str,
+    sensor_name: &str,
+    incident: &Incident,
+) -> PendingEmail {
     let checkin_timestamp_riga_time = checkin_timestamp.with_timezone(&Riga);
     let subject = format!("sensor validation FAILED: {}-{}", node_id, sensor_name);
 
@@ -196,48 +292,282 @@ pub async fn sensor_validation_failed_email(
 
     );
 
-    send_email_generic(
-        notification_recipient_list,
-        &subject,
-        &body_plain,
-        &body_html,
-        email_config,
-        telegram_config,
-    ).await;
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: Some(incident.id),
+    }
 }
 
-pub async fn sensor_validation_ok_email(
-    node_id: &String,
-    notification_recipient_list: &String,
+pub fn sensor_validation_ok_email(
+    node_id: &str,
+    notification_recipient_list: &str,
     checkin_timestamp: &DateTime<Utc>,
-    validation_message: &String,
-    sensor_id: &String,
-    sensor_name: &String,
-    email_config: &web::Data<Email>,
-    telegram_config:&web::Data<TelegramConfig>,
-) {
+    incident_opened_at: &DateTime<Utc>,
+    validation_message: &str,
+    sensor_id: &str,
+    sensor_name: &str,
+) -> PendingEmail {
     let checkin_timestamp_riga_time = checkin_timestamp.with_timezone(&Riga);
+    let incident_duration_text = incident_duration_text(checkin_timestamp, incident_opened_at);
 
     let subject = format!("Sensor validation OK: {}-{}", node_id, sensor_name);
 
     let body_plain = format!(
-        "Sensor validation SUCCESSFUL:\n Node ID:{}\n Sensor Name: {}\n Sensor ID: {}\n Timestamp: {}\n Validation: {}",
+        "Sensor validation SUCCESSFUL:\n Node ID:{}\n Sensor Name: {}\n Sensor ID: {}\n Timestamp: {}\n Validation: {}\n Validation was failing for: {}",
         node_id,
         sensor_name,
         sensor_id,
         checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
-        validation_message
+        validation_message,
+        incident_duration_text
     );
     let body_html = format!(
-        "Sensor validation <span style='color:green'>SUCCESSFUL</span>.<br> Node ID:{}<br>Sensor Name: {} <br> Sensor ID: {}<br> Timestamp: {}<br> Validation: <b>{}</b>",
+        "Sensor validation <span style='color:green'>SUCCESSFUL</span>.<br> Node ID:{}<br>Sensor Name: {} <br> Sensor ID: {}<br> Timestamp: {}<br> Validation: <b>{}</b><br> Validation was failing for: {}",
         node_id,
         sensor_name,
         sensor_id,
         checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
-        validation_message
+        validation_message,
+        incident_duration_text
 
     );
 
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: None,
+    }
+}
+
+pub fn sensor_missing_email(
+    node_id: &str,
+    notification_recipient_list: &str,
+    missing_since: &DateTime<Utc>,
+    sensor_id: &str,
+    last_known_sensor_name: &str,
+    last_seen_timestamp: &Option<DateTime<Utc>>,
+    incident: &Incident,
+) -> PendingEmail {
+    let missing_since_riga_time = missing_since.with_timezone(&Riga);
+    let last_seen_text = match last_seen_timestamp {
+        Some(x) => x.with_timezone(&Riga).format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "never".to_string(),
+    };
+
+    let subject = format!("Sensor MISSING: {}-{}", node_id, last_known_sensor_name);
+
+    let body_plain = format!(
+        "Sensor is MISSING from checkins:\n Node ID:{}\n Sensor Name: {}\n Sensor ID: {}\n Missing since: {}\n Last seen: {}",
+        node_id,
+        last_known_sensor_name,
+        sensor_id,
+        missing_since_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        last_seen_text,
+    );
+    let body_html = format!(
+        "Sensor is <span style='color:red'>MISSING</span> from checkins.<br> Node ID:{}<br>Sensor Name: {}<br> Sensor ID: {}<br> Missing since: {}<br> Last seen: <b>{}</b>",
+        node_id,
+        last_known_sensor_name,
+        sensor_id,
+        missing_since_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        last_seen_text,
+    );
+
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: Some(incident.id),
+    }
+}
+
+pub fn sensor_reporting_again_email(
+    node_id: &str,
+    notification_recipient_list: &str,
+    checkin_timestamp: &DateTime<Utc>,
+    incident_opened_at: &DateTime<Utc>,
+    sensor_id: &str,
+    sensor_name: &str,
+) -> PendingEmail {
+    let checkin_timestamp_riga_time = checkin_timestamp.with_timezone(&Riga);
+    let incident_duration_text = incident_duration_text(checkin_timestamp, incident_opened_at);
+
+    let subject = format!("Sensor reporting again: {}-{}", node_id, sensor_name);
+
+    let body_plain = format!(
+        "Sensor is reporting again:\n Node ID:{}\n Sensor Name: {}\n Sensor ID: {}\n Timestamp: {}\n Sensor was reported missing for: {}",
+        node_id,
+        sensor_name,
+        sensor_id,
+        checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        incident_duration_text
+    );
+    let body_html = format!(
+        "Sensor is <span style='color:green'>reporting again</span>.<br> Node ID:{}<br>Sensor Name: {} <br> Sensor ID: {}<br> Timestamp: {}<br> Sensor was reported missing for: {}",
+        node_id,
+        sensor_name,
+        sensor_id,
+        checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        incident_duration_text
+    );
+
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: None,
+    }
+}
+
+pub fn system_health_alert_email(
+    node_id: &str,
+    notification_recipient_list: &str,
+    checkin_timestamp: &DateTime<Utc>,
+    problem: &str,
+    details: &str,
+    incident: &Incident,
+) -> PendingEmail {
+    let checkin_timestamp_riga_time = checkin_timestamp.with_timezone(&Riga);
+
+    let subject = format!("Node system health: {} - {}", node_id, problem);
+
+    let body_plain = format!(
+        "Node system health problem:\n Node ID:{}\n Problem: {}\n Timestamp: {}\n {}",
+        node_id,
+        problem,
+        checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        details,
+    );
+    let body_html = format!(
+        "Node system health <span style='color:red'>problem</span>.<br> Node ID:{}<br> Problem: <b>{}</b><br> Timestamp: {}<br> {}",
+        node_id,
+        problem,
+        checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        details,
+    );
+
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: Some(incident.id),
+    }
+}
+
+pub fn system_health_ok_email(
+    node_id: &str,
+    notification_recipient_list: &str,
+    checkin_timestamp: &DateTime<Utc>,
+    incident_opened_at: &DateTime<Utc>,
+    problem: &str,
+    details: &str,
+) -> PendingEmail {
+    let checkin_timestamp_riga_time = checkin_timestamp.with_timezone(&Riga);
+    let incident_duration_text = incident_duration_text(checkin_timestamp, incident_opened_at);
+
+    let subject = format!("Node system health OK: {} - {}", node_id, problem);
+
+    let body_plain = format!(
+        "Node system health problem cleared:\n Node ID:{}\n Problem: {}\n Timestamp: {}\n {}\n Problem lasted for: {}",
+        node_id,
+        problem,
+        checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        details,
+        incident_duration_text
+    );
+    let body_html = format!(
+        "Node system health problem <span style='color:green'>cleared</span>.<br> Node ID:{}<br> Problem: {}<br> Timestamp: {}<br> {}<br> Problem lasted for: {}",
+        node_id,
+        problem,
+        checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        details,
+        incident_duration_text
+    );
+
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: None,
+    }
+}
+
+pub fn new_node_registered_email(
+    notification_recipient_list: &str,
+    node_id: &str,
+    api_key_id: &i32,
+    approval_status: &str,
+    checkin_timestamp: &DateTime<Utc>,
+) -> PendingEmail {
+    let checkin_timestamp_riga_time = checkin_timestamp.with_timezone(&Riga);
+
+    let subject = format!("New node registered: {} ({})", node_id, approval_status);
+
+    let body_plain = format!(
+        "New node registered:\n Node ID:{}\n API key id: {}\n Status: {}\n First checkin: {}",
+        node_id,
+        api_key_id,
+        approval_status,
+        checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
+    );
+    let body_html = format!(
+        "New node registered.<br> Node ID:{}<br> API key id: {}<br> Status: <b>{}</b><br> First checkin: {}",
+        node_id,
+        api_key_id,
+        approval_status,
+        checkin_timestamp_riga_time.format("%Y-%m-%d %H:%M:%S"),
+    );
+
+    PendingEmail {
+        recipient_list: notification_recipient_list.to_string(),
+        subject,
+        body_plain,
+        body_html,
+        incident_id: None,
+    }
+}
+
+pub async fn send_uptime_report_email(
+    notification_recipient_list: &str,
+    report: &UptimeReport,
+    email_config: &web::Data<Email>,
+    telegram_config:&web::Data<TelegramConfig>,
+) -> Result<(), String> {
+    let from_riga_time = report.from.with_timezone(&Riga);
+    let to_riga_time = report.to.with_timezone(&Riga);
+
+    let subject = format!("Uptime report {}", from_riga_time.format("%Y-%m"));
+
+    let mut body_plain = format!(
+        "Uptime report {} - {}\n\nNodes:\n",
+        from_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        to_riga_time.format("%Y-%m-%d %H:%M:%S"),
+    );
+    let mut body_html = format!(
+        "<b>Uptime report</b> {} - {}<br><br><b>Nodes</b>{}",
+        from_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        to_riga_time.format("%Y-%m-%d %H:%M:%S"),
+        uptime_summary_html_table(&report.nodes),
+    );
+    for summary in &report.nodes {
+        body_plain.push_str(&uptime_summary_plain_line(summary));
+    }
+
+    body_plain.push_str("\nGroups:\n");
+    body_html.push_str(&format!("<br><b>Groups</b>{}", uptime_summary_html_table(&report.groups)));
+    for summary in &report.groups {
+        body_plain.push_str(&uptime_summary_plain_line(summary));
+    }
+
     send_email_generic(
         notification_recipient_list,
         &subject,
@@ -245,5 +575,62 @@ pub async fn sensor_validation_ok_email(
         &body_html,
         email_config,
         telegram_config,
-    ).await;
+    ).await
+}
+
+fn uptime_summary_plain_line(summary: &UptimeSummary) -> String {
+    format!(
+        " {}: uptime {}, outages {}, MTTR {}, longest outage {}\n",
+        summary.name,
+        uptime_percent_text(summary),
+        summary.outage_count,
+        summary.mttr_seconds.map(format_dhms).unwrap_or_else(|| "-".to_string()),
+        format_dhms(summary.longest_outage_seconds),
+    )
+}
+
+fn uptime_summary_html_table(summaries: &[UptimeSummary]) -> String {
+    let mut table = "<table border='1' cellpadding='4' style='border-collapse:collapse'><tr><th>Name</th><th>Uptime</th><th>Outages</th><th>MTTR</th><th>Longest outage</th></tr>".to_string();
+    for summary in summaries {
+        table.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            summary.name,
+            uptime_percent_text(summary),
+            summary.outage_count,
+            summary.mttr_seconds.map(format_dhms).unwrap_or_else(|| "-".to_string()),
+            format_dhms(summary.longest_outage_seconds),
+        ));
+    }
+    table.push_str("</table>");
+    table
+}
+
+fn uptime_percent_text(summary: &UptimeSummary) -> String {
+    match summary.uptime_percent {
+        Some(x) => format!("{:.3}%", x),
+        None => "no data".to_string(),
+    }
+}
+
+fn incident_duration_text(
+    resolved_at: &DateTime<Utc>,
+    incident_opened_at: &DateTime<Utc>,
+) -> String {
+    let incident_seconds = resolved_at
+        .signed_duration_since(*incident_opened_at)
+        .num_seconds();
+
+    format_dhms(incident_seconds)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recipients_are_trimmed() {
+        assert_eq!(recipients("a@example.com; b@example.com;").collect::<Vec<_>>(), vec!["a@example.com", "b@example.com"]);
+        assert_eq!(recipients(" ").count(), 0);
+    }
 }
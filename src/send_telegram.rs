@@ -1,13 +1,21 @@
 use crate::{ models::TelegramConfig};
 use rustygram::types::{SendMessageOption, SendMessageParseMode};
 use log::debug;
+use log::error;
+use log::warn;
+use crate::metrics;
 
 
+/// Sends the message to the telegram channel. A failed send is logged and returned, it does not stop the caller.
 pub async fn send_telegram_msg(
     message_text: &String,
     telegram_config: &TelegramConfig,
-) {
-    debug!("Sending message to telegram {} {} {}",message_text ,telegram_config.bot_token , telegram_config.channel_id ) ;
+) -> Result<(), String> {
+    if !telegram_config.is_enabled() {
+        warn!("Telegram is not configured. Message not sent: {}", message_text);
+        return Ok(());
+    }
+    debug!("Sending message to telegram {} {}",message_text , telegram_config.channel_id ) ;
 
     
  // telegram configuration 
@@ -17,7 +25,15 @@ pub async fn send_telegram_msg(
      // send a simple text message
     let option = SendMessageOption { parse_mode: Some(SendMessageParseMode::MarkdownV2) };
 
-    rustygram::send_message(&instance, message_text, Some(option)).await.unwrap();
-
-
+    match rustygram::send_message(&instance, message_text, Some(option)).await {
+        Ok(_) => {
+            metrics::increment(metrics::NOTIFICATIONS_SENT_TOTAL, &[("channel", metrics::CHANNEL_TELEGRAM)]);
+            Ok(())
+        }
+        Err(e) => {
+            error!("Could not send telegram message: {} (code {})", e, e.code);
+            metrics::increment(metrics::NOTIFICATIONS_FAILED_TOTAL, &[("channel", metrics::CHANNEL_TELEGRAM)]);
+            Err(format!("{} (code {})", e, e.code))
+        }
+    }
 }
@@ -0,0 +1,131 @@
+use crate::models::SensorData;
+use chrono::{DateTime, Duration, Utc};
+use deadpool_postgres::GenericClient;
+
+use log::debug;
+
+
+/// Stores every sensor value of the checkin in the sensor_readings table. Readings without own timestamp
+/// are stored with `reading_timestamp`.
+pub async fn store_sensor_readings(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    sensor_data: &Option<Vec<SensorData>>,
+    reading_timestamp: &DateTime<Utc>,
+) {
+    if let Some(sensor_data) = sensor_data {
+        let stmt_reading_insert = dbconnection.prepare_cached("INSERT INTO sensor_readings(
+	id, node_id, sensor_id, sensor_name, value_type, value, value_text, unit, reading_timestamp)
+	VALUES (DEFAULT, $1, $2, $3, $4, $5, $6, $7, $8);").await.unwrap();
+
+        for sensor_value in sensor_data {
+            let _result = dbconnection.query(&stmt_reading_insert, &[
+                node_id_db,
+                &sensor_value.id,
+                &sensor_value.sensor_name,
+                &sensor_value.value.value_type(),
+                &sensor_value.value.as_number(),
+                &sensor_value.value.as_text(),
+                &sensor_value.unit,
+                sensor_value.timestamp.as_ref().unwrap_or(reading_timestamp),
+            ]).await.unwrap();
+        }
+        debug!("stored {} sensor readings for nodes.id = {}", sensor_data.len(), node_id_db);
+    }
+}
+
+/// Latest numeric value of the sensor stored before the given timestamp. Readings without numeric value (strings) are skipped.
+pub async fn previous_sensor_value(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    sensor_id: &str,
+    before: &DateTime<Utc>,
+) -> Option<(f64, DateTime<Utc>)> {
+    let stmt_previous_reading = dbconnection.prepare_cached("SELECT value, reading_timestamp
+	FROM sensor_readings WHERE node_id = $1 AND sensor_id = $2 AND reading_timestamp < $3 AND value IS NOT NULL
+	ORDER BY reading_timestamp DESC LIMIT 1;").await.unwrap();
+    let rows = dbconnection.query(&stmt_previous_reading, &[node_id_db, &sensor_id, before]).await.unwrap();
+
+    rows.first().map(|row| (row.get(0), row.get(1)))
+}
+
+/// Sensor name, numeric value (None for strings) and timestamp of the latest stored reading of the sensor.
+pub async fn last_sensor_reading(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    sensor_id: &str,
+) -> Option<(String, Option<f64>, DateTime<Utc>)> {
+    let stmt_last_reading = dbconnection.prepare_cached("SELECT sensor_name, value, reading_timestamp
+	FROM sensor_readings WHERE node_id = $1 AND sensor_id = $2
+	ORDER BY reading_timestamp DESC LIMIT 1;").await.unwrap();
+    let rows = dbconnection.query(&stmt_last_reading, &[node_id_db, &sensor_id]).await.unwrap();
+
+    rows.first().map(|row| (row.get(0), row.get(1), row.get(2)))
+}
+
+/// Oldest numeric value of the sensor stored within [since, before).
+pub async fn oldest_sensor_value_since(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    sensor_id: &str,
+    since: &DateTime<Utc>,
+    before: &DateTime<Utc>,
+) -> Option<(f64, DateTime<Utc>)> {
+    let stmt_oldest_reading = dbconnection.prepare_cached("SELECT value, reading_timestamp
+	FROM sensor_readings WHERE node_id = $1 AND sensor_id = $2 AND reading_timestamp >= $3 AND reading_timestamp < $4 AND value IS NOT NULL
+	ORDER BY reading_timestamp ASC LIMIT 1;").await.unwrap();
+    let rows = dbconnection.query(&stmt_oldest_reading, &[node_id_db, &sensor_id, since, before]).await.unwrap();
+
+    rows.first().map(|row| (row.get(0), row.get(1)))
+}
+
+/// avg / min / max of the sensor values stored within [since, before).
+pub async fn sensor_value_aggregate(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    sensor_id: &str,
+    aggregate_function: &str,
+    since: &DateTime<Utc>,
+    before: &DateTime<Utc>,
+) -> Option<f64> {
+    let stmt_aggregate = dbconnection.prepare_cached("SELECT avg(value)::double precision, min(value)::double precision, max(value)::double precision
+	FROM sensor_readings WHERE node_id = $1 AND sensor_id = $2 AND reading_timestamp >= $3 AND reading_timestamp < $4;").await.unwrap();
+    let row = dbconnection.query_one(&stmt_aggregate, &[node_id_db, &sensor_id, since, before]).await.unwrap();
+
+    match aggregate_function {
+        "avg" => row.get(0),
+        "min" => row.get(1),
+        _ => row.get(2),
+    }
+}
+
+/// Timestamp since which the sensor keeps reporting values within `epsilon` of `sensor_value`: the first reading after
+/// the latest reading that differed by more than epsilon (or the first reading at all). None when there is no history.
+pub async fn sensor_value_unchanged_since(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    sensor_id: &str,
+    sensor_value: f64,
+    epsilon: f64,
+    before: &DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let stmt_unchanged_since = dbconnection.prepare_cached("SELECT min(reading_timestamp)
+	FROM sensor_readings WHERE node_id = $1 AND sensor_id = $2 AND reading_timestamp < $5 AND value IS NOT NULL
+	AND reading_timestamp > COALESCE(
+	    (SELECT max(reading_timestamp) FROM sensor_readings
+	     WHERE node_id = $1 AND sensor_id = $2 AND reading_timestamp < $5 AND abs(value - $3) > $4),
+	    '-infinity');").await.unwrap();
+    let row = dbconnection.query_one(&stmt_unchanged_since, &[node_id_db, &sensor_id, &sensor_value, &epsilon, before]).await.unwrap();
+
+    row.get(0)
+}
+
+/// Deletes readings older than the retention, called by the alert sweep. Returns the number of deleted readings.
+pub async fn delete_expired_sensor_readings(
+    dbconnection: &impl GenericClient,
+    now: &DateTime<Utc>,
+    retention_days: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    let stmt_reading_delete = dbconnection.prepare_cached("DELETE FROM sensor_readings WHERE reading_timestamp < $1;").await?;
+    dbconnection.execute(&stmt_reading_delete, &[&(*now - Duration::days(retention_days))]).await
+}
@@ -0,0 +1,94 @@
+use crate::models::{RegisteredSensor, SensorData, SensorValue};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+
+use log::debug;
+
+use crate::node_sensor_functions;
+
+
+/// Adds sensors seen for the first time to the sensors table and updates last seen / last value of known ones.
+/// The unit is kept when a checkin does not send one. Readings older than the last seen one (backfill) do not
+/// replace the last value.
+pub async fn register_sensors(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    sensor_data: &Option<Vec<SensorData>>,
+    checkin_timestamp: &DateTime<Utc>,
+) {
+    if let Some(sensor_data) = sensor_data {
+        let stmt_sensor_upsert = dbconnection.prepare_cached("INSERT INTO sensors(
+	id, node_id, sensor_id, sensor_name, first_seen, last_seen, value_type, last_value, last_value_text, unit)
+	VALUES (DEFAULT, $1, $2, $3, $4, $4, $5, $6, $7, $8)
+	ON CONFLICT (node_id, sensor_id) DO UPDATE SET sensor_name = EXCLUDED.sensor_name, last_seen = EXCLUDED.last_seen, value_type = EXCLUDED.value_type,
+	    last_value = EXCLUDED.last_value, last_value_text = EXCLUDED.last_value_text, unit = COALESCE(EXCLUDED.unit, sensors.unit)
+	WHERE sensors.last_seen <= EXCLUDED.last_seen
+	RETURNING (xmax = 0) AS inserted;").await.unwrap();
+
+        for sensor_value in sensor_data {
+            let rows = dbconnection.query(&stmt_sensor_upsert, &[
+                node_id_db,
+                &sensor_value.id,
+                &sensor_value.sensor_name,
+                sensor_value.timestamp.as_ref().unwrap_or(checkin_timestamp),
+                &sensor_value.value.value_type(),
+                &sensor_value.value.as_number(),
+                &sensor_value.value.as_text(),
+                &sensor_value.unit,
+            ]).await.unwrap();
+            let inserted = rows.first().map(|row| row.get::<_, bool>(0)).unwrap_or(false);
+            if inserted {
+                debug!("New sensor registered: nodes.id = {} sensor_id = {} sensor_name = {}", node_id_db, sensor_value.id, sensor_value.sensor_name);
+            }
+        }
+    }
+}
+
+/// Sensors of the nodes of an API key, optionally limited to one node. `has_trigger` tells whether any trigger
+/// (including composite triggers referencing the sensor) monitors it.
+pub async fn list_sensors(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+    node_id_external: &Option<String>,
+) -> Vec<RegisteredSensor> {
+    let stmt_sensors = dbconnection.prepare_cached("SELECT n.node_id_external, s.sensor_id, s.sensor_name, s.label, s.first_seen, s.last_seen, s.value_type, s.last_value, s.last_value_text, s.unit,
+	EXISTS (SELECT 1 FROM sensor_triggers t WHERE t.node_id = s.node_id
+	    AND (t.sensor_id = s.sensor_id OR (t.validation_function = $3 AND strpos(t.validation_expression, '\"' || s.sensor_id || '\"') > 0))) AS has_trigger
+	FROM sensors s JOIN nodes n ON n.id = s.node_id
+	WHERE n.fk_api_key_id = $1 AND ($2::varchar IS NULL OR n.node_id_external = $2)
+	ORDER BY n.node_id_external, s.sensor_id;").await.unwrap();
+    let rows = dbconnection.query(&stmt_sensors, &[api_key_id, node_id_external, &node_sensor_functions::VALIDATION_FUNCTION_COMPOSITE]).await.unwrap();
+
+    rows.iter()
+        .map(|row| {
+            let value_type: String = row.get(6);
+            RegisteredSensor {
+                node_id: row.get(0),
+                sensor_id: row.get(1),
+                sensor_name: row.get(2),
+                label: row.get(3),
+                first_seen: row.get(4),
+                last_seen: row.get(5),
+                last_value: SensorValue::from_db(&value_type, row.get(7), row.get(8)),
+                value_type,
+                unit: row.get(9),
+                has_trigger: row.get(10),
+            }
+        })
+        .collect()
+}
+
+/// Sets the human readable label of a sensor. Returns false when the sensor is not registered for the node.
+pub async fn update_sensor_label(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+    node_id_external: &str,
+    sensor_id: &str,
+    label: &Option<String>,
+) -> bool {
+    let stmt_label_update = dbconnection.prepare_cached("UPDATE sensors s SET label = $4
+	FROM nodes n WHERE n.id = s.node_id AND n.fk_api_key_id = $1 AND n.node_id_external = $2 AND s.sensor_id = $3;").await.unwrap();
+    let updated = dbconnection.execute(&stmt_label_update, &[api_key_id, &node_id_external, &sensor_id, label]).await.unwrap();
+
+    updated == 1
+}
@@ -0,0 +1,269 @@
+use crate::models::{NodeInfo, SystemHealth, SystemHealthConfig};
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::GenericClient;
+
+use log::debug;
+use log::error;
+
+use crate::incident_functions;
+use crate::send_email;
+use crate::notification_outbox::Outbox;
+
+
+/// Stores the system health block of the checkin as the latest snapshot of the node.
+pub async fn store_system_health(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    system: &SystemHealth,
+    reported_at: &DateTime<Utc>,
+) {
+    let stmt_system_upsert = dbconnection.prepare_cached("INSERT INTO node_system_health(
+	node_id, reported_at, uptime_seconds, cpu_temperature, cpu_throttled, load_1m, load_5m, load_15m, disk_used_percent, memory_used_percent, ip_addresses, os, agent_version)
+	VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+	ON CONFLICT (node_id) DO UPDATE SET reported_at = EXCLUDED.reported_at, uptime_seconds = EXCLUDED.uptime_seconds, cpu_temperature = EXCLUDED.cpu_temperature,
+	    cpu_throttled = EXCLUDED.cpu_throttled, load_1m = EXCLUDED.load_1m, load_5m = EXCLUDED.load_5m, load_15m = EXCLUDED.load_15m,
+	    disk_used_percent = EXCLUDED.disk_used_percent, memory_used_percent = EXCLUDED.memory_used_percent, ip_addresses = EXCLUDED.ip_addresses,
+	    os = EXCLUDED.os, agent_version = EXCLUDED.agent_version;").await.unwrap();
+    let _result = dbconnection.query(&stmt_system_upsert, &[
+        node_id_db,
+        reported_at,
+        &system.uptime_seconds,
+        &system.cpu_temperature,
+        &system.cpu_throttled,
+        &system.load_1m,
+        &system.load_5m,
+        &system.load_15m,
+        &system.disk_used_percent,
+        &system.memory_used_percent,
+        &system.ip_addresses,
+        &system.os,
+        &system.agent_version,
+    ]).await.unwrap();
+    debug!("stored system health for nodes.id = {}", node_id_db);
+}
+
+/// Latest stored system health snapshot of the node.
+pub async fn latest_system_health(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+) -> Option<(DateTime<Utc>, SystemHealth)> {
+    let stmt_system = dbconnection.prepare_cached("SELECT reported_at, uptime_seconds, cpu_temperature, cpu_throttled, load_1m, load_5m, load_15m,
+	disk_used_percent, memory_used_percent, ip_addresses, os, agent_version
+	FROM node_system_health WHERE node_id = $1;").await.unwrap();
+    let rows = dbconnection.query(&stmt_system, &[node_id_db]).await.unwrap();
+
+    rows.first().map(|row| (row.get(0), system_health_from_row(row, 1)))
+}
+
+/// Nodes of an API key with their latest system health snapshot, optionally limited to one node.
+pub async fn list_nodes(
+    dbconnection: &impl GenericClient,
+    api_key_id: &i32,
+    node_id_external: &Option<String>,
+) -> Result<Vec<NodeInfo>, tokio_postgres::Error> {
+    let stmt_nodes = dbconnection.prepare_cached("SELECT n.node_id_external, n.node_group, n.monitoring_enabled, n.approval_status, n.last_checkin_timestamp, n.clock_offset_seconds,
+	h.reported_at, h.uptime_seconds, h.cpu_temperature, h.cpu_throttled, h.load_1m, h.load_5m, h.load_15m,
+	h.disk_used_percent, h.memory_used_percent, h.ip_addresses, h.os, h.agent_version
+	FROM nodes n LEFT JOIN node_system_health h ON h.node_id = n.id
+	WHERE n.fk_api_key_id = $1 AND ($2::varchar IS NULL OR n.node_id_external = $2)
+	ORDER BY n.node_id_external;").await?;
+    let rows = dbconnection.query(&stmt_nodes, &[api_key_id, node_id_external]).await?;
+
+    Ok(rows.iter()
+        .map(|row| {
+            let system_reported_at: Option<DateTime<Utc>> = row.get(6);
+            NodeInfo {
+                node_id: row.get(0),
+                node_group: row.get(1),
+                monitoring_enabled: row.get(2),
+                approval_status: row.get(3),
+                last_checkin_timestamp: row.get(4),
+                clock_offset_seconds: row.get(5),
+                system_reported_at,
+                system: system_reported_at.map(|_| system_health_from_row(row, 7)),
+            }
+        })
+        .collect())
+}
+
+fn system_health_from_row(row: &tokio_postgres::Row, first_column: usize) -> SystemHealth {
+    SystemHealth {
+        uptime_seconds: row.get(first_column),
+        cpu_temperature: row.get(first_column + 1),
+        cpu_throttled: row.get(first_column + 2),
+        load_1m: row.get(first_column + 3),
+        load_5m: row.get(first_column + 4),
+        load_15m: row.get(first_column + 5),
+        disk_used_percent: row.get(first_column + 6),
+        memory_used_percent: row.get(first_column + 7),
+        ip_addresses: row.get(first_column + 8),
+        os: row.get(first_column + 9),
+        agent_version: row.get(first_column + 10),
+    }
+}
+
+
+/// Some(true) when disk usage is above the limit, Some(false) when below or at the limit, None when not reported.
+pub fn disk_usage_exceeded(system: &SystemHealth, disk_used_percent_limit: f64) -> Option<bool> {
+    system.disk_used_percent.map(|x| x > disk_used_percent_limit)
+}
+
+/// A node rebooted when its uptime is lower than in the previous snapshot.
+pub fn reboot_detected(previous_system: &Option<SystemHealth>, system: &SystemHealth) -> bool {
+    match (previous_system.as_ref().and_then(|x| x.uptime_seconds), system.uptime_seconds) {
+        (Some(previous_uptime), Some(uptime)) => uptime < previous_uptime,
+        _ => false,
+    }
+}
+
+/// Built-in checks of the system health block: disk above the configured limit, CPU throttling and reboots.
+/// Disk and throttling incidents stay open until the condition clears, a reboot incident is resolved at once.
+#[allow(clippy::too_many_arguments)]
+pub async fn system_health_check(
+    node_id_db: &i32,
+    system: &SystemHealth,
+    previous_system: &Option<SystemHealth>,
+    node_id_external: &str,
+    notification_email_list: &str,
+    node_checkin_timestamp: &DateTime<Utc>,
+    dbconnection: &impl GenericClient,
+    outbox: &mut Outbox,
+    system_health_config: &web::Data<SystemHealthConfig>,
+) {
+    let disk_problem = format!("Disk usage above {}%", system_health_config.disk_used_percent_limit);
+    let disk_details = format!("Disk used: {:?}%", system.disk_used_percent);
+    let throttled_details = format!("CPU temperature: {:?}", system.cpu_temperature);
+
+    let conditions = [
+        (incident_functions::INCIDENT_TYPE_DISK_FULL, disk_usage_exceeded(system, system_health_config.disk_used_percent_limit), disk_problem.as_str(), disk_details.as_str(), system.disk_used_percent),
+        (incident_functions::INCIDENT_TYPE_CPU_THROTTLED, system.cpu_throttled, "CPU throttled", throttled_details.as_str(), system.cpu_temperature),
+    ];
+
+    for (incident_type, failing, problem, details, last_value) in conditions {
+        node_condition_check(
+            node_id_db,
+            incident_type,
+            failing,
+            problem,
+            details,
+            &last_value,
+            node_id_external,
+            notification_email_list,
+            node_checkin_timestamp,
+            dbconnection,
+            outbox,
+        ).await;
+    }
+
+    if reboot_detected(previous_system, system) {
+        let uptime_seconds = system.uptime_seconds.unwrap_or_default();
+        debug!("Reboot detected: uptime {} seconds", uptime_seconds);
+
+        let incident = incident_functions::open_incident(
+            dbconnection,
+            incident_functions::INCIDENT_TYPE_NODE_REBOOTED,
+            node_id_db,
+            &None,
+            node_checkin_timestamp,
+            &Some(uptime_seconds as f64),
+        ).await.unwrap();
+        incident_functions::resolve_incident(dbconnection, &incident.id, node_checkin_timestamp, &None).await;
+
+        if !notification_email_list.is_empty() {
+            outbox.push(send_email::system_health_alert_email(
+                node_id_external,
+                notification_email_list,
+                node_checkin_timestamp,
+                "Node rebooted",
+                &format!("Uptime: {} seconds", uptime_seconds),
+                &incident,
+            ));
+        } else {
+            error!("Can not send notification. recipient list not set");
+        }
+    }
+}
+
+
+/// Opens an incident of a built-in node check when `failing` is Some(true) and resolves it when Some(false).
+/// None (value not reported) keeps the current state.
+#[allow(clippy::too_many_arguments)]
+pub async fn node_condition_check(
+    node_id_db: &i32,
+    incident_type: &str,
+    failing: Option<bool>,
+    problem: &str,
+    details: &str,
+    last_value: &Option<f64>,
+    node_id_external: &str,
+    notification_email_list: &str,
+    node_checkin_timestamp: &DateTime<Utc>,
+    dbconnection: &impl GenericClient,
+    outbox: &mut Outbox,
+) {
+    let open_incident = incident_functions::find_open_incident(dbconnection, incident_type, node_id_db, &None).await;
+    debug!("Node check {}: failing = {:?} incident open = {}", incident_type, failing, open_incident.is_some());
+
+    match (failing, open_incident) {
+        (Some(true), None) => {
+            let incident = incident_functions::open_incident(dbconnection, incident_type, node_id_db, &None, node_checkin_timestamp, last_value).await.unwrap();
+            if !notification_email_list.is_empty() {
+                outbox.push(send_email::system_health_alert_email(
+                    node_id_external,
+                    notification_email_list,
+                    node_checkin_timestamp,
+                    problem,
+                    details,
+                    &incident,
+                ));
+            } else {
+                error!("Can not send notification. recipient list not set");
+            }
+        }
+        (Some(false), Some(incident)) => {
+            incident_functions::resolve_incident(dbconnection, &incident.id, node_checkin_timestamp, last_value).await;
+            if !notification_email_list.is_empty() {
+                outbox.push(send_email::system_health_ok_email(
+                    node_id_external,
+                    notification_email_list,
+                    node_checkin_timestamp,
+                    &incident.opened_at,
+                    problem,
+                    details,
+                ));
+            } else {
+                error!("Can not send notification. recipient list not set");
+            }
+        }
+        (Some(true), Some(incident)) => {
+            incident_functions::update_incident_last_value(dbconnection, &incident.id, last_value).await;
+        }
+        _ => {}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(uptime_seconds: Option<i64>, disk_used_percent: Option<f64>) -> SystemHealth {
+        SystemHealth { uptime_seconds, disk_used_percent, ..Default::default() }
+    }
+
+    #[test]
+    fn disk_usage_limit() {
+        assert_eq!(disk_usage_exceeded(&system(None, Some(90.5)), 90.0), Some(true));
+        assert_eq!(disk_usage_exceeded(&system(None, Some(90.0)), 90.0), Some(false));
+        assert_eq!(disk_usage_exceeded(&system(None, None), 90.0), None);
+    }
+
+    #[test]
+    fn reboot_when_uptime_decreases() {
+        assert!(reboot_detected(&Some(system(Some(3600), None)), &system(Some(120), None)));
+        assert!(!reboot_detected(&Some(system(Some(3600), None)), &system(Some(3660), None)));
+        assert!(!reboot_detected(&None, &system(Some(120), None)));
+        assert!(!reboot_detected(&Some(system(None, None)), &system(Some(120), None)));
+    }
+}
@@ -0,0 +1,737 @@
+// Expression language for sensor triggers (validation_function 'exp').
+//
+// The expression describes the alert condition, e.g. `value < 5 || value > 30`. When it evaluates to true the
+// trigger validation fails. Supported:
+//   numbers, true / false, identifiers, ( ), unary - and !, * /, + -, < <= > >= == !=, &&, ||
+//   functions abs(x), min(a, b), max(a, b)
+// Identifiers: `value` (sensor of the trigger), sensor names from the checkin payload and history aggregates of the
+// trigger sensor like avg_1h, min_30m, max_2d (units s, m, h, d).
+// Sensors of the same checkin can be referenced by id: sensor("28-0000071d5b2c") is the current value and
+// delta("28-0000071d5b2c") the change since the previous checkin. Composite triggers (validation_function 'cmp') use these.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Longer expressions are rejected, evaluation and explanation recurse over the expression tree.
+pub const MAX_EXPRESSION_LENGTH: usize = 1000;
+/// Maximum nesting of parentheses, function calls and unary operators.
+pub const MAX_NESTING_DEPTH: usize = 32;
+/// Longest history aggregate window (avg_31d).
+pub const MAX_HISTORY_WINDOW_DAYS: i64 = 31;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Bool(bool),
+    Identifier(String),
+    Sensor(String),
+    SensorDelta(String),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Function(String, Vec<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+/// History aggregate referenced by an identifier like avg_1h.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryAggregate {
+    pub identifier: String,
+    pub function: String,
+    pub window_seconds: i64,
+}
+
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(x) => write!(f, "{}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+impl BinaryOperator {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::Or => "||",
+            BinaryOperator::And => "&&",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessOrEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterOrEqual => ">=",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Number(x) => write!(f, "{}", x),
+            Expression::Bool(x) => write!(f, "{}", x),
+            Expression::Identifier(name) => write!(f, "{}", name),
+            Expression::Sensor(sensor_id) => write!(f, "sensor(\"{}\")", sensor_id),
+            Expression::SensorDelta(sensor_id) => write!(f, "delta(\"{}\")", sensor_id),
+            Expression::Negate(x) => write!(f, "-{}", x),
+            Expression::Not(x) => write!(f, "!{}", x),
+            Expression::Binary(operator, left, right) => write!(f, "({} {} {})", left, operator.symbol(), right),
+            Expression::Function(name, arguments) => {
+                let arguments: Vec<String> = arguments.iter().map(|x| x.to_string()).collect();
+                write!(f, "{}({})", name, arguments.join(", "))
+            }
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Text(String),
+    Operator(&'static str),
+    LeftParenthesis,
+    RightParenthesis,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    const OPERATORS: [&str; 14] = ["||", "&&", "<=", ">=", "==", "!=", "<", ">", "!", "+", "-", "*", "/", "="];
+
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = source.chars().collect();
+    let mut position = 0;
+
+    while position < chars.len() {
+        let c = chars[position];
+        if c.is_whitespace() {
+            position += 1;
+        } else if c.is_ascii_digit() || (c == '.' && chars.get(position + 1).is_some_and(|x| x.is_ascii_digit())) {
+            let start = position;
+            while position < chars.len() && (chars[position].is_ascii_digit() || chars[position] == '.') {
+                position += 1;
+            }
+            let text: String = chars[start..position].iter().collect();
+            let number = text.parse::<f64>().map_err(|_| ParseError { position: start, message: format!("invalid number '{}'", text) })?;
+            tokens.push((start, Token::Number(number)));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = position;
+            while position < chars.len() && (chars[position].is_ascii_alphanumeric() || chars[position] == '_') {
+                position += 1;
+            }
+            tokens.push((start, Token::Identifier(chars[start..position].iter().collect())));
+        } else if c == '"' {
+            let start = position;
+            position += 1;
+            while position < chars.len() && chars[position] != '"' {
+                position += 1;
+            }
+            if position == chars.len() {
+                return Err(ParseError { position: start, message: "unterminated string".to_string() });
+            }
+            tokens.push((start, Token::Text(chars[start + 1..position].iter().collect())));
+            position += 1;
+        } else if c == '(' {
+            tokens.push((position, Token::LeftParenthesis));
+            position += 1;
+        } else if c == ')' {
+            tokens.push((position, Token::RightParenthesis));
+            position += 1;
+        } else if c == ',' {
+            tokens.push((position, Token::Comma));
+            position += 1;
+        } else {
+            let operator = OPERATORS.iter().find(|operator| {
+                operator.chars().enumerate().all(|(i, x)| chars.get(position + i) == Some(&x))
+            });
+            match operator {
+                Some(&"=") => return Err(ParseError { position, message: "unexpected '=' (use '==')".to_string() }),
+                Some(operator) => {
+                    tokens.push((position, Token::Operator(operator)));
+                    position += operator.len();
+                }
+                None => return Err(ParseError { position, message: format!("unexpected character '{}'", c) }),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+    // in chars, like the token positions
+    source_length: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.index).map(|(position, _)| *position).unwrap_or(self.source_length)
+    }
+
+    fn next_operator_in(&mut self, operators: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(operator)) if operators.contains(operator) => {
+                let operator = *operator;
+                self.index += 1;
+                Some(operator)
+            }
+            _ => None,
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, ParseError> {
+        let found = match self.peek() {
+            Some(Token::Number(x)) => format!("'{}'", x),
+            Some(Token::Identifier(x)) => format!("'{}'", x),
+            Some(Token::Text(x)) => format!("\"{}\"", x),
+            Some(Token::Operator(x)) => format!("'{}'", x),
+            Some(Token::LeftParenthesis) => "'('".to_string(),
+            Some(Token::RightParenthesis) => "')'".to_string(),
+            Some(Token::Comma) => "','".to_string(),
+            None => "end of expression".to_string(),
+        };
+        Err(ParseError { position: self.position(), message: format!("{}, found {}", message, found) })
+    }
+
+    fn parse_binary(
+        &mut self,
+        operators: &[&'static str],
+        operand: fn(&mut Parser) -> Result<Expression, ParseError>,
+    ) -> Result<Expression, ParseError> {
+        let mut left = operand(self)?;
+        while let Some(operator) = self.next_operator_in(operators) {
+            let right = operand(self)?;
+            left = Expression::Binary(binary_operator(operator), Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, ParseError> {
+        self.parse_binary(&["||"], Parser::parse_and)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, ParseError> {
+        self.parse_binary(&["&&"], Parser::parse_comparison)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, ParseError> {
+        let left = self.parse_sum()?;
+        match self.next_operator_in(&["<", "<=", ">", ">=", "==", "!="]) {
+            Some(operator) => {
+                let right = self.parse_sum()?;
+                if matches!(self.peek(), Some(Token::Operator(x)) if ["<", "<=", ">", ">=", "==", "!="].contains(x)) {
+                    return self.error("comparisons can not be chained, use &&");
+                }
+                Ok(Expression::Binary(binary_operator(operator), Box::new(left), Box::new(right)))
+            }
+            None => Ok(left),
+        }
+    }
+
+    fn parse_sum(&mut self) -> Result<Expression, ParseError> {
+        self.parse_binary(&["+", "-"], Parser::parse_product)
+    }
+
+    fn parse_product(&mut self) -> Result<Expression, ParseError> {
+        self.parse_binary(&["*", "/"], Parser::parse_unary)
+    }
+
+    // every nesting level (parentheses, function arguments, unary operators) passes here
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(ParseError { position: self.position(), message: format!("expression is nested deeper than {} levels", MAX_NESTING_DEPTH) });
+        }
+        self.depth += 1;
+        let expression = match self.next_operator_in(&["-", "!"]) {
+            Some("-") => self.parse_unary().map(|x| Expression::Negate(Box::new(x))),
+            Some(_) => self.parse_unary().map(|x| Expression::Not(Box::new(x))),
+            None => self.parse_primary(),
+        };
+        self.depth -= 1;
+        expression
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        match self.peek().cloned() {
+            Some(Token::Number(x)) => {
+                self.index += 1;
+                Ok(Expression::Number(x))
+            }
+            Some(Token::Identifier(name)) => {
+                let position = self.position();
+                self.index += 1;
+                if self.peek() == Some(&Token::LeftParenthesis) && (name == "sensor" || name == "delta") {
+                    self.index += 1;
+                    let sensor_id = match self.peek().cloned() {
+                        Some(Token::Text(sensor_id)) => sensor_id,
+                        _ => return self.error(&format!("expected sensor id string in {}()", name)),
+                    };
+                    self.index += 1;
+                    if self.peek() != Some(&Token::RightParenthesis) {
+                        return self.error("expected ')'");
+                    }
+                    self.index += 1;
+                    if name == "sensor" {
+                        Ok(Expression::Sensor(sensor_id))
+                    } else {
+                        Ok(Expression::SensorDelta(sensor_id))
+                    }
+                } else if self.peek() == Some(&Token::LeftParenthesis) {
+                    self.index += 1;
+                    let mut arguments = Vec::new();
+                    if self.peek() != Some(&Token::RightParenthesis) {
+                        loop {
+                            arguments.push(self.parse_or()?);
+                            if self.peek() == Some(&Token::Comma) {
+                                self.index += 1;
+                            } else {
+                                break;
+                            }
+                        }
+                    }
+                    if self.peek() != Some(&Token::RightParenthesis) {
+                        return self.error("expected ')'");
+                    }
+                    self.index += 1;
+                    check_function(&name, arguments.len(), position)?;
+                    Ok(Expression::Function(name, arguments))
+                } else {
+                    if let Some(Err(message)) = parse_history_aggregate(&name) {
+                        return Err(ParseError { position, message });
+                    }
+                    match name.as_str() {
+                        "true" => Ok(Expression::Bool(true)),
+                        "false" => Ok(Expression::Bool(false)),
+                        _ => Ok(Expression::Identifier(name)),
+                    }
+                }
+            }
+            Some(Token::LeftParenthesis) => {
+                self.index += 1;
+                let expression = self.parse_or()?;
+                if self.peek() != Some(&Token::RightParenthesis) {
+                    return self.error("expected ')'");
+                }
+                self.index += 1;
+                Ok(expression)
+            }
+            _ => self.error("expected a number, identifier or '('"),
+        }
+    }
+}
+
+fn binary_operator(operator: &str) -> BinaryOperator {
+    match operator {
+        "||" => BinaryOperator::Or,
+        "&&" => BinaryOperator::And,
+        "<" => BinaryOperator::Less,
+        "<=" => BinaryOperator::LessOrEqual,
+        ">" => BinaryOperator::Greater,
+        ">=" => BinaryOperator::GreaterOrEqual,
+        "==" => BinaryOperator::Equal,
+        "!=" => BinaryOperator::NotEqual,
+        "+" => BinaryOperator::Add,
+        "-" => BinaryOperator::Subtract,
+        "*" => BinaryOperator::Multiply,
+        _ => BinaryOperator::Divide,
+    }
+}
+
+fn check_function(name: &str, argument_count: usize, position: usize) -> Result<(), ParseError> {
+    let expected_count = match name {
+        "abs" => 1,
+        "min" | "max" => 2,
+        _ => return Err(ParseError { position, message: format!("unknown function '{}'", name) }),
+    };
+    if argument_count != expected_count {
+        return Err(ParseError { position, message: format!("function '{}' expects {} argument(s), got {}", name, expected_count, argument_count) });
+    }
+    Ok(())
+}
+
+pub fn parse(source: &str) -> Result<Expression, ParseError> {
+    let source_length = source.chars().count();
+    if source_length > MAX_EXPRESSION_LENGTH {
+        return Err(ParseError { position: MAX_EXPRESSION_LENGTH, message: format!("expression is longer than {} characters", MAX_EXPRESSION_LENGTH) });
+    }
+    let tokens = tokenize(source)?;
+    let mut parser = Parser { tokens, index: 0, source_length, depth: 0 };
+
+    let expression = parser.parse_or()?;
+    if parser.peek().is_some() {
+        return parser.error("unexpected input");
+    }
+    Ok(expression)
+}
+
+/// Parses the expression and checks that it evaluates to a boolean.
+pub fn parse_condition(source: &str) -> Result<Expression, ParseError> {
+    let expression = parse(source)?;
+    if !expression.is_boolean() {
+        return Err(ParseError { position: 0, message: "expression must be a condition (comparison or && / || / !)".to_string() });
+    }
+    Ok(expression)
+}
+
+
+impl Expression {
+    fn is_boolean(&self) -> bool {
+        match self {
+            Expression::Bool(_) | Expression::Not(_) => true,
+            Expression::Binary(operator, _, _) => matches!(
+                operator,
+                BinaryOperator::Or | BinaryOperator::And | BinaryOperator::Less | BinaryOperator::LessOrEqual
+                    | BinaryOperator::Greater | BinaryOperator::GreaterOrEqual | BinaryOperator::Equal | BinaryOperator::NotEqual
+            ),
+            _ => false,
+        }
+    }
+
+    /// Identifiers referenced by the expression.
+    pub fn identifiers(&self) -> Vec<String> {
+        let mut identifiers = Vec::new();
+        self.collect_identifiers(&mut identifiers);
+        identifiers
+    }
+
+    fn collect_identifiers(&self, identifiers: &mut Vec<String>) {
+        match self {
+            Expression::Identifier(name) => {
+                if !identifiers.contains(name) {
+                    identifiers.push(name.clone());
+                }
+            }
+            Expression::Negate(x) | Expression::Not(x) => x.collect_identifiers(identifiers),
+            Expression::Binary(_, left, right) => {
+                left.collect_identifiers(identifiers);
+                right.collect_identifiers(identifiers);
+            }
+            Expression::Function(_, arguments) => arguments.iter().for_each(|x| x.collect_identifiers(identifiers)),
+            Expression::Number(_) | Expression::Bool(_) | Expression::Sensor(_) | Expression::SensorDelta(_) => {}
+        }
+    }
+
+    /// Sensor ids referenced with sensor("...") or delta("...").
+    pub fn sensor_ids(&self) -> Vec<String> {
+        let mut sensor_ids = Vec::new();
+        self.collect_sensor_ids(&mut sensor_ids, false);
+        sensor_ids
+    }
+
+    /// Sensor ids referenced with delta("...").
+    pub fn delta_sensor_ids(&self) -> Vec<String> {
+        let mut sensor_ids = Vec::new();
+        self.collect_sensor_ids(&mut sensor_ids, true);
+        sensor_ids
+    }
+
+    fn collect_sensor_ids(&self, sensor_ids: &mut Vec<String>, delta_only: bool) {
+        match self {
+            Expression::Sensor(sensor_id) | Expression::SensorDelta(sensor_id) => {
+                let is_delta = matches!(self, Expression::SensorDelta(_));
+                if (is_delta || !delta_only) && !sensor_ids.contains(sensor_id) {
+                    sensor_ids.push(sensor_id.clone());
+                }
+            }
+            Expression::Negate(x) | Expression::Not(x) => x.collect_sensor_ids(sensor_ids, delta_only),
+            Expression::Binary(_, left, right) => {
+                left.collect_sensor_ids(sensor_ids, delta_only);
+                right.collect_sensor_ids(sensor_ids, delta_only);
+            }
+            Expression::Function(_, arguments) => arguments.iter().for_each(|x| x.collect_sensor_ids(sensor_ids, delta_only)),
+            Expression::Number(_) | Expression::Bool(_) | Expression::Identifier(_) => {}
+        }
+    }
+
+    /// History aggregates (avg_1h, ...) referenced by the expression.
+    pub fn history_aggregates(&self) -> Vec<HistoryAggregate> {
+        self.identifiers().iter().filter_map(|identifier| history_aggregate(identifier)).collect()
+    }
+
+    pub fn evaluate(&self, variables: &HashMap<String, f64>) -> Result<Value, String> {
+        match self {
+            Expression::Number(x) => Ok(Value::Number(*x)),
+            Expression::Bool(x) => Ok(Value::Bool(*x)),
+            Expression::Identifier(name) => match variables.get(name) {
+                Some(x) => Ok(Value::Number(*x)),
+                None => Err(format!("'{}' has no value", name)),
+            },
+            Expression::Sensor(sensor_id) => match variables.get(&sensor_variable(sensor_id)) {
+                Some(x) => Ok(Value::Number(*x)),
+                None => Err(format!("sensor '{}' has no value", sensor_id)),
+            },
+            Expression::SensorDelta(sensor_id) => match variables.get(&delta_variable(sensor_id)) {
+                Some(x) => Ok(Value::Number(*x)),
+                None => Err(format!("sensor '{}' has no previous value", sensor_id)),
+            },
+            Expression::Negate(x) => Ok(Value::Number(-x.evaluate_number(variables)?)),
+            Expression::Not(x) => Ok(Value::Bool(!x.evaluate_bool(variables)?)),
+            Expression::Binary(BinaryOperator::Or, left, right) => Ok(Value::Bool(left.evaluate_bool(variables)? || right.evaluate_bool(variables)?)),
+            Expression::Binary(BinaryOperator::And, left, right) => Ok(Value::Bool(left.evaluate_bool(variables)? && right.evaluate_bool(variables)?)),
+            Expression::Binary(operator, left, right) => {
+                let left_value = left.evaluate(variables)?;
+                let right_value = right.evaluate(variables)?;
+                match (left_value, right_value) {
+                    (Value::Number(x), Value::Number(y)) => match operator {
+                        BinaryOperator::Less => Ok(Value::Bool(x < y)),
+                        BinaryOperator::LessOrEqual => Ok(Value::Bool(x <= y)),
+                        BinaryOperator::Greater => Ok(Value::Bool(x > y)),
+                        BinaryOperator::GreaterOrEqual => Ok(Value::Bool(x >= y)),
+                        BinaryOperator::Equal => Ok(Value::Bool(x == y)),
+                        BinaryOperator::NotEqual => Ok(Value::Bool(x != y)),
+                        BinaryOperator::Add => Ok(Value::Number(x + y)),
+                        BinaryOperator::Subtract => Ok(Value::Number(x - y)),
+                        BinaryOperator::Multiply => Ok(Value::Number(x * y)),
+                        BinaryOperator::Divide if y == 0.0 => Err(format!("division by zero in {}", self)),
+                        BinaryOperator::Divide => Ok(Value::Number(x / y)),
+                        BinaryOperator::Or | BinaryOperator::And => unreachable!(),
+                    },
+                    (Value::Bool(x), Value::Bool(y)) if *operator == BinaryOperator::Equal => Ok(Value::Bool(x == y)),
+                    (Value::Bool(x), Value::Bool(y)) if *operator == BinaryOperator::NotEqual => Ok(Value::Bool(x != y)),
+                    _ => Err(format!("type mismatch in {}", self)),
+                }
+            }
+            Expression::Function(name, arguments) => {
+                let values = arguments.iter().map(|x| x.evaluate_number(variables)).collect::<Result<Vec<f64>, String>>()?;
+                match name.as_str() {
+                    "abs" => Ok(Value::Number(values[0].abs())),
+                    "min" => Ok(Value::Number(values[0].min(values[1]))),
+                    _ => Ok(Value::Number(values[0].max(values[1]))),
+                }
+            }
+        }
+    }
+
+    fn evaluate_number(&self, variables: &HashMap<String, f64>) -> Result<f64, String> {
+        match self.evaluate(variables)? {
+            Value::Number(x) => Ok(x),
+            Value::Bool(_) => Err(format!("expected a number: {}", self)),
+        }
+    }
+
+    fn evaluate_bool(&self, variables: &HashMap<String, f64>) -> Result<bool, String> {
+        match self.evaluate(variables)? {
+            Value::Bool(x) => Ok(x),
+            Value::Number(_) => Err(format!("expected a condition: {}", self)),
+        }
+    }
+
+    /// Explains the result of a condition by listing the sub-expressions that decided it, with the values
+    /// of their operands. For `a && b` that is false only the false operands are listed, for `a || b` that is
+    /// true only the true operands.
+    pub fn explain(&self, variables: &HashMap<String, f64>) -> String {
+        let result = match self.evaluate(variables) {
+            Ok(x) => x,
+            Err(e) => return e,
+        };
+        match (self, result) {
+            (Expression::Binary(operator @ (BinaryOperator::And | BinaryOperator::Or), left, right), Value::Bool(x)) => {
+                let deciding = (*operator == BinaryOperator::And) != x;
+                let parts: Vec<String> = [left, right]
+                    .iter()
+                    .filter(|operand| !deciding || operand.evaluate(variables) == Ok(Value::Bool(x)))
+                    .map(|operand| operand.explain(variables))
+                    .collect();
+                parts.join(if deciding { " / " } else { " and " })
+            }
+            (Expression::Not(x), _) => format!("not ({})", x.explain(variables)),
+            (Expression::Binary(operator, left, right), Value::Bool(x)) => format!(
+                "{} {} {} is {} ({} {} {})",
+                left, operator.symbol(), right, x,
+                left.evaluate(variables).map(|x| x.to_string()).unwrap_or_default(),
+                operator.symbol(),
+                right.evaluate(variables).map(|x| x.to_string()).unwrap_or_default(),
+            ),
+            (_, result) => format!("{} is {}", self, result),
+        }
+    }
+}
+
+/// Key of the sensor("...") value in the evaluation variables.
+pub fn sensor_variable(sensor_id: &str) -> String {
+    Expression::Sensor(sensor_id.to_string()).to_string()
+}
+
+/// Key of the delta("...") value in the evaluation variables.
+pub fn delta_variable(sensor_id: &str) -> String {
+    Expression::SensorDelta(sensor_id.to_string()).to_string()
+}
+
+/// Parses identifiers like avg_1h, min_30m, max_2d.
+pub fn history_aggregate(identifier: &str) -> Option<HistoryAggregate> {
+    parse_history_aggregate(identifier)?.ok()
+}
+
+/// None when the identifier is not a history aggregate, Err when its window is longer than MAX_HISTORY_WINDOW_DAYS.
+fn parse_history_aggregate(identifier: &str) -> Option<Result<HistoryAggregate, String>> {
+    let (function, window) = identifier.split_once('_')?;
+    if !matches!(function, "avg" | "min" | "max") || window.len() < 2 {
+        return None;
+    }
+    let (count, unit) = window.split_at(window.len() - 1);
+    if !count.chars().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let unit_seconds: i64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return None,
+    };
+    let window_seconds = count.parse::<i64>().ok().and_then(|x| x.checked_mul(unit_seconds));
+    match window_seconds {
+        Some(window_seconds) if window_seconds <= MAX_HISTORY_WINDOW_DAYS * 86400 => Some(Ok(HistoryAggregate {
+            identifier: identifier.to_string(),
+            function: function.to_string(),
+            window_seconds,
+        })),
+        _ => Some(Err(format!("history window of '{}' is longer than {} days", identifier, MAX_HISTORY_WINDOW_DAYS))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(values: &[(&str, f64)]) -> HashMap<String, f64> {
+        values.iter().map(|(name, value)| (name.to_string(), *value)).collect()
+    }
+
+    #[test]
+    fn parse_and_evaluate() {
+        let expression = parse_condition("value < 5 || value > 30").unwrap();
+        assert_eq!(expression.evaluate(&variables(&[("value", 4.0)])), Ok(Value::Bool(true)));
+        assert_eq!(expression.evaluate(&variables(&[("value", 20.0)])), Ok(Value::Bool(false)));
+
+        let expression = parse_condition("abs(value - avg_1h) > 2").unwrap();
+        assert_eq!(expression.evaluate(&variables(&[("value", 20.0), ("avg_1h", 17.5)])), Ok(Value::Bool(true)));
+        assert_eq!(expression.history_aggregates(), vec![HistoryAggregate { identifier: "avg_1h".to_string(), function: "avg".to_string(), window_seconds: 3600 }]);
+
+        let expression = parse_condition("!(1 + 2 * 3 == 7) || -value >= max(1, 2) / 2").unwrap();
+        assert_eq!(expression.evaluate(&variables(&[("value", -1.0)])), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse("value >").unwrap_err().message, "expected a number, identifier or '(', found end of expression");
+        assert_eq!(parse("value > 5)").unwrap_err().position, 9);
+        assert_eq!(parse("value = 5").unwrap_err().position, 6);
+        assert_eq!(parse("value # 5").unwrap_err().message, "unexpected character '#'");
+        assert_eq!(parse("1 < value < 5").unwrap_err().message, "comparisons can not be chained, use &&, found '<'");
+        assert_eq!(parse("sqrt(value) > 1").unwrap_err().message, "unknown function 'sqrt'");
+        assert_eq!(parse("abs(value, 1) > 1").unwrap_err().message, "function 'abs' expects 1 argument(s), got 2");
+        assert!(parse_condition("value + 1").is_err());
+    }
+
+    #[test]
+    fn parse_limits() {
+        assert!(parse_condition("avg_31d > 1").is_ok());
+        assert_eq!(parse("value > avg_32d").unwrap_err().message, "history window of 'avg_32d' is longer than 31 days");
+        assert_eq!(parse("value > avg_99999999999999d").unwrap_err().position, 8);
+        assert!(parse("value > avg_99999999999999999999d").is_err());
+        assert_eq!(history_aggregate("max_99999999999999d"), None);
+
+        let nested = format!("{}value{} > 1", "(".repeat(MAX_NESTING_DEPTH), ")".repeat(MAX_NESTING_DEPTH));
+        assert_eq!(parse(&nested).unwrap_err().message, format!("expression is nested deeper than {} levels", MAX_NESTING_DEPTH));
+        assert!(parse(&format!("{}value > 1", "!".repeat(5000))).is_err());
+        assert!(parse(&format!("{}1", "-".repeat(MAX_NESTING_DEPTH - 1))).is_ok());
+        assert!(parse(&format!("value > {}", "1+".repeat(MAX_EXPRESSION_LENGTH))).is_err());
+
+        // positions count chars, also at the end of the expression
+        assert_eq!(parse("sensor(\"é\") >").unwrap_err().position, 13);
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        let expression = parse_condition("humidity / value > 1").unwrap();
+        assert_eq!(expression.evaluate(&variables(&[("value", 1.0)])), Err("'humidity' has no value".to_string()));
+        assert!(expression.evaluate(&variables(&[("value", 0.0), ("humidity", 1.0)])).is_err());
+        assert!(parse_condition("(value > 1) + 1 > 1").unwrap().evaluate(&variables(&[("value", 1.0)])).is_err());
+    }
+
+    #[test]
+    fn explain_deciding_sub_expressions() {
+        let expression = parse_condition("humidity > 80 && temp > 25").unwrap();
+        assert_eq!(
+            expression.explain(&variables(&[("humidity", 75.0), ("temp", 30.0)])),
+            "humidity > 80 is false (75 > 80)"
+        );
+        assert_eq!(
+            expression.explain(&variables(&[("humidity", 85.0), ("temp", 30.0)])),
+            "humidity > 80 is true (85 > 80) and temp > 25 is true (30 > 25)"
+        );
+
+        let expression = parse_condition("value < 5 || value > 30").unwrap();
+        assert_eq!(expression.explain(&variables(&[("value", 31.0)])), "value > 30 is true (31 > 30)");
+    }
+
+    #[test]
+    fn sensor_references() {
+        // "heater relay on but temperature falling"
+        let expression = parse_condition("sensor(\"relay-1\") == 1 && delta(\"28-0001\") < 0").unwrap();
+        assert_eq!(expression.sensor_ids(), vec!["relay-1".to_string(), "28-0001".to_string()]);
+        assert_eq!(expression.delta_sensor_ids(), vec!["28-0001".to_string()]);
+
+        let mut values = variables(&[]);
+        values.insert(sensor_variable("relay-1"), 1.0);
+        values.insert(delta_variable("28-0001"), -0.5);
+        assert_eq!(expression.evaluate(&values), Ok(Value::Bool(true)));
+        assert_eq!(
+            expression.explain(&values),
+            "sensor(\"relay-1\") == 1 is true (1 == 1) and delta(\"28-0001\") < 0 is true (-0.5 < 0)"
+        );
+
+        assert_eq!(parse("sensor(relay) > 1").unwrap_err().message, "expected sensor id string in sensor(), found 'relay'");
+        assert_eq!(parse("sensor(\"relay) > 1").unwrap_err().message, "unterminated string");
+        assert!(parse("\"relay\" > 1").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Datelike, TimeZone, Utc};
+use chrono_tz::Europe::Riga;
+use deadpool_postgres::GenericClient;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use log::debug;
+
+pub const NODE_STATE_ONLINE: &str = "online";
+pub const NODE_STATE_OFFLINE: &str = "offline";
+
+const NODE_GROUP_NONE: &str = "(no group)";
+
+
+#[derive(Serialize)]
+pub struct UptimeSummary {
+    pub name: String,
+    pub observed_seconds: i64,
+    pub online_seconds: i64,
+    pub uptime_percent: Option<f64>,
+    pub outage_count: usize,
+    pub mttr_seconds: Option<i64>,
+    pub longest_outage_seconds: i64,
+}
+
+#[derive(Serialize)]
+pub struct UptimeReport {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub nodes: Vec<UptimeSummary>,
+    pub groups: Vec<UptimeSummary>,
+}
+
+
+pub async fn record_node_transition(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+    state: &str,
+    transition_at: &DateTime<Utc>,
+) {
+    debug!("Node state transition: nodes.id = {} state = {} at {:?}", node_id_db, state, transition_at);
+
+    let stmt_transition_insert = dbconnection.prepare_cached("INSERT INTO node_state_transitions(
+	id, node_id, state, transition_at)
+	VALUES (DEFAULT, $1, $2, $3);").await.unwrap();
+    let _result = dbconnection.query(&stmt_transition_insert, &[node_id_db, &state, transition_at]).await.unwrap();
+}
+
+/// State of the latest transition of the node, None when none is recorded.
+pub async fn latest_node_state(
+    dbconnection: &impl GenericClient,
+    node_id_db: &i32,
+) -> Option<String> {
+    let stmt_latest_transition = dbconnection.prepare_cached("SELECT state FROM node_state_transitions
+	WHERE node_id = $1 ORDER BY transition_at DESC, id DESC LIMIT 1;").await.unwrap();
+    let rows = dbconnection.query(&stmt_latest_transition, &[node_id_db]).await.unwrap();
+    rows.first().map(|row| row.get(0))
+}
+
+/// Records an offline transition, at the last checkin, for every node that did not check in since `offline_before`
+/// and is not offline yet. Independent of monitoring and notifications, the uptime report covers all nodes.
+pub async fn record_offline_transitions(
+    dbconnection: &impl GenericClient,
+    offline_before: &DateTime<Utc>,
+) -> Result<u64, tokio_postgres::Error> {
+    let stmt_offline_transitions = dbconnection.prepare_cached("INSERT INTO node_state_transitions(node_id, state, transition_at)
+	SELECT nodes.id, $2::varchar, nodes.last_checkin_timestamp FROM nodes
+	WHERE nodes.last_checkin_timestamp < $1
+	AND (SELECT state FROM node_state_transitions WHERE node_id = nodes.id ORDER BY transition_at DESC, id DESC LIMIT 1) IS DISTINCT FROM $2::varchar;").await?;
+    let recorded = dbconnection.execute(&stmt_offline_transitions, &[offline_before, &NODE_STATE_OFFLINE]).await?;
+    debug!("Offline transitions recorded = {}", recorded);
+
+    Ok(recorded)
+}
+
+/// Builds the uptime report for [from, to). When `api_key_id` is set only nodes of that key are included.
+pub async fn build_uptime_report(
+    dbconnection: &impl GenericClient,
+    api_key_id: &Option<i32>,
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+) -> UptimeReport {
+    let stmt_nodes = dbconnection.prepare_cached("SELECT id, node_id_external, node_group
+	FROM nodes WHERE $1::integer IS NULL OR fk_api_key_id = $1 ORDER BY node_group, node_id_external;").await.unwrap();
+    let rows_nodes = dbconnection.query(&stmt_nodes, &[api_key_id]).await.unwrap();
+
+    let stmt_transitions = dbconnection.prepare_cached("SELECT t.node_id, t.state, t.transition_at
+	FROM node_state_transitions t JOIN nodes n ON n.id = t.node_id
+	WHERE ($1::integer IS NULL OR n.fk_api_key_id = $1) AND t.transition_at < $2 ORDER BY t.node_id, t.transition_at, t.id;").await.unwrap();
+    let rows_transitions = dbconnection.query(&stmt_transitions, &[api_key_id, to]).await.unwrap();
+
+    let mut transitions_by_node: BTreeMap<i32, Vec<(String, DateTime<Utc>)>> = BTreeMap::new();
+    for row in rows_transitions {
+        transitions_by_node.entry(row.get(0)).or_default().push((row.get(1), row.get(2)));
+    }
+
+    let mut nodes = Vec::new();
+    let mut outages_by_group: BTreeMap<String, (i64, i64, Vec<i64>)> = BTreeMap::new();
+
+    for row in rows_nodes {
+        let node_id_db: i32 = row.get(0);
+        let node_id_external: String = row.get(1);
+        let node_group: Option<String> = row.get(2);
+
+        let empty = Vec::new();
+        let transitions = transitions_by_node.get(&node_id_db).unwrap_or(&empty);
+        let (observed_seconds, online_seconds, outages) = node_availability(transitions, from, to);
+
+        let group = outages_by_group.entry(node_group.unwrap_or_else(|| NODE_GROUP_NONE.to_string())).or_default();
+        group.0 += observed_seconds;
+        group.1 += online_seconds;
+        group.2.extend(&outages);
+
+        nodes.push(uptime_summary(node_id_external, observed_seconds, online_seconds, &outages));
+    }
+
+    let groups = outages_by_group
+        .into_iter()
+        .map(|(name, (observed_seconds, online_seconds, outages))| uptime_summary(name, observed_seconds, online_seconds, &outages))
+        .collect();
+
+    UptimeReport { from: *from, to: *to, nodes, groups }
+}
+
+/// Walks the ordered state transitions of one node and returns (observed seconds, online seconds, outage durations)
+/// within [from, to). Time before the first known transition is not observed. Outages still open at `to` are cut off there.
+pub fn node_availability(
+    transitions: &[(String, DateTime<Utc>)],
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+) -> (i64, i64, Vec<i64>) {
+    let mut observed_seconds = 0;
+    let mut online_seconds = 0;
+    let mut outages = Vec::new();
+
+    let mut current_state: Option<(&str, DateTime<Utc>)> = None;
+
+    let period_end = (NODE_STATE_ONLINE, *to);
+    for (state, transition_at) in transitions.iter().map(|(s, t)| (s.as_str(), *t)).chain(std::iter::once(period_end)) {
+        if let Some((previous_state, previous_at)) = current_state {
+            if previous_state == state && transition_at < *to {
+                continue; // repeated state, keep the earlier start
+            }
+            let interval_start = previous_at.max(*from);
+            let interval_end = transition_at.min(*to);
+            if interval_end > interval_start {
+                let seconds = interval_end.signed_duration_since(interval_start).num_seconds();
+                observed_seconds += seconds;
+                if previous_state == NODE_STATE_ONLINE {
+                    online_seconds += seconds;
+                } else {
+                    outages.push(seconds);
+                }
+            }
+        }
+        current_state = Some((state, transition_at));
+    }
+
+    (observed_seconds, online_seconds, outages)
+}
+
+fn uptime_summary(name: String, observed_seconds: i64, online_seconds: i64, outages: &[i64]) -> UptimeSummary {
+    UptimeSummary {
+        name,
+        observed_seconds,
+        online_seconds,
+        uptime_percent: if observed_seconds > 0 { Some(online_seconds as f64 * 100.0 / observed_seconds as f64) } else { None },
+        outage_count: outages.len(),
+        mttr_seconds: if outages.is_empty() { None } else { Some(outages.iter().sum::<i64>() / outages.len() as i64) },
+        longest_outage_seconds: outages.iter().copied().max().unwrap_or(0),
+    }
+}
+
+
+/// Start and end of the calendar month (Riga time) before the given timestamp.
+pub fn previous_month_period(now: &DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let now_riga_time = now.with_timezone(&Riga);
+    let (year, month) = if now_riga_time.month() == 1 { (now_riga_time.year() - 1, 12) } else { (now_riga_time.year(), now_riga_time.month() - 1) };
+
+    let from = Riga.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap().with_timezone(&Utc);
+    let to = Riga.with_ymd_and_hms(now_riga_time.year(), now_riga_time.month(), 1, 0, 0, 0).unwrap().with_timezone(&Utc);
+
+    (from, to)
+}
+
+/// Returns true when the report for the month starting at `report_month` was not sent yet and marks it as sent.
+/// The claim keeps concurrent calls from sending twice, it is released when the send fails.
+pub async fn claim_monthly_report(
+    dbconnection: &impl GenericClient,
+    report_month: &DateTime<Utc>,
+) -> bool {
+    let stmt_report_insert = dbconnection.prepare_cached("INSERT INTO uptime_reports_sent(
+	report_month, sent_at)
+	VALUES ($1, now()) ON CONFLICT (report_month) DO NOTHING;").await.unwrap();
+    let inserted = dbconnection.execute(&stmt_report_insert, &[report_month]).await.unwrap();
+
+    inserted == 1
+}
+
+/// Removes the claim of a report that could not be sent, the next report-sender call sends it again.
+pub async fn release_monthly_report(
+    dbconnection: &impl GenericClient,
+    report_month: &DateTime<Utc>,
+) {
+    let stmt_report_delete = dbconnection.prepare_cached("DELETE FROM uptime_reports_sent WHERE report_month = $1;").await.unwrap();
+    dbconnection.execute(&stmt_report_delete, &[report_month]).await.unwrap();
+}